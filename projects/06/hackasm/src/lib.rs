@@ -0,0 +1,1660 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Instruction set accepted by the assembler
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Official comp/dest/jump tables with the assembler directives and expressions
+    Standard,
+    /// Only the official Hack assembly language without any assembler extensions
+    Strict,
+    /// Standard mode that also accepts common variant mnemonics like "M+D" or "DM="
+    Extended,
+}
+
+/// Type of line from asm code
+#[derive(Debug)]
+enum LineType {
+    Blank,
+    AInstruction,
+    CInstruction,
+    Label,
+    /// .org with the address to continue emitting from
+    Org(u16),
+    /// .word with the number of words emitted
+    Word(usize),
+}
+
+#[derive(Debug)]
+struct CInstruction {
+    comp: String,
+    dest: Option<String>,
+    jump: Option<String>,
+}
+
+#[derive(Debug)]
+struct AInstruction {
+    value: u16,
+}
+
+/// Raw value emitted by .word or to pad gaps left by .org
+#[derive(Debug)]
+struct DataWord {
+    value: u16,
+}
+
+/// Errors found in the asm source
+#[derive(thiserror::Error, Debug)]
+pub enum AsmError {
+    #[error("{file}:{line}: unknown comp \"{text}\"")]
+    UnknownComp {
+        file: String,
+        line: usize,
+        text: String,
+    },
+    #[error("{file}:{line}: unknown dest \"{text}\"")]
+    UnknownDest {
+        file: String,
+        line: usize,
+        text: String,
+    },
+    #[error("{file}:{line}: unknown jump \"{text}\"")]
+    UnknownJump {
+        file: String,
+        line: usize,
+        text: String,
+    },
+    #[error("{file}:{line}: invalid A instruction \"{text}\"")]
+    InvalidAInstruction {
+        file: String,
+        line: usize,
+        text: String,
+    },
+    #[error("{file}:{line}: undefined symbol \"{text}\"")]
+    UndefinedSymbol {
+        file: String,
+        line: usize,
+        text: String,
+    },
+    #[error("{file}:{line}: invalid label \"{text}\"")]
+    InvalidLabel {
+        file: String,
+        line: usize,
+        text: String,
+    },
+    #[error("{file}:{line}: invalid instruction word \"{text}\"")]
+    InvalidWord {
+        file: String,
+        line: usize,
+        text: String,
+    },
+    #[error("{file}:{line}: invalid expression \"{text}\"")]
+    InvalidExpression {
+        file: String,
+        line: usize,
+        text: String,
+    },
+    #[error("{file}:{line}: constant \"{text}\" evaluates to {value} which is out of range")]
+    ConstantOutOfRange {
+        file: String,
+        line: usize,
+        text: String,
+        value: i64,
+    },
+    #[error("{file}:{line}: unknown directive \"{text}\"")]
+    UnknownDirective {
+        file: String,
+        line: usize,
+        text: String,
+    },
+    #[error("{file}:{line}: invalid directive \"{text}\"")]
+    InvalidDirective {
+        file: String,
+        line: usize,
+        text: String,
+    },
+    #[error("{file}:{line}: .org {address} moves back before address {current}")]
+    OrgOverlap {
+        file: String,
+        line: usize,
+        address: u16,
+        current: u16,
+    },
+    #[error("{file}: {source}")]
+    Io {
+        file: String,
+        source: std::io::Error,
+    },
+    #[error("{file}:{line}: cannot include \"{text}\": {source}")]
+    Include {
+        file: String,
+        line: usize,
+        text: String,
+        source: std::io::Error,
+    },
+    #[error("{file}:{line}: include cycle \"{text}\"")]
+    IncludeCycle {
+        file: String,
+        line: usize,
+        text: String,
+    },
+    /// Error in an included file with the chain of .include directives leading to it
+    #[error("{error}{chain}")]
+    Included { error: Box<AsmError>, chain: String },
+    #[error("cannot write output: {0}")]
+    Output(std::io::Error),
+    #[error("{count} warning(s) treated as errors")]
+    DeniedWarnings { count: usize },
+    #[error("{file}:{line}: program exceeds the ROM size of {size} words")]
+    RomOverflow {
+        file: String,
+        line: usize,
+        size: usize,
+    },
+    #[error("{file}:{line}: \"{text}\" is not allowed in strict mode")]
+    NotStrict {
+        file: String,
+        line: usize,
+        text: String,
+    },
+    #[error("{file}:{line}: invalid macro \"{text}\"")]
+    InvalidMacro {
+        file: String,
+        line: usize,
+        text: String,
+    },
+    #[error("{file}:{line}: macro \"{text}\" is missing .endmacro")]
+    UnterminatedMacro {
+        file: String,
+        line: usize,
+        text: String,
+    },
+    #[error("{file}:{line}: recursive macro \"{text}\"")]
+    RecursiveMacro {
+        file: String,
+        line: usize,
+        text: String,
+    },
+}
+
+impl AsmError {
+    /// Line of the source file the error was found in.
+    /// Errors in included files return the line in the included file
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            AsmError::UnknownComp { line, .. }
+            | AsmError::UnknownDest { line, .. }
+            | AsmError::UnknownJump { line, .. }
+            | AsmError::InvalidAInstruction { line, .. }
+            | AsmError::UndefinedSymbol { line, .. }
+            | AsmError::InvalidLabel { line, .. }
+            | AsmError::InvalidWord { line, .. }
+            | AsmError::InvalidExpression { line, .. }
+            | AsmError::ConstantOutOfRange { line, .. }
+            | AsmError::UnknownDirective { line, .. }
+            | AsmError::InvalidDirective { line, .. }
+            | AsmError::OrgOverlap { line, .. }
+            | AsmError::Include { line, .. }
+            | AsmError::IncludeCycle { line, .. }
+            | AsmError::RomOverflow { line, .. }
+            | AsmError::NotStrict { line, .. }
+            | AsmError::InvalidMacro { line, .. }
+            | AsmError::UnterminatedMacro { line, .. }
+            | AsmError::RecursiveMacro { line, .. } => Some(*line),
+            AsmError::Included { error, .. } => error.line(),
+            AsmError::Io { .. } | AsmError::Output(_) | AsmError::DeniedWarnings { .. } => None,
+        }
+    }
+}
+
+/// File that source lines are read from
+#[derive(Debug)]
+struct SourceFile {
+    /// Name used for error reporting
+    name: String,
+    /// Path used to resolve includes and detect include cycles
+    path: PathBuf,
+    /// File and line of the .include directive that included this file
+    included_from: Option<(Rc<SourceFile>, usize)>,
+}
+
+/// Line of source after include and macro expansion.
+/// Keeps the file and line number it came from for error reporting
+#[derive(Debug, Clone)]
+struct SourceLine {
+    file: Rc<SourceFile>,
+    number: usize,
+    text: String,
+}
+
+/// Macro name to the body lines between .macro and .endmacro
+type MacroTable = HashMap<String, Vec<SourceLine>>;
+
+type SymbolTable = HashMap<String, u16>;
+/// Symbols with their addresses in the order they were found
+pub type SymbolList = Vec<(String, u16)>;
+const A_INSTRUCTION_SYMBOL: char = '@';
+const COMMENT_SYMBOL: &str = "//";
+const LEFT_LABEL_SYMBOL: char = '(';
+const RIGHT_LABEL_SYMBOL: char = ')';
+const MAX_CONSTANT: i64 = 0x7fff;
+/// Number of words in the instruction memory
+const ROM_SIZE: usize = 32768;
+const DIRECTIVE_SYMBOL: char = '.';
+const ORG_DIRECTIVE: &str = ".org";
+const WORD_DIRECTIVE: &str = ".word";
+const INCLUDE_DIRECTIVE: &str = ".include";
+const MACRO_DIRECTIVE: &str = ".macro";
+const END_MACRO_DIRECTIVE: &str = ".endmacro";
+const PREDEFINED_SYMBOL: [(&str, u16); 23] = [
+    ("SP", 0),
+    ("LCL", 1),
+    ("ARG", 2),
+    ("THIS", 3),
+    ("THAT", 4),
+    ("R0", 0),
+    ("R1", 1),
+    ("R2", 2),
+    ("R3", 3),
+    ("R4", 4),
+    ("R5", 5),
+    ("R6", 6),
+    ("R7", 7),
+    ("R8", 8),
+    ("R9", 9),
+    ("R10", 10),
+    ("R11", 11),
+    ("R12", 12),
+    ("R13", 13),
+    ("R14", 14),
+    ("R15", 15),
+    ("SCREEN", 0x4000),
+    ("KBD", 0x6000),
+];
+
+trait Instruction {
+    /// Convert instruction to binary text (hack format)
+    fn to_binary_text(&self) -> String;
+
+    /// Convert instruction to a 16 bit word
+    fn to_word(&self) -> u16 {
+        u16::from_str_radix(self.to_binary_text().trim_end(), 2).unwrap()
+    }
+}
+
+/// All mnemonics accepted by encode_comp, encode_dest, and encode_jump.
+/// Used to decode in reverse with the same tables.
+const COMP_MNEMONICS: [&str; 28] = [
+    "0", "1", "-1", "D", "A", "M", "!D", "!A", "!M", "-D", "-A", "-M", "D+1", "A+1", "M+1", "D-1",
+    "A-1", "M-1", "D+A", "D+M", "D-A", "D-M", "A-D", "M-D", "D&A", "D&M", "D|A", "D|M",
+];
+const DEST_MNEMONICS: [&str; 7] = ["M", "D", "MD", "A", "AM", "AD", "AMD"];
+const JUMP_MNEMONICS: [&str; 7] = ["JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
+
+/// Shift comps of extended Hack CPUs.
+/// These are encoded with SHIFT_PREFIX instead of C_PREFIX
+const SHIFT_MNEMONICS: [&str; 6] = ["A<<", "D<<", "M<<", "A>>", "D>>", "M>>"];
+const C_PREFIX: &str = "111";
+const SHIFT_PREFIX: &str = "101";
+
+/// Get the 7 bit 'a' + 'c' field for shift comps.
+/// Only available with the "shift" feature
+#[cfg(feature = "shift")]
+fn encode_shift(comp: &str) -> Option<&'static str> {
+    let bits = match comp {
+        "A<<" => "0100000",
+        "D<<" => "0110000",
+        "M<<" => "1100000",
+        "A>>" => "0000000",
+        "D>>" => "0010000",
+        "M>>" => "1000000",
+        _ => return None,
+    };
+    Some(bits)
+}
+
+#[cfg(not(feature = "shift"))]
+fn encode_shift(_comp: &str) -> Option<&'static str> {
+    None
+}
+
+/// Get the 7 bit 'a' + 'c' field for comp
+fn encode_comp(comp: &str) -> Option<&'static str> {
+    let bits = match comp {
+        "0" => "0101010",
+        "1" => "0111111",
+        "-1" => "0111010",
+        "D" => "0001100",
+        "A" => "0110000",
+        "M" => "1110000",
+        "!D" => "0001101",
+        "!A" => "0110001",
+        "!M" => "1110001",
+        "-D" => "0001111",
+        "-A" => "0110011",
+        "-M" => "1110011",
+        "D+1" => "0011111",
+        "A+1" => "0110111",
+        "M+1" => "1110111",
+        "D-1" => "0001110",
+        "A-1" => "0110010",
+        "M-1" => "1110010",
+        "D+A" => "0000010",
+        "D+M" => "1000010",
+        "D-A" => "0010011",
+        "D-M" => "1010011",
+        "A-D" => "0000111",
+        "M-D" => "1000111",
+        "D&A" => "0000000",
+        "D&M" => "1000000",
+        "D|A" => "0010101",
+        "D|M" => "1010101",
+        _ => return None,
+    };
+    Some(bits)
+}
+
+fn encode_dest(dest: Option<&str>) -> Option<&'static str> {
+    match dest {
+        None => Some("000"),
+        Some("M") => Some("001"),
+        Some("D") => Some("010"),
+        Some("MD") => Some("011"),
+        Some("A") => Some("100"),
+        Some("AM") => Some("101"),
+        Some("AD") => Some("110"),
+        Some("AMD") => Some("111"),
+        _ => None,
+    }
+}
+
+/// Variant comp mnemonics accepted in extended mode and their official form
+const EXTENDED_COMP_ALIASES: [(&str, &str); 9] = [
+    ("1+D", "D+1"),
+    ("1+A", "A+1"),
+    ("1+M", "M+1"),
+    ("A+D", "D+A"),
+    ("M+D", "D+M"),
+    ("A&D", "D&A"),
+    ("M&D", "D&M"),
+    ("A|D", "D|A"),
+    ("M|D", "D|M"),
+];
+
+/// Order of registers in official dest mnemonics
+const DEST_ORDER: &str = "AMD";
+
+/// Get the official form of a variant comp mnemonic
+fn normalize_comp(comp: &str) -> &str {
+    EXTENDED_COMP_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == comp)
+        .map_or(comp, |(_, official)| official)
+}
+
+/// Get the official form of a dest with registers in any order (e.g. "DM" to "MD")
+fn normalize_dest(dest: &str) -> String {
+    let mut registers: Vec<char> = dest.chars().collect();
+    registers.sort_by_key(|r| DEST_ORDER.find(*r));
+    registers.into_iter().collect()
+}
+
+fn encode_jump(jump: Option<&str>) -> Option<&'static str> {
+    match jump {
+        None => Some("000"),
+        Some("JGT") => Some("001"),
+        Some("JEQ") => Some("010"),
+        Some("JGE") => Some("011"),
+        Some("JLT") => Some("100"),
+        Some("JNE") => Some("101"),
+        Some("JLE") => Some("110"),
+        Some("JMP") => Some("111"),
+        _ => None,
+    }
+}
+
+impl Instruction for CInstruction {
+    fn to_binary_text(&self) -> String {
+        // Each field is validated when the instruction is created
+        let (prefix, comp) = match encode_shift(&self.comp) {
+            Some(bits) => (SHIFT_PREFIX, bits),
+            None => (C_PREFIX, encode_comp(&self.comp).unwrap()),
+        };
+        format!(
+            "{}{}{}{}\n",
+            prefix,
+            comp,
+            encode_dest(self.dest.as_deref()).unwrap(),
+            encode_jump(self.jump.as_deref()).unwrap()
+        )
+    }
+}
+
+impl CInstruction {
+    fn new(code: &str, mode: Mode, file: &str, line: usize) -> Result<CInstruction, AsmError> {
+        // Extended mode allows spaces between fields like "D; JLE"
+        let code: String = match mode {
+            Mode::Extended => code.chars().filter(|c| !c.is_whitespace()).collect(),
+            _ => code.to_string(),
+        };
+        let code = code.as_str();
+        let dest_delimiter = '=';
+        let jmp_delimiter = ';';
+        let (dest, comp_jmp) = match code.find(dest_delimiter) {
+            Some(pos) => (Some(&code[..pos]), &code[pos + 1..]),
+            None => (None, code),
+        };
+        let (comp, jump) = match comp_jmp.find(jmp_delimiter) {
+            Some(pos) => (&comp_jmp[..pos], Some(&comp_jmp[pos + 1..])),
+            None => (comp_jmp, None),
+        };
+        let normalized_dest = dest.map(normalize_dest);
+        let (comp, dest) = match mode {
+            Mode::Extended => (normalize_comp(comp), normalized_dest.as_deref()),
+            _ => (comp, dest),
+        };
+        // Shifts are not part of the official instruction set
+        let is_shift = mode != Mode::Strict && encode_shift(comp).is_some();
+        if encode_comp(comp).is_none() && !is_shift {
+            return Err(AsmError::UnknownComp {
+                file: file.to_string(),
+                line,
+                text: comp.to_string(),
+            });
+        }
+        if encode_dest(dest).is_none() {
+            return Err(AsmError::UnknownDest {
+                file: file.to_string(),
+                line,
+                text: dest.unwrap().to_string(),
+            });
+        }
+        if encode_jump(jump).is_none() {
+            return Err(AsmError::UnknownJump {
+                file: file.to_string(),
+                line,
+                text: jump.unwrap().to_string(),
+            });
+        }
+        Ok(CInstruction {
+            comp: comp.to_string(),
+            dest: dest.map(|d| d.to_string()),
+            jump: jump.map(|j| j.to_string()),
+        })
+    }
+}
+
+impl Instruction for AInstruction {
+    fn to_binary_text(&self) -> String {
+        format!("{:016b}\n", self.value)
+    }
+}
+
+impl Instruction for DataWord {
+    fn to_binary_text(&self) -> String {
+        format!("{:016b}\n", self.value)
+    }
+}
+
+/// Returns true if text is a valid symbol.
+/// Symbols are letters, digits, '_', '.', '$', and ':' not starting with a digit
+fn is_valid_symbol(text: &str) -> bool {
+    match text.chars().next() {
+        None => false,
+        Some(c) if c.is_ascii_digit() => false,
+        Some(_) => text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$' | ':')),
+    }
+}
+
+/// Get symbol from label line
+fn get_symbol_from_label<'a>(code: &'a str, file: &str, line: usize) -> Result<&'a str, AsmError> {
+    let symbol = code
+        .strip_prefix(LEFT_LABEL_SYMBOL)
+        .and_then(|c| c.strip_suffix(RIGHT_LABEL_SYMBOL));
+    match symbol {
+        Some(s) if is_valid_symbol(s) => Ok(s),
+        _ => Err(AsmError::InvalidLabel {
+            file: file.to_string(),
+            line,
+            text: code.to_string(),
+        }),
+    }
+}
+
+/// Token of a constant expression in an A instruction
+#[derive(Debug, PartialEq)]
+enum ExpressionToken<'a> {
+    Number(i64),
+    Symbol(&'a str),
+    Plus,
+    Minus,
+    LeftParen,
+    RightParen,
+}
+
+/// Returns true if text should be evaluated as a constant expression
+fn is_expression(text: &str) -> bool {
+    text.contains(['+', '-', '(', ')'])
+}
+
+/// Split expression text to tokens. Returns None if an unexpected character is found
+fn tokenize_expression(text: &str) -> Option<Vec<ExpressionToken<'_>>> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let token_length = match c {
+            '+' | '-' | '(' | ')' => {
+                tokens.push(match c {
+                    '+' => ExpressionToken::Plus,
+                    '-' => ExpressionToken::Minus,
+                    '(' => ExpressionToken::LeftParen,
+                    _ => ExpressionToken::RightParen,
+                });
+                1
+            }
+            _ => {
+                let length = rest
+                    .find(|c: char| {
+                        !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$' | ':'))
+                    })
+                    .unwrap_or(rest.len());
+                let word = &rest[..length];
+                if c.is_ascii_digit() {
+                    tokens.push(ExpressionToken::Number(str::parse::<i64>(word).ok()?));
+                } else if is_valid_symbol(word) {
+                    tokens.push(ExpressionToken::Symbol(word));
+                } else {
+                    return None;
+                }
+                length
+            }
+        };
+        rest = rest[token_length..].trim_start();
+    }
+    Some(tokens)
+}
+
+/// Evaluates expressions of numbers and symbols combined with '+', '-', and parentheses
+struct ExpressionEvaluator<'a, 'b> {
+    tokens: std::iter::Peekable<std::vec::IntoIter<ExpressionToken<'a>>>,
+    symbol_table: &'b SymbolTable,
+    code: &'b str,
+    file: &'b str,
+    line: usize,
+}
+
+impl<'a, 'b> ExpressionEvaluator<'a, 'b> {
+    fn invalid(&self) -> AsmError {
+        AsmError::InvalidExpression {
+            file: self.file.to_string(),
+            line: self.line,
+            text: self.code.to_string(),
+        }
+    }
+
+    /// sum := term (('+' | '-') term)*
+    fn sum(&mut self) -> Result<i64, AsmError> {
+        let mut value = self.term()?;
+        loop {
+            match self.tokens.peek() {
+                Some(ExpressionToken::Plus) => {
+                    self.tokens.next();
+                    value = value.saturating_add(self.term()?);
+                }
+                Some(ExpressionToken::Minus) => {
+                    self.tokens.next();
+                    value = value.saturating_sub(self.term()?);
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    /// term := number | symbol | '-' term | '(' sum ')'
+    fn term(&mut self) -> Result<i64, AsmError> {
+        match self.tokens.next() {
+            Some(ExpressionToken::Number(n)) => Ok(n),
+            Some(ExpressionToken::Symbol(symbol)) => match self.symbol_table.get(symbol) {
+                Some(address) => Ok(*address as i64),
+                None => Err(AsmError::UndefinedSymbol {
+                    file: self.file.to_string(),
+                    line: self.line,
+                    text: symbol.to_string(),
+                }),
+            },
+            Some(ExpressionToken::Minus) => Ok(-self.term()?),
+            Some(ExpressionToken::LeftParen) => {
+                let value = self.sum()?;
+                match self.tokens.next() {
+                    Some(ExpressionToken::RightParen) => Ok(value),
+                    _ => Err(self.invalid()),
+                }
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+}
+
+/// Evaluate a constant expression like "LABEL+2" or "(KBD-1)".
+/// code is the whole line used for error reporting
+fn evaluate_expression(
+    expression: &str,
+    code: &str,
+    symbol_table: &SymbolTable,
+    file: &str,
+    line: usize,
+) -> Result<i64, AsmError> {
+    let tokens = tokenize_expression(expression).ok_or_else(|| AsmError::InvalidExpression {
+        file: file.to_string(),
+        line,
+        text: code.to_string(),
+    })?;
+    let mut evaluator = ExpressionEvaluator {
+        tokens: tokens.into_iter().peekable(),
+        symbol_table,
+        code,
+        file,
+        line,
+    };
+    let value = evaluator.sum()?;
+    if evaluator.tokens.next().is_some() {
+        return Err(evaluator.invalid());
+    }
+    Ok(value)
+}
+
+/// Check that a constant fits in range
+fn check_constant(
+    value: i64,
+    range: std::ops::RangeInclusive<i64>,
+    text: &str,
+    file: &str,
+    line: usize,
+) -> Result<i64, AsmError> {
+    if range.contains(&value) {
+        Ok(value)
+    } else {
+        Err(AsmError::ConstantOutOfRange {
+            file: file.to_string(),
+            line,
+            text: text.to_string(),
+            value,
+        })
+    }
+}
+
+/// Get symbol from A instruction line.
+/// Returns None if a direct address or a constant expression is specified.
+/// Symbols in expressions never allocate variables.
+fn get_symbol_from_a_instruction<'a>(
+    code: &'a str,
+    file: &str,
+    line: usize,
+) -> Result<Option<&'a str>, AsmError> {
+    let address_or_symbol = &code[1..];
+    let is_address =
+        !address_or_symbol.is_empty() && address_or_symbol.chars().all(|c| c.is_ascii_digit());
+    if is_address || is_expression(address_or_symbol) {
+        // found direct address or expression so we don't have any symbols
+        Ok(None)
+    } else if is_valid_symbol(address_or_symbol) {
+        // Return symbol as string
+        Ok(Some(address_or_symbol))
+    } else {
+        Err(AsmError::InvalidAInstruction {
+            file: file.to_string(),
+            line,
+            text: code.to_string(),
+        })
+    }
+}
+
+impl AInstruction {
+    fn new(
+        code: &str,
+        symbol_table: &SymbolTable,
+        file: &str,
+        line: usize,
+    ) -> Result<AInstruction, AsmError> {
+        match get_symbol_from_a_instruction(code, file, line)? {
+            // A instruction is direct address or expression
+            // The value must fit in the 15 bits of an A instruction
+            None => {
+                let expression = &code[1..];
+                let value = evaluate_expression(expression, code, symbol_table, file, line)?;
+                let value = check_constant(value, 0..=MAX_CONSTANT, expression, file, line)?;
+                Ok(AInstruction {
+                    value: value as u16,
+                })
+            }
+            // A instruction is a symbol
+            // Lookup table to get address.
+            // Labels right after the end of a full ROM do not fit either
+            Some(symbol) => match symbol_table.get(symbol) {
+                Some(address) => {
+                    check_constant(*address as i64, 0..=MAX_CONSTANT, symbol, file, line)?;
+                    Ok(AInstruction { value: *address })
+                }
+                None => Err(AsmError::UndefinedSymbol {
+                    file: file.to_string(),
+                    line,
+                    text: symbol.to_string(),
+                }),
+            },
+        }
+    }
+}
+
+fn remove_comment(line: &str) -> &str {
+    match line.find(COMMENT_SYMBOL) {
+        Some(pos) => {
+            // create substr based on comment position
+            let (first, _last) = line.split_at(pos);
+            first
+        }
+        // No comment so we just use the original line
+        None => line,
+    }
+}
+
+/// Get the line type of a directive line without evaluating .word values
+fn scan_directive(code: &str, file: &str, line: usize) -> Result<LineType, AsmError> {
+    let invalid = || AsmError::InvalidDirective {
+        file: file.to_string(),
+        line,
+        text: code.to_string(),
+    };
+    let (directive, operand) = match code.find(char::is_whitespace) {
+        Some(pos) => (&code[..pos], code[pos..].trim()),
+        None => (code, ""),
+    };
+    match directive {
+        ORG_DIRECTIVE => match str::parse::<u16>(operand) {
+            Ok(address) if address as i64 <= MAX_CONSTANT => Ok(LineType::Org(address)),
+            _ => Err(invalid()),
+        },
+        WORD_DIRECTIVE if operand.is_empty() => Err(invalid()),
+        WORD_DIRECTIVE => Ok(LineType::Word(operand.split(',').count())),
+        _ => Err(AsmError::UnknownDirective {
+            file: file.to_string(),
+            line,
+            text: directive.to_string(),
+        }),
+    }
+}
+
+/// Emit the words of a directive line.
+/// .word emits each value as is while .org is left to the caller that tracks the address
+fn parse_directive(
+    code: &str,
+    symbol_table: &SymbolTable,
+    instruction_output: &mut Vec<Box<dyn Instruction>>,
+    file: &str,
+    line: usize,
+) -> Result<LineType, AsmError> {
+    let line_type = scan_directive(code, file, line)?;
+    match line_type {
+        LineType::Org(_) => {}
+        _ => {
+            let values = code[WORD_DIRECTIVE.len()..].split(',');
+            for text in values {
+                let text = text.trim();
+                let value = evaluate_expression(text, code, symbol_table, file, line)?;
+                // Allow both signed and unsigned 16 bit values
+                let value =
+                    check_constant(value, i16::MIN as i64..=u16::MAX as i64, text, file, line)?;
+                instruction_output.push(Box::new(DataWord {
+                    value: value as u16,
+                }));
+            }
+        }
+    }
+    Ok(line_type)
+}
+
+fn parse_line(
+    line: &str,
+    symbol_table: &SymbolTable,
+    instruction_output: &mut Vec<Box<dyn Instruction>>,
+    mode: Mode,
+    file: &str,
+    line_number: usize,
+) -> Result<LineType, AsmError> {
+    let mut code = remove_comment(line);
+    code = code.trim();
+    if code.is_empty() {
+        // is comment line
+        return Ok(LineType::Blank);
+    }
+    let first_char = code.chars().next();
+    match first_char {
+        Some(A_INSTRUCTION_SYMBOL) => {
+            let ainst = AInstruction::new(code, symbol_table, file, line_number)?;
+            // println!("{:?}", ainst);
+            instruction_output.push(Box::new(ainst));
+            Ok(LineType::AInstruction)
+        }
+        Some(LEFT_LABEL_SYMBOL) => Ok(LineType::Label),
+        Some(DIRECTIVE_SYMBOL) => {
+            parse_directive(code, symbol_table, instruction_output, file, line_number)
+        }
+        _ => {
+            let cinst = CInstruction::new(code, mode, file, line_number)?;
+            // println!("{:?}", cinst);
+            instruction_output.push(Box::new(cinst));
+            Ok(LineType::CInstruction)
+        }
+    }
+}
+
+fn scan_label_symbol(
+    line: &str,
+    symbol_table: &mut SymbolTable,
+    current_address: u16,
+    labels: &mut Vec<(String, u16)>,
+    file: &str,
+    line_number: usize,
+) -> Result<LineType, AsmError> {
+    let mut code = remove_comment(line);
+    code = code.trim();
+    if code.is_empty() {
+        // is comment line
+        return Ok(LineType::Blank);
+    }
+    // println!("{}", code);
+    let first_char = code.chars().next();
+    match first_char {
+        Some(A_INSTRUCTION_SYMBOL) => Ok(LineType::AInstruction), // Nothing to do for A instructions
+        Some(LEFT_LABEL_SYMBOL) => {
+            // for label lines we get address for the next line and store it to the symbol table
+            let symbol = get_symbol_from_label(code, file, line_number)?;
+            symbol_table.insert(symbol.to_string(), current_address);
+            labels.push((symbol.to_string(), current_address));
+            Ok(LineType::Label)
+        }
+        Some(DIRECTIVE_SYMBOL) => scan_directive(code, file, line_number),
+        _ => Ok(LineType::CInstruction), // Nothing to do for C instructions
+    }
+}
+
+fn scan_variable_symbol(
+    line: &str,
+    symbol_table: &mut SymbolTable,
+    variable_address: &mut u16,
+    variables: &mut Vec<(String, u16)>,
+    file: &str,
+    line_number: usize,
+) -> Result<LineType, AsmError> {
+    let mut code = remove_comment(line);
+    code = code.trim();
+    if code.is_empty() {
+        // is comment line
+        return Ok(LineType::Blank);
+    }
+    // println!("{}", code);
+    let first_char = code.chars().next();
+    match first_char {
+        Some(A_INSTRUCTION_SYMBOL) => {
+            let maybe_symbol = get_symbol_from_a_instruction(code, file, line_number)?;
+            // println!("{:?}", maybe_symbol);
+            if let Some(symbol) = maybe_symbol {
+                // If symbol is new we assign a new address
+                if !symbol_table.contains_key(symbol) {
+                    symbol_table.insert(symbol.to_string(), *variable_address);
+                    variables.push((symbol.to_string(), *variable_address));
+                    *variable_address += 1;
+                }
+            }
+            // Direct address specified. Ignore and go next
+            Ok(LineType::AInstruction)
+        }
+        Some(LEFT_LABEL_SYMBOL) => Ok(LineType::Label), // Nothing to do for Labels
+        Some(DIRECTIVE_SYMBOL) => scan_directive(code, file, line_number),
+        _ => Ok(LineType::CInstruction), // Nothing to do for C instructions
+    }
+}
+
+impl SourceFile {
+    /// Describe the .include directives leading to this file, innermost first
+    fn include_chain(&self) -> String {
+        let mut chain = String::new();
+        let mut current = &self.included_from;
+        while let Some((parent, line)) = current {
+            chain.push_str(&format!("\n  included from {}:{}", parent.name, line));
+            current = &parent.included_from;
+        }
+        chain
+    }
+}
+
+/// Attach the include chain of the line to an error found on it
+fn with_include_chain(error: AsmError, line: &SourceLine) -> AsmError {
+    if line.file.included_from.is_none() {
+        return error;
+    }
+    AsmError::Included {
+        error: Box::new(error),
+        chain: line.file.include_chain(),
+    }
+}
+
+/// Get the quoted path of an .include line.
+/// Returns None if the line is not an .include directive
+fn get_include_path(code: &str) -> Option<&str> {
+    let mut words = code.split_whitespace();
+    if words.next() != Some(INCLUDE_DIRECTIVE) {
+        return None;
+    }
+    Some(code[INCLUDE_DIRECTIVE.len()..].trim())
+}
+
+/// Check that a line only uses the official Hack assembly language.
+/// Directives and expressions are assembler extensions
+fn check_strict(line: &SourceLine) -> Result<(), AsmError> {
+    let code = remove_comment(&line.text).trim();
+    let is_extension = match code.chars().next() {
+        Some(DIRECTIVE_SYMBOL) => true,
+        Some(A_INSTRUCTION_SYMBOL) => is_expression(&code[1..]),
+        _ => false,
+    };
+    if is_extension {
+        return Err(AsmError::NotStrict {
+            file: line.file.name.clone(),
+            line: line.number,
+            text: code.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Input that source lines are read from
+pub type SourceInput<'a> = Box<dyn BufRead + 'a>;
+
+/// Reads source lines one at a time while expanding .include directives and macros.
+/// Only macro bodies are kept in memory so a pass streams through the source
+struct SourceReader<'a> {
+    /// Files being read with the innermost include last
+    files: Vec<(
+        Rc<SourceFile>,
+        std::iter::Enumerate<std::io::Lines<SourceInput<'a>>>,
+    )>,
+    macros: MacroTable,
+    /// Name, definition line, and body of the macro currently being defined
+    definition: Option<(String, SourceLine, Vec<SourceLine>)>,
+    /// Expanded lines waiting to be read
+    pending: VecDeque<SourceLine>,
+    /// Paths of all .include directives read so far, including ones that failed to open
+    included: Vec<PathBuf>,
+    mode: Mode,
+}
+
+impl<'a> SourceReader<'a> {
+    fn new(input: SourceInput<'a>, file: &str, mode: Mode) -> SourceReader<'a> {
+        let source_file = Rc::new(SourceFile {
+            name: file.to_string(),
+            path: std::fs::canonicalize(file).unwrap_or_else(|_| PathBuf::from(file)),
+            included_from: None,
+        });
+        SourceReader {
+            files: vec![(source_file, input.lines().enumerate())],
+            macros: MacroTable::new(),
+            definition: None,
+            pending: VecDeque::new(),
+            included: Vec::new(),
+            mode,
+        }
+    }
+
+    /// Start reading an included file.
+    /// Included paths are relative to the directory of the including file
+    fn include(
+        &mut self,
+        line: &SourceLine,
+        code: &str,
+        quoted_path: &str,
+    ) -> Result<(), AsmError> {
+        let file = &line.file;
+        let error = |e| with_include_chain(e, line);
+        let include_path = match quoted_path
+            .strip_prefix('"')
+            .and_then(|p| p.strip_suffix('"'))
+        {
+            Some(p) if !p.is_empty() => file.path.parent().unwrap_or(Path::new("")).join(p),
+            _ => {
+                return Err(error(AsmError::InvalidDirective {
+                    file: file.name.clone(),
+                    line: line.number,
+                    text: code.to_string(),
+                }))
+            }
+        };
+        if !self.included.contains(&include_path) {
+            self.included.push(include_path.clone());
+        }
+        let include_error = |e| {
+            error(AsmError::Include {
+                file: file.name.clone(),
+                line: line.number,
+                text: include_path.display().to_string(),
+                source: e,
+            })
+        };
+        let canonical_path = std::fs::canonicalize(&include_path).map_err(include_error)?;
+        // Walk up the include stack to find cycles
+        let mut stack = vec![file.clone()];
+        while let Some((parent, _)) = &stack[stack.len() - 1].included_from {
+            stack.push(parent.clone());
+        }
+        if stack.iter().any(|f| f.path == canonical_path) {
+            let mut names: Vec<&str> = stack.iter().rev().map(|f| f.name.as_str()).collect();
+            let name = include_path.display().to_string();
+            names.push(&name);
+            return Err(error(AsmError::IncludeCycle {
+                file: file.name.clone(),
+                line: line.number,
+                text: names.join(" -> "),
+            }));
+        }
+        let input: SourceInput = Box::new(BufReader::new(
+            File::open(&canonical_path).map_err(include_error)?,
+        ));
+        let included_file = Rc::new(SourceFile {
+            name: include_path.display().to_string(),
+            path: canonical_path,
+            included_from: Some((file.clone(), line.number)),
+        });
+        // Keep the directive as a comment so the listing shows where the lines came from
+        self.pending.push_back(SourceLine {
+            text: format!("{} {}", COMMENT_SYMBOL, line.text.trim()),
+            ..line.clone()
+        });
+        self.files.push((included_file, input.lines().enumerate()));
+        Ok(())
+    }
+
+    /// Collect macro definitions and expand macro invocations.
+    /// Macros live in their own namespace and must be defined before they are invoked.
+    /// Body lines keep the line number of the definition
+    fn preprocess(&mut self, line: SourceLine) -> Result<(), AsmError> {
+        if self.mode == Mode::Strict {
+            check_strict(&line)?;
+        }
+        let code = remove_comment(&line.text).trim();
+        if let Some(quoted_path) = get_include_path(code) {
+            return self.include(&line, code, quoted_path);
+        }
+        let invalid = || {
+            with_include_chain(
+                AsmError::InvalidMacro {
+                    file: line.file.name.clone(),
+                    line: line.number,
+                    text: code.to_string(),
+                },
+                &line,
+            )
+        };
+        let mut words = code.split_whitespace();
+        match words.next() {
+            Some(MACRO_DIRECTIVE) => {
+                let name = words.next().unwrap_or("");
+                // Names that are also C instructions (e.g. "D") could never be told apart
+                if self.definition.is_some()
+                    || words.next().is_some()
+                    || !is_valid_symbol(name)
+                    || encode_comp(name).is_some()
+                    || self.macros.contains_key(name)
+                {
+                    return Err(invalid());
+                }
+                self.definition = Some((name.to_string(), line.clone(), Vec::new()));
+            }
+            Some(END_MACRO_DIRECTIVE) => match self.definition.take() {
+                Some((name, _, body)) if words.next().is_none() => {
+                    self.macros.insert(name, body);
+                }
+                _ => return Err(invalid()),
+            },
+            _ => match self.definition.as_mut() {
+                Some((_, _, body)) => body.push(line),
+                None => expand_line(&line, &self.macros, &mut Vec::new(), &mut self.pending)?,
+            },
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for SourceReader<'a> {
+    type Item = Result<SourceLine, AsmError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.pending.pop_front() {
+                return Some(Ok(line));
+            }
+            let (file, lines) = match self.files.last_mut() {
+                Some(current) => current,
+                None => {
+                    // Report a macro left open at the end of the source
+                    return self.definition.take().map(|(name, line, _)| {
+                        Err(with_include_chain(
+                            AsmError::UnterminatedMacro {
+                                file: line.file.name.clone(),
+                                line: line.number,
+                                text: name,
+                            },
+                            &line,
+                        ))
+                    });
+                }
+            };
+            let file = file.clone();
+            match lines.next() {
+                None => {
+                    self.files.pop();
+                }
+                Some((_, Err(e))) => {
+                    return Some(Err(AsmError::Io {
+                        file: file.name.clone(),
+                        source: e,
+                    }))
+                }
+                Some((idx, Ok(text))) => {
+                    let line = SourceLine {
+                        file,
+                        number: idx + 1,
+                        text,
+                    };
+                    if let Err(e) = self.preprocess(line) {
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Start a pass over the source
+fn read_source<'a, O>(open: &O, file: &str, mode: Mode) -> Result<SourceReader<'a>, AsmError>
+where
+    O: Fn() -> std::io::Result<SourceInput<'a>>,
+{
+    let input = open().map_err(|e| AsmError::Io {
+        file: file.to_string(),
+        source: e,
+    })?;
+    Ok(SourceReader::new(input, file, mode))
+}
+
+/// Expand an invocation of a macro, or pass the line through if it is not one.
+/// Macros are expanded recursively so bodies may invoke other macros
+fn expand_line(
+    line: &SourceLine,
+    macros: &MacroTable,
+    expanding: &mut Vec<String>,
+    output: &mut VecDeque<SourceLine>,
+) -> Result<(), AsmError> {
+    let code = remove_comment(&line.text).trim();
+    let body = match macros.get(code) {
+        Some(body) => body,
+        None => {
+            // Macros take no parameters
+            let name = code.split_whitespace().next().unwrap_or("");
+            if macros.contains_key(name) {
+                return Err(with_include_chain(
+                    AsmError::InvalidMacro {
+                        file: line.file.name.clone(),
+                        line: line.number,
+                        text: code.to_string(),
+                    },
+                    line,
+                ));
+            }
+            output.push_back(line.clone());
+            return Ok(());
+        }
+    };
+    if expanding.iter().any(|name| name == code) {
+        expanding.push(code.to_string());
+        return Err(with_include_chain(
+            AsmError::RecursiveMacro {
+                file: line.file.name.clone(),
+                line: line.number,
+                text: expanding.join(" -> "),
+            },
+            line,
+        ));
+    }
+    // Keep the invocation as a comment so the listing shows where the expansion came from
+    output.push_back(SourceLine {
+        text: format!("{} {}", COMMENT_SYMBOL, line.text.trim()),
+        ..line.clone()
+    });
+    expanding.push(code.to_string());
+    for body_line in body {
+        expand_line(body_line, macros, expanding, output)?;
+    }
+    expanding.pop();
+    Ok(())
+}
+
+/// Go through source code to init all symbol tables.
+/// Each scan is a separate pass over the source
+fn init_symbol_table<'a, O>(
+    table: &mut SymbolTable,
+    open: &O,
+    file: &str,
+    mode: Mode,
+) -> Result<Symbols, AsmError>
+where
+    O: Fn() -> std::io::Result<SourceInput<'a>>,
+{
+    let mut current_address: usize = 0;
+    let mut labels = Vec::new();
+    // We want to scan for labels first since A instructions can refer to labels that come later.
+    // In such case we cannot distinguish if a symbol is a label or variable, so we scan for labels first to determine variable symbols
+    for line in read_source(open, file, mode)? {
+        let line = &line?;
+        let file = &line.file.name;
+        let line_type = scan_label_symbol(
+            &line.text,
+            table,
+            current_address as u16,
+            &mut labels,
+            file,
+            line.number,
+        )
+        .map_err(|e| with_include_chain(e, line))?;
+        match line_type {
+            // Count up address only for valid instructions
+            LineType::AInstruction | LineType::CInstruction => current_address += 1,
+            LineType::Word(count) => current_address += count,
+            LineType::Org(address) if (address as usize) < current_address => {
+                return Err(with_include_chain(
+                    AsmError::OrgOverlap {
+                        file: file.to_string(),
+                        line: line.number,
+                        address,
+                        current: current_address as u16,
+                    },
+                    line,
+                ))
+            }
+            LineType::Org(address) => current_address = address as usize,
+            _ => {}
+        }
+        if current_address > ROM_SIZE {
+            return Err(with_include_chain(
+                AsmError::RomOverflow {
+                    file: file.to_string(),
+                    line: line.number,
+                    size: ROM_SIZE,
+                },
+                line,
+            ));
+        }
+    }
+    // Scan again for variables
+    let mut variable_address = 16; // variable allocation starts from 16
+    let mut variables = Vec::new();
+    for line in read_source(open, file, mode)? {
+        let line = &line?;
+        let _line_type = scan_variable_symbol(
+            &line.text,
+            table,
+            &mut variable_address,
+            &mut variables,
+            &line.file.name,
+            line.number,
+        )
+        .map_err(|e| with_include_chain(e, line))?;
+    }
+    Ok(Symbols { labels, variables })
+}
+
+/// Find the mnemonic whose encoding matches the bits
+fn decode_field(
+    bits: &str,
+    mnemonics: &[&'static str],
+    encode: fn(&str) -> Option<&'static str>,
+) -> Option<&'static str> {
+    mnemonics.iter().find(|m| encode(m) == Some(bits)).copied()
+}
+
+/// Decode a line of .hack text back to an asm instruction
+fn disassemble_line(code: &str, file: &str, line: usize) -> Result<String, AsmError> {
+    let invalid = || AsmError::InvalidWord {
+        file: file.to_string(),
+        line,
+        text: code.to_string(),
+    };
+    if code.len() != 16 {
+        return Err(invalid());
+    }
+    let word = u16::from_str_radix(code, 2).map_err(|_| invalid())?;
+    if word >> 15 == 0 {
+        return Ok(format!("{}{}", A_INSTRUCTION_SYMBOL, word));
+    }
+    let comp = if code.starts_with(C_PREFIX) {
+        decode_field(&code[3..10], &COMP_MNEMONICS, encode_comp)
+    } else if code.starts_with(SHIFT_PREFIX) {
+        decode_field(&code[3..10], &SHIFT_MNEMONICS, encode_shift)
+    } else {
+        None
+    }
+    .ok_or_else(invalid)?;
+    let dest = decode_field(&code[10..13], &DEST_MNEMONICS, |d| encode_dest(Some(d)));
+    let jump = decode_field(&code[13..16], &JUMP_MNEMONICS, |j| encode_jump(Some(j)));
+    let mut text = String::new();
+    if let Some(d) = dest {
+        text.push_str(&format!("{}=", d));
+    }
+    text.push_str(comp);
+    if let Some(j) = jump {
+        text.push_str(&format!(";{}", j));
+    }
+    Ok(text)
+}
+
+/// Disassemble a whole .hack file
+pub fn disassemble(source: &str, file: &str) -> Result<String, AsmError> {
+    let mut output = String::new();
+    for (idx, line) in source.lines().enumerate() {
+        let code = line.trim();
+        if code.is_empty() {
+            continue;
+        }
+        output.push_str(&disassemble_line(code, file, idx + 1)?);
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+/// Labels and variables resolved by the assembler
+#[derive(Debug)]
+pub struct Symbols {
+    /// Labels in order of definition
+    pub labels: SymbolList,
+    /// Variables in order of allocation
+    pub variables: SymbolList,
+}
+
+/// Suspicious but valid usage of symbols
+#[derive(thiserror::Error, Debug)]
+pub enum AsmWarning {
+    #[error("label \"{name}\" at {address} is never referenced")]
+    UnusedLabel { name: String, address: u16 },
+    #[error("variable \"{name}\" at {address} is never read")]
+    UnreadVariable { name: String, address: u16 },
+}
+
+/// Tracks how symbols are used while encoding to find unused ones
+#[derive(Default)]
+struct SymbolUsage {
+    /// Symbols referenced by A instructions or .word values
+    referenced: HashSet<String>,
+    /// Variables whose memory is written by a dest with M
+    written: HashSet<String>,
+    /// Variables used by a comp, either reading their memory with M or their address with A
+    read: HashSet<String>,
+    /// Symbol held in the A register by the last A instruction
+    current: Option<String>,
+}
+
+impl SymbolUsage {
+    fn track(&mut self, line: &str) {
+        let code = remove_comment(line).trim();
+        match code.chars().next() {
+            Some(A_INSTRUCTION_SYMBOL) => {
+                let operand = &code[1..];
+                self.current = None;
+                if is_valid_symbol(operand) {
+                    self.referenced.insert(operand.to_string());
+                    self.current = Some(operand.to_string());
+                } else {
+                    self.track_expression(operand);
+                }
+            }
+            Some(DIRECTIVE_SYMBOL) => {
+                if let Some(values) = code.strip_prefix(WORD_DIRECTIVE) {
+                    values.split(',').for_each(|v| self.track_expression(v));
+                }
+            }
+            Some(LEFT_LABEL_SYMBOL) | None => {}
+            _ => {
+                let (dest, comp_jump) = match code.find('=') {
+                    Some(pos) => (&code[..pos], &code[pos + 1..]),
+                    None => ("", code),
+                };
+                let comp = comp_jump.split(';').next().unwrap_or("");
+                if let Some(symbol) = &self.current {
+                    if comp.contains('M') || comp.contains('A') {
+                        self.read.insert(symbol.clone());
+                    }
+                    if dest.contains('M') {
+                        self.written.insert(symbol.clone());
+                    }
+                }
+                // A no longer holds the symbol
+                if dest.contains('A') {
+                    self.current = None;
+                }
+            }
+        }
+    }
+
+    /// Symbols in expressions count as both referenced and read
+    fn track_expression(&mut self, expression: &str) {
+        for token in tokenize_expression(expression).unwrap_or_default() {
+            if let ExpressionToken::Symbol(symbol) = token {
+                self.referenced.insert(symbol.to_string());
+                self.read.insert(symbol.to_string());
+            }
+        }
+    }
+
+    /// Get warnings for labels never referenced and variables written but never read
+    fn warnings(&self, symbols: &Symbols) -> Vec<AsmWarning> {
+        let unused_labels = symbols
+            .labels
+            .iter()
+            .filter(|(name, _)| !self.referenced.contains(name))
+            .map(|(name, address)| AsmWarning::UnusedLabel {
+                name: name.clone(),
+                address: *address,
+            });
+        let unread_variables = symbols
+            .variables
+            .iter()
+            .filter(|(name, _)| self.written.contains(name) && !self.read.contains(name))
+            .map(|(name, address)| AsmWarning::UnreadVariable {
+                name: name.clone(),
+                address: *address,
+            });
+        unused_labels.chain(unread_variables).collect()
+    }
+}
+
+/// Symbols and warnings from assembling a source
+#[derive(Debug)]
+pub struct Assembly {
+    pub symbols: Symbols,
+    pub warnings: Vec<AsmWarning>,
+}
+
+/// Result of assembling a source
+#[derive(Debug)]
+pub struct Program {
+    /// Words of the ROM image in address order
+    pub words: Vec<u16>,
+    pub symbols: Symbols,
+    pub warnings: Vec<AsmWarning>,
+    /// ROM address, encoding, and source of each line
+    pub listing: String,
+}
+
+/// Write the listing entry of a line.
+/// Only instruction lines get an address and encoding, and .word lines list every word
+fn write_listing_entry(
+    writer: &mut dyn Write,
+    address: usize,
+    instructions: &[Box<dyn Instruction>],
+    source: &str,
+) -> std::io::Result<()> {
+    if instructions.is_empty() {
+        writeln!(writer, "{:5}  {:16}  {}", "", "", source)?;
+    }
+    for (offset, inst) in instructions.iter().enumerate() {
+        let entry = format!(
+            "{:>5}  {}  {}",
+            address + offset,
+            inst.to_binary_text().trim_end(),
+            if offset == 0 { source } else { "" }
+        );
+        writeln!(writer, "{}", entry.trim_end())?;
+    }
+    Ok(())
+}
+
+/// Assemble source in streaming passes for labels, variables, and encoding.
+/// Symbol usage is tracked in the encoding pass to warn about unused symbols.
+/// open is called at the start of each pass so the whole source is never held in memory.
+/// Each word of the ROM image is passed to emit in address order,
+/// and the listing is written if a writer is given
+pub fn assemble_stream<'a, O, E>(
+    open: O,
+    file: &str,
+    mode: Mode,
+    mut emit: E,
+    mut listing: Option<&mut dyn Write>,
+) -> Result<Assembly, AsmError>
+where
+    O: Fn() -> std::io::Result<SourceInput<'a>>,
+    E: FnMut(u16) -> std::io::Result<()>,
+{
+    let mut symbol_table: SymbolTable = PREDEFINED_SYMBOL
+        .iter()
+        .cloned()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect();
+    let symbols = init_symbol_table(&mut symbol_table, &open, file, mode)?;
+    // println!("{:?}", symbol_table);
+    let mut address = 0;
+    let mut instructions = vec![];
+    let mut usage = SymbolUsage::default();
+    for line in read_source(&open, file, mode)? {
+        let line = line?;
+        instructions.clear();
+        let line_type = parse_line(
+            &line.text,
+            &symbol_table,
+            &mut instructions,
+            mode,
+            &line.file.name,
+            line.number,
+        )
+        .map_err(|e| with_include_chain(e, &line))?;
+        // println!("{:?}: {}", line_type, line.text);
+        usage.track(&line.text);
+        if let LineType::Org(org_address) = line_type {
+            // Pad the gap with zeros. Overlaps are already checked while scanning labels
+            while address < org_address as usize {
+                emit(0).map_err(AsmError::Output)?;
+                address += 1;
+            }
+        }
+        if let Some(writer) = listing.as_mut() {
+            write_listing_entry(*writer, address, &instructions, line.text.trim_end())
+                .map_err(AsmError::Output)?;
+        }
+        for inst in &instructions {
+            emit(inst.to_word()).map_err(AsmError::Output)?;
+            address += 1;
+        }
+    }
+    let warnings = usage.warnings(&symbols);
+    Ok(Assembly { symbols, warnings })
+}
+
+/// Assemble asm source text in memory.
+/// file is used to resolve includes and report errors
+pub fn assemble(source: &str, file: &str, mode: Mode) -> Result<Program, AsmError> {
+    let mut words = Vec::new();
+    let mut listing = Vec::new();
+    let open = || -> std::io::Result<SourceInput> { Ok(Box::new(source.as_bytes())) };
+    let assembly = assemble_stream(
+        open,
+        file,
+        mode,
+        |word| {
+            words.push(word);
+            Ok(())
+        },
+        Some(&mut listing),
+    )?;
+    Ok(Program {
+        words,
+        symbols: assembly.symbols,
+        warnings: assembly.warnings,
+        listing: String::from_utf8(listing).unwrap(),
+    })
+}
+
+/// Assemble asm source text to ROM words
+pub fn assemble_source(source: &str) -> Result<Vec<u16>, AsmError> {
+    Ok(assemble(source, "<source>", Mode::Standard)?.words)
+}
+
+/// Assemble an asm file to ROM words
+pub fn assemble_file(path: &Path) -> Result<Vec<u16>, AsmError> {
+    let file = path.display().to_string();
+    let source = std::fs::read_to_string(path).map_err(|e| AsmError::Io {
+        file: file.clone(),
+        source: e,
+    })?;
+    Ok(assemble(&source, &file, Mode::Standard)?.words)
+}
+
+/// Files included by the asm file, directly or through other included files, in the order they are read.
+/// Lines with errors are skipped so the includes after them are still found,
+/// and includes that can't be opened are listed too since they may be created later
+pub fn included_files(path: &Path, mode: Mode) -> Vec<PathBuf> {
+    let input: SourceInput = match File::open(path) {
+        Ok(file) => Box::new(BufReader::new(file)),
+        Err(_) => return Vec::new(),
+    };
+    let mut reader = SourceReader::new(input, &path.display().to_string(), mode);
+    for _line in reader.by_ref() {}
+    reader.included
+}
+
+/// Convert a word to a line of .hack text
+pub fn to_binary_text(word: u16) -> String {
+    format!("{:016b}\n", word)
+}
+
+/// Generate a symbol file with the labels and variables.
+/// Each line is "<kind> <address> <name>" where kind is "label" or "variable",
+/// sorted by kind, address, and name so the output is stable
+pub fn generate_symbol_file(symbols: &Symbols) -> String {
+    let mut entries: Vec<(&str, u16, &str)> = symbols
+        .labels
+        .iter()
+        .map(|(name, address)| ("label", *address, name.as_str()))
+        .chain(
+            symbols
+                .variables
+                .iter()
+                .map(|(name, address)| ("variable", *address, name.as_str())),
+        )
+        .collect();
+    entries.sort_unstable();
+    entries
+        .iter()
+        .map(|(kind, address, name)| format!("{} {} {}\n", kind, address, name))
+        .collect()
+}
@@ -1,371 +1,358 @@
 use clap::{AppSettings, Clap};
-use std::collections::HashMap;
+use hackasm::{AsmError, Mode, SourceInput};
+use hackrom::Format;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Seek, Write};
+use std::io::{BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 #[derive(Clap)]
 #[clap(version = "1.0", author = "Masato Nakasaka <rillomas@gmail.com>")]
 #[clap(setting = AppSettings::ColoredHelp)]
 struct Opts {
+    /// Input files or directories.
+    /// All .asm files (.hack files with --disassemble) in a directory are used,
+    /// and multiple inputs are processed in parallel
+    #[clap(short, required = true)]
+    input_file: Vec<String>,
+    /// Output file. Use "-" to write to stdout.
+    /// Defaults to the input path with the extension of the output format
     #[clap(short)]
-    input_file: String,
+    output_file: Option<String>,
+    /// Output format. "text" for .hack text, "bin" for raw 16 bit big endian words (.bin)
+    #[clap(long, default_value = "text", possible_values = &["text", "bin"])]
+    format: String,
+    /// Also write a listing (.lst) with ROM address, encoding, and source of each line
+    #[clap(long)]
+    listing: bool,
+    /// Write the labels and variables with their addresses to a symbol file.
+    /// Only available with a single input
+    #[clap(long)]
+    emit_symbols: Option<String>,
+    /// Only accept the official Hack assembly language without assembler extensions
+    #[clap(long, conflicts_with = "extended")]
+    strict: bool,
+    /// Also accept common variant mnemonics like "M+D", "DM=", or "D; JLE"
+    #[clap(long)]
+    extended: bool,
+    /// Disassemble a .hack file to <name>.dis.asm instead of assembling
+    #[clap(long)]
+    disassemble: bool,
+    /// Fail when there are warnings (unused labels or variables written but never read)
+    #[clap(long)]
+    deny_warnings: bool,
+    /// Keep watching the input and the files it includes, and run again whenever they change
+    #[clap(long)]
+    watch: bool,
 }
 
-/// Type of line from asm code
-#[derive(Debug)]
-enum LineType {
-    Blank,
-    AInstruction,
-    CInstruction,
-    Label,
-}
+/// Output path that writes to stdout
+const STDOUT_PATH: &str = "-";
 
-#[derive(Debug)]
-struct CInstruction {
-    comp: String,
-    dest: Option<String>,
-    jump: Option<String>,
-}
+/// Interval to check the input for changes in watch mode
+const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
 
-#[derive(Debug)]
-struct AInstruction {
-    value: u16,
+/// Print the error and stop assembling
+fn exit_with_error(error: AsmError) -> ! {
+    eprintln!("{}", error);
+    std::process::exit(1);
 }
 
-type SymbolTable = HashMap<String, u16>;
-const A_INSTRUCTION_SYMBOL: char = '@';
-const COMMENT_SYMBOL: &str = "//";
-const LEFT_LABEL_SYMBOL: char = '(';
-const RIGHT_LABEL_SYMBOL: char = ')';
-const PREDEFINED_SYMBOL: [(&str, u16); 23] = [
-    ("SP", 0),
-    ("LCL", 1),
-    ("ARG", 2),
-    ("THIS", 3),
-    ("THAT", 4),
-    ("R0", 0),
-    ("R1", 1),
-    ("R2", 2),
-    ("R3", 3),
-    ("R4", 4),
-    ("R5", 5),
-    ("R6", 6),
-    ("R7", 7),
-    ("R8", 8),
-    ("R9", 9),
-    ("R10", 10),
-    ("R11", 11),
-    ("R12", 12),
-    ("R13", 13),
-    ("R14", 14),
-    ("R15", 15),
-    ("SCREEN", 0x4000),
-    ("KBD", 0x6000),
-];
-
-trait Instruction {
-    /// Convert instruction to binary text (hack format)
-    fn to_binary_text(&self) -> Result<String, &'static str>;
+/// Wrap an IO error on a file
+fn io_error(path: &Path) -> impl Fn(std::io::Error) -> AsmError + '_ {
+    move |e| AsmError::Io {
+        file: path.display().to_string(),
+        source: e,
+    }
 }
 
-impl Instruction for CInstruction {
-    fn to_binary_text(&self) -> Result<String, &'static str> {
-        let mut output = String::from("111");
-        match self.comp.as_str() {
-            "0" => output.push_str("0101010"),
-            "1" => output.push_str("0111111"),
-            "-1" => output.push_str("0111010"),
-            "D" => output.push_str("0001100"),
-            "A" => output.push_str("0110000"),
-            "M" => output.push_str("1110000"),
-            "!D" => output.push_str("0001101"),
-            "!A" => output.push_str("0110001"),
-            "!M" => output.push_str("1110001"),
-            "-D" => output.push_str("0001111"),
-            "-A" => output.push_str("0110011"),
-            "-M" => output.push_str("1110011"),
-            "D+1" => output.push_str("0011111"),
-            "A+1" => output.push_str("0110111"),
-            "M+1" => output.push_str("1110111"),
-            "D-1" => output.push_str("0001110"),
-            "A-1" => output.push_str("0110010"),
-            "M-1" => output.push_str("1110010"),
-            "D+A" => output.push_str("0000010"),
-            "D+M" => output.push_str("1000010"),
-            "D-A" => output.push_str("0010011"),
-            "D-M" => output.push_str("1010011"),
-            "A-D" => output.push_str("0000111"),
-            "M-D" => output.push_str("1000111"),
-            "D&A" => output.push_str("0000000"),
-            "D&M" => output.push_str("1000000"),
-            "D|A" => output.push_str("0010101"),
-            "D|M" => output.push_str("1010101"),
-            _ => return Err("Unknown comp"),
+impl Opts {
+    /// Get the output path, or the input path with the extension if not specified
+    fn output_path(&self, input_file_path: &Path, extension: &str) -> PathBuf {
+        match &self.output_file {
+            Some(path) => PathBuf::from(path),
+            None => input_file_path.with_extension(extension),
         }
-        match self.dest.as_deref() {
-            None => output.push_str("000"),
-            Some("M") => output.push_str("001"),
-            Some("D") => output.push_str("010"),
-            Some("MD") => output.push_str("011"),
-            Some("A") => output.push_str("100"),
-            Some("AM") => output.push_str("101"),
-            Some("AD") => output.push_str("110"),
-            Some("AMD") => output.push_str("111"),
-            _ => return Err("Unknown dest"),
-        }
-        match self.jump.as_deref() {
-            None => output.push_str("000\n"),
-            Some("JGT") => output.push_str("001\n"),
-            Some("JEQ") => output.push_str("010\n"),
-            Some("JGE") => output.push_str("011\n"),
-            Some("JLT") => output.push_str("100\n"),
-            Some("JNE") => output.push_str("101\n"),
-            Some("JLE") => output.push_str("110\n"),
-            Some("JMP") => output.push_str("111\n"),
-            _ => return Err("Unknown jump"),
+    }
+
+    /// Instruction set selected by --strict and --extended
+    fn mode(&self) -> Mode {
+        if self.strict {
+            Mode::Strict
+        } else if self.extended {
+            Mode::Extended
+        } else {
+            Mode::Standard
         }
-        Ok(output)
     }
-}
 
-impl CInstruction {
-    fn new(line: &str) -> CInstruction {
-        let dest_delimiter = '=';
-        let jmp_delimiter = ';';
-        let dest_position = line.find(dest_delimiter);
-        let jmp_position = line.find(jmp_delimiter);
-        if dest_position == None {
-            if jmp_position == None {
-                // no dest, no jmp
-                CInstruction {
-                    comp: line.to_string(),
-                    dest: None,
-                    jump: None,
-                }
-            } else {
-                // no dest, has jmp
-                let comp_jmp: Vec<_> = line.split(jmp_delimiter).collect();
-                CInstruction {
-                    comp: comp_jmp[0].to_string(),
-                    dest: None,
-                    jump: Some(comp_jmp[1].to_string()),
-                }
-            }
+    /// Print progress.
+    /// Goes to stderr when the output is written to stdout so it does not mix with the output
+    fn report(&self, message: &str) {
+        if self.output_file.as_deref() == Some(STDOUT_PATH) {
+            eprintln!("{}", message);
         } else {
-            if jmp_position == None {
-                // has dest, no jmp
-                let dest_comp: Vec<_> = line.split(dest_delimiter).collect();
-                CInstruction {
-                    comp: dest_comp[1].to_string(),
-                    dest: Some(dest_comp[0].to_string()),
-                    jump: None,
-                }
-            } else {
-                // has both dest and jmp
-                let dest_comp_jmp: Vec<_> = line.split(dest_delimiter).collect();
-                let comp_jmp: Vec<_> = dest_comp_jmp[1].split(jmp_delimiter).collect();
-                CInstruction {
-                    comp: comp_jmp[0].to_string(),
-                    dest: Some(dest_comp_jmp[0].to_string()),
-                    jump: Some(comp_jmp[1].to_string()),
-                }
-            }
+            println!("{}", message);
         }
     }
 }
 
-impl Instruction for AInstruction {
-    fn to_binary_text(&self) -> Result<String, &'static str> {
-        Ok(format!("{:016b}\n", self.value))
+/// Open the output file, or stdout for STDOUT_PATH
+fn create_output(path: &Path) -> Result<Box<dyn Write>, AsmError> {
+    if path == Path::new(STDOUT_PATH) {
+        Ok(Box::new(std::io::stdout()))
+    } else {
+        Ok(Box::new(File::create(path).map_err(io_error(path))?))
     }
 }
 
-/// Get symbol from label line
-fn get_symbol_from_label(line: &str) -> &str {
-    let chars: &[_] = &[LEFT_LABEL_SYMBOL, RIGHT_LABEL_SYMBOL];
-    line.trim_matches(chars)
+/// Disassemble the input to <name>.dis.asm
+fn run_disassemble(opts: &Opts, input_file_path: &Path) -> Result<(), AsmError> {
+    let file_name = input_file_path.display().to_string();
+    let output_file_path = opts.output_path(input_file_path, "dis.asm");
+    opts.report(&format!("input: {}", input_file_path.display()));
+    opts.report(&format!("output: {}", output_file_path.display()));
+    let source = std::fs::read_to_string(input_file_path).map_err(io_error(input_file_path))?;
+    let asm = hackasm::disassemble(&source, &file_name)?;
+    let mut out_file = create_output(&output_file_path)?;
+    out_file
+        .write_all(asm.as_bytes())
+        .and_then(|_| out_file.flush())
+        .map_err(AsmError::Output)
 }
 
-/// Get symbol from A instruction line
-fn get_symbol_from_a_instruction(line: &str) -> Option<&str> {
-    let splitten: Vec<_> = line.split(A_INSTRUCTION_SYMBOL).collect();
-    let address_or_symbol = splitten[1];
-    let maybe_address = str::parse::<u16>(address_or_symbol);
-    if maybe_address.is_ok() {
-        // found direct address so we don't have any symbols
-        None
+/// Assemble the input and write all requested outputs
+fn run_assemble(opts: &Opts, input_file_path: &Path) -> Result<(), AsmError> {
+    let file_name = input_file_path.display().to_string();
+    let binary_output = opts.format == "bin";
+    let output_file_path =
+        opts.output_path(input_file_path, if binary_output { "bin" } else { "hack" });
+    opts.report(&format!("input: {}", input_file_path.display()));
+    opts.report(&format!("output: {}", output_file_path.display()));
+    let mode = opts.mode();
+    // Each pass reopens the input so large sources are never read into memory at once
+    let open = || -> std::io::Result<SourceInput> {
+        Ok(Box::new(BufReader::new(File::open(input_file_path)?)))
+    };
+    let mut out_file = BufWriter::new(create_output(&output_file_path)?);
+    let listing_file_path = input_file_path.with_extension("lst");
+    let mut listing_file = if opts.listing {
+        opts.report(&format!("listing: {}", listing_file_path.display()));
+        Some(BufWriter::new(
+            File::create(&listing_file_path).map_err(io_error(&listing_file_path))?,
+        ))
     } else {
-        // Return symbol as string
-        Some(address_or_symbol)
+        None
+    };
+    let result = hackasm::assemble_stream(
+        open,
+        &file_name,
+        mode,
+        |word| {
+            if binary_output {
+                out_file.write_all(&hackrom::serialize(&[word], Format::Binary))
+            } else {
+                out_file.write_all(hackasm::to_binary_text(word).as_bytes())
+            }
+        },
+        listing_file.as_mut().map(|w| w as &mut dyn Write),
+    );
+    // Warnings fail the assembly with --deny-warnings
+    let result = result.and_then(|assembly| {
+        for warning in &assembly.warnings {
+            eprintln!("{}: warning: {}", file_name, warning);
+        }
+        match assembly.warnings.len() {
+            count if count > 0 && opts.deny_warnings => Err(AsmError::DeniedWarnings { count }),
+            _ => Ok(assembly.symbols),
+        }
+    });
+    let symbols = match result {
+        Ok(symbols) => symbols,
+        Err(e) => {
+            // Do not leave partially written output behind.
+            // Buffered output is discarded without flushing
+            let _ = out_file.into_parts();
+            drop(listing_file);
+            if output_file_path != Path::new(STDOUT_PATH) {
+                let _ = std::fs::remove_file(&output_file_path);
+            }
+            if opts.listing {
+                let _ = std::fs::remove_file(&listing_file_path);
+            }
+            return Err(e);
+        }
+    };
+    out_file.flush().map_err(AsmError::Output)?;
+    if let Some(mut listing_file) = listing_file {
+        listing_file.flush().map_err(AsmError::Output)?;
+    }
+    if let Some(symbol_file_path) = &opts.emit_symbols {
+        opts.report(&format!("symbols: {}", symbol_file_path));
+        std::fs::write(symbol_file_path, hackasm::generate_symbol_file(&symbols))
+            .map_err(io_error(Path::new(symbol_file_path)))?;
     }
+    // Report where each variable was allocated
+    opts.report(&format!("variables: {}", symbols.variables.len()));
+    for (name, address) in symbols.variables {
+        opts.report(&format!("  {}: {}", name, address));
+    }
+    Ok(())
 }
 
-impl AInstruction {
-    fn new(line: &str, symbol_table: &SymbolTable) -> AInstruction {
-        let splitten: Vec<_> = line.split(A_INSTRUCTION_SYMBOL).collect();
-        let address_or_symbol = splitten[1];
-        let maybe_address = str::parse::<u16>(address_or_symbol);
-        if maybe_address.is_ok() {
-            // A instruction is direct address
-            let value = maybe_address.unwrap();
-            AInstruction { value: value }
-        } else {
-            // A instruction is a symbol
-            // Lookup table to get address
-            let address = symbol_table.get(address_or_symbol).unwrap();
-            AInstruction { value: *address }
-        }
+/// Run a single input
+fn run_one(opts: &Opts, input_file_path: &Path) -> Result<(), AsmError> {
+    if opts.disassemble {
+        run_disassemble(opts, input_file_path)
+    } else {
+        run_assemble(opts, input_file_path)
     }
 }
 
-fn remove_comment(line: &str) -> &str {
-    match line.find(COMMENT_SYMBOL) {
-        Some(pos) => {
-            // create substr based on comment position
-            let (first, _last) = line.split_at(pos);
-            first
-        }
-        // No comment so we just use the original line
-        None => line,
-    }
+/// Run each input on worker threads.
+/// Returns the result of each input in the order of inputs
+fn run_parallel(opts: &Opts, inputs: &[PathBuf]) -> Vec<Result<(), AsmError>> {
+    let workers = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(inputs.len());
+    let next_input = AtomicUsize::new(0);
+    let mut results: Vec<(usize, Result<(), AsmError>)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut worker_results = Vec::new();
+                    loop {
+                        let idx = next_input.fetch_add(1, Ordering::Relaxed);
+                        match inputs.get(idx) {
+                            Some(input) => worker_results.push((idx, run_one(opts, input))),
+                            None => return worker_results,
+                        }
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    });
+    results.sort_by_key(|(idx, _)| *idx);
+    results.into_iter().map(|(_, result)| result).collect()
 }
 
-fn parse_line(
-    line: &str,
-    symbol_table: &SymbolTable,
-    instruction_output: &mut Vec<Box<dyn Instruction>>,
-) -> Result<LineType, &'static str> {
-    let mut code = remove_comment(line);
-    code = code.trim();
-    if code.is_empty() {
-        // is comment line
-        return Ok(LineType::Blank);
+/// Run all inputs and print errors.
+/// Multiple inputs also print a summary. Returns true if all inputs succeeded
+fn run(opts: &Opts, inputs: &[PathBuf]) -> bool {
+    if let [input] = inputs {
+        return match run_one(opts, input) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("{}", e);
+                false
+            }
+        };
     }
-    let first_char = code.chars().nth(0);
-    match first_char {
-        Some(A_INSTRUCTION_SYMBOL) => {
-            let ainst = AInstruction::new(code, symbol_table);
-            // println!("{:?}", ainst);
-            instruction_output.push(Box::new(ainst));
-            Ok(LineType::AInstruction)
-        }
-        Some('(') => Ok(LineType::Label),
-        _ => {
-            let cinst = CInstruction::new(code);
-            // println!("{:?}", cinst);
-            instruction_output.push(Box::new(cinst));
-            Ok(LineType::CInstruction)
-        }
+    let results = run_parallel(opts, inputs);
+    let failures: Vec<(&PathBuf, &AsmError)> = inputs
+        .iter()
+        .zip(&results)
+        .filter_map(|(input, result)| result.as_ref().err().map(|e| (input, e)))
+        .collect();
+    opts.report(&format!(
+        "{} succeeded, {} failed",
+        inputs.len() - failures.len(),
+        failures.len()
+    ));
+    for (input, error) in &failures {
+        eprintln!("failed: {}\n{}", input.display(), error);
     }
+    failures.is_empty()
 }
 
-fn scan_label_symbol(line: &str, symbol_table: &mut SymbolTable, current_address: u16) -> LineType {
-    let mut code = remove_comment(line);
-    code = code.trim();
-    if code.is_empty() {
-        // is comment line
-        return LineType::Blank;
-    }
-    // println!("{}", code);
-    let first_char = code.chars().nth(0);
-    match first_char {
-        Some(A_INSTRUCTION_SYMBOL) => LineType::AInstruction, // Nothing to do for A instructions
-        Some(LEFT_LABEL_SYMBOL) => {
-            // for label lines we get address for the next line and store it to the symbol table
-            let symbol = get_symbol_from_label(code);
-            symbol_table.insert(symbol.to_string(), current_address);
-            LineType::Label
+/// Expand directories to the files to process in them
+fn collect_inputs(opts: &Opts) -> Result<Vec<PathBuf>, AsmError> {
+    let extension = if opts.disassemble { "hack" } else { "asm" };
+    let mut inputs = Vec::new();
+    for input in &opts.input_file {
+        let path = Path::new(input);
+        if !path.is_dir() {
+            inputs.push(path.to_owned());
+            continue;
         }
-        _ => LineType::CInstruction, // Nothing to do for C instructions
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(path).map_err(io_error(path))? {
+            let file = entry.map_err(io_error(path))?.path();
+            if file.is_file() && file.extension().is_some_and(|e| e == extension) {
+                files.push(file);
+            }
+        }
+        files.sort();
+        inputs.append(&mut files);
     }
+    Ok(inputs)
 }
 
-fn scan_variable_symbol(
-    line: &str,
-    symbol_table: &mut SymbolTable,
-    variable_address: &mut u16,
-) -> LineType {
-    let mut code = remove_comment(line);
-    code = code.trim();
-    if code.is_empty() {
-        // is comment line
-        return LineType::Blank;
-    }
-    // println!("{}", code);
-    let first_char = code.chars().nth(0);
-    match first_char {
-        Some(A_INSTRUCTION_SYMBOL) => {
-            let maybe_symbol = get_symbol_from_a_instruction(code);
-            // println!("{:?}", maybe_symbol);
-            match maybe_symbol {
-                Some(symbol) => {
-                    // If symbol is new we assign a new address
-                    if !symbol_table.contains_key(symbol) {
-                        symbol_table.insert(symbol.to_string(), *variable_address);
-                        *variable_address += 1;
-                    }
-                    LineType::AInstruction
+/// Inputs with the files they include, which are all checked for changes in watch mode
+fn watched_files(opts: &Opts, inputs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = inputs.to_vec();
+    if !opts.disassemble {
+        for input in inputs {
+            for file in hackasm::included_files(input, opts.mode()) {
+                if !files.contains(&file) {
+                    files.push(file);
                 }
-                None => LineType::AInstruction, // Direct address specified. Ignore and go next
             }
         }
-        Some(LEFT_LABEL_SYMBOL) => LineType::Label, // Nothing to do for Labels
-        _ => LineType::CInstruction,                // Nothing to do for C instructions
     }
+    files
 }
 
-/// Go through source code to init all symbol tables
-fn init_symbol_table(table: &mut SymbolTable, reader: &mut BufReader<std::fs::File>) {
-    let mut current_address = 0;
-    // We want to scan for labels first since A instructions can refer to labels that come later.
-    // In such case we cannot distinguish if a symbol is a label or variable, so we scan for labels first to determine variable symbols
-    for line in reader.lines() {
-        let line_type = scan_label_symbol(&line.unwrap(), table, current_address);
-        match line_type {
-            // Count up address only for valid instructions
-            LineType::AInstruction | LineType::CInstruction => current_address += 1,
-            _ => {}
+/// Run again each time an input or a file it includes is modified.
+/// Included files are found again after each run so added or removed includes are followed.
+/// Errors are printed and watching continues until the process is stopped
+fn watch(opts: &Opts, inputs: &[PathBuf]) -> ! {
+    let modified_times = |files: &[PathBuf]| -> Vec<Option<std::time::SystemTime>> {
+        files
+            .iter()
+            .map(|file| std::fs::metadata(file).and_then(|m| m.modified()).ok())
+            .collect()
+    };
+    let mut files = watched_files(opts, inputs);
+    let mut last_modified = Vec::new();
+    loop {
+        let modified = modified_times(&files);
+        if modified != last_modified {
+            files = watched_files(opts, inputs);
+            last_modified = modified_times(&files);
+            if run(opts, inputs) {
+                opts.report("done. watching for changes...");
+            } else {
+                eprintln!("watching for changes...");
+            }
         }
-    }
-    // Reset file to beginning and scan for variables
-    reader.seek(std::io::SeekFrom::Start(0)).unwrap();
-    let mut variable_address = 16; // variable allocation starts from 16
-    for line in reader.lines() {
-        let _line_type = scan_variable_symbol(&line.unwrap(), table, &mut variable_address);
+        std::thread::sleep(WATCH_INTERVAL);
     }
 }
 
-fn main() -> std::io::Result<()> {
+fn main() {
     let opts = Opts::parse();
-    let input_file_path = Path::new(&opts.input_file);
-    let mut output_file_path = PathBuf::from(input_file_path);
-    output_file_path.set_extension("hack");
-    println!("input: {}", input_file_path.display());
-    println!("output: {}", output_file_path.display());
-    let file = File::open(input_file_path)?;
-    let mut reader = BufReader::new(file);
-    let mut instructions = vec![];
-    let mut symbol_table: SymbolTable = PREDEFINED_SYMBOL
-        .iter()
-        .cloned()
-        .map(|(k, v)| (k.to_string(), v))
-        .collect();
-    init_symbol_table(&mut symbol_table, &mut reader);
-    // println!("{:?}", symbol_table);
-    // reset file to beginning
-    reader.seek(std::io::SeekFrom::Start(0))?;
-    for line in reader.lines() {
-        let line_text = line.unwrap();
-        let _line_type = parse_line(&line_text, &symbol_table, &mut instructions).unwrap();
-        // println!("{:?}: {}", _line_type, line_text);
+    let inputs = collect_inputs(&opts).unwrap_or_else(|e| exit_with_error(e));
+    if inputs.is_empty() {
+        eprintln!("no input files found");
+        std::process::exit(1);
     }
-    let mut out_file = File::create(output_file_path)?;
-    for inst in instructions {
-        let written = out_file
-            .write(inst.to_binary_text().unwrap().as_bytes())
-            .unwrap();
-        assert_eq!(written, 17); // 16 chars + new line
+    if inputs.len() > 1 && opts.output_file.is_some() {
+        eprintln!("-o cannot be used with multiple inputs");
+        std::process::exit(1);
+    }
+    if inputs.len() > 1 && opts.emit_symbols.is_some() {
+        eprintln!("--emit-symbols cannot be used with multiple inputs");
+        std::process::exit(1);
+    }
+    if opts.watch {
+        watch(&opts, &inputs);
+    }
+    if !run(&opts, &inputs) {
+        std::process::exit(1);
     }
-    Ok(())
 }
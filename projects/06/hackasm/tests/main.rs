@@ -0,0 +1,334 @@
+use hackasm::{
+    assemble, assemble_file, assemble_source, disassemble, included_files, AsmError, Mode,
+};
+use std::path::PathBuf;
+
+const ADD_ASM: &str = "// Computes R0 = 2 + 3
+@2
+D=A
+@3
+D=D+A
+@0
+M=D
+";
+
+#[test]
+fn test_assemble_add() {
+    let words = assemble_source(ADD_ASM).unwrap();
+    assert_eq!(words, vec![0x0002, 0xec10, 0x0003, 0xe090, 0x0000, 0xe308]);
+}
+
+#[test]
+fn test_assemble_symbols() {
+    let source = "(LOOP)
+@counter
+M=M+1
+@LOOP
+0;JMP
+@SCREEN+32
+";
+    let words = assemble_source(source).unwrap();
+    // variables start from 16 and labels point to the next instruction
+    assert_eq!(words[0], 16);
+    assert_eq!(words[2], 0);
+    assert_eq!(words[4], 0x4020);
+}
+
+#[test]
+fn test_assemble_error() {
+    match assemble_source("@2\nD=X\n") {
+        Err(AsmError::UnknownComp { line, text, .. }) => {
+            assert_eq!(line, 2);
+            assert_eq!(text, "X");
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn test_disassemble_round_trip() {
+    let words = assemble_source(ADD_ASM).unwrap();
+    let hack: String = words.iter().map(|w| hackasm::to_binary_text(*w)).collect();
+    let asm = disassemble(&hack, "<source>").unwrap();
+    assert_eq!(assemble_source(&asm).unwrap(), words);
+}
+
+#[test]
+fn test_constant_range() {
+    assert_eq!(assemble_source("@32767\n").unwrap(), vec![0x7fff]);
+    match assemble_source("@32768\n") {
+        Err(AsmError::ConstantOutOfRange { value, .. }) => assert_eq!(value, 32768),
+        other => panic!("unexpected result {:?}", other),
+    }
+    let too_large = "D=A\n".repeat(32769);
+    assert!(matches!(
+        assemble_source(&too_large),
+        Err(AsmError::RomOverflow { line: 32769, .. })
+    ));
+    assert_eq!(assemble_source("D=A\n@32768\n").unwrap_err().line(), Some(2));
+}
+
+#[test]
+fn test_modes() {
+    let variant = "DM=M+D\nD; JLE\n";
+    assert!(assemble(variant, "<source>", Mode::Standard).is_err());
+    let official = assemble("MD=D+M\nD;JLE\n", "<source>", Mode::Strict).unwrap();
+    let extended = assemble(variant, "<source>", Mode::Extended).unwrap();
+    assert_eq!(official.words, extended.words);
+    assert!(matches!(
+        assemble("@SCREEN+1\n", "<source>", Mode::Strict),
+        Err(AsmError::NotStrict { line: 1, .. })
+    ));
+}
+
+/// Write the source files to a directory of the test and return its path.
+/// The path is canonical like the paths of included files in errors
+fn write_sources(test: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hackasm_{}_{}", test, std::process::id()));
+    for (name, text) in files {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, text).unwrap();
+    }
+    std::fs::canonicalize(dir).unwrap()
+}
+
+#[test]
+fn test_include() {
+    // included paths are relative to the including file
+    let dir = write_sources(
+        "include",
+        &[
+            ("main.asm", ".include \"lib/defs.asm\"\n@VALUE\nD=A\n"),
+            ("lib/defs.asm", ".include \"routine.asm\"\n"),
+            ("lib/routine.asm", "(VALUE)\n@VALUE\n0;JMP\n"),
+        ],
+    );
+    assert_eq!(
+        assemble_file(&dir.join("main.asm")).unwrap(),
+        vec![0x0000, 0xea87, 0x0000, 0xec10]
+    );
+    assert_eq!(
+        included_files(&dir.join("main.asm"), Mode::Standard),
+        [dir.join("lib/defs.asm"), dir.join("lib/routine.asm")]
+    );
+
+    let dir = write_sources(
+        "include_cycle",
+        &[
+            ("a.asm", "@1\n.include \"b.asm\"\n"),
+            ("b.asm", "D=A\n.include \"a.asm\"\n"),
+        ],
+    );
+    // the cycle is found in b.asm and reported with the chain of includes leading to it
+    let a = dir.join("a.asm").display().to_string();
+    let b = dir.join("b.asm").display().to_string();
+    match assemble_file(&dir.join("a.asm")) {
+        Err(AsmError::Included { error, chain }) => {
+            match *error {
+                AsmError::IncludeCycle { file, line, text } => {
+                    assert_eq!((file, line), (b.clone(), 2));
+                    assert_eq!(text, format!("{} -> {} -> {}", a, b, a));
+                }
+                other => panic!("unexpected error {:?}", other),
+            }
+            assert_eq!(chain, format!("\n  included from {}:2", a));
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    let dir = write_sources(
+        "include_missing",
+        &[("main.asm", "@1\n.include \"missing.asm\"\n")],
+    );
+    match assemble_file(&dir.join("main.asm")) {
+        Err(AsmError::Include { line, text, .. }) => {
+            assert_eq!(line, 2);
+            assert_eq!(text, dir.join("missing.asm").display().to_string());
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    // includes that can't be opened are listed so they can be watched for
+    assert_eq!(
+        included_files(&dir.join("main.asm"), Mode::Standard),
+        [dir.join("missing.asm")]
+    );
+}
+
+#[test]
+fn test_macros() {
+    let source = ".macro INC
+M=M+1
+.endmacro
+// macros may invoke macros defined before the invocation
+.macro INC2
+INC
+INC
+.endmacro
+@0
+INC2
+";
+    let program = assemble(source, "<source>", Mode::Standard).unwrap();
+    assert_eq!(program.words, vec![0x0000, 0xfdc8, 0xfdc8]);
+    // invocations are kept as comments in the listing
+    assert!(program
+        .listing
+        .contains("// INC2\n                         // INC\n    1  1111110111001000  M=M+1\n"));
+
+    let error = |source: &str| assemble(source, "<source>", Mode::Standard).unwrap_err();
+    // macros take no parameters
+    assert!(matches!(
+        error(".macro PUSH value\nD=A\n.endmacro\n"),
+        AsmError::InvalidMacro { line: 1, .. }
+    ));
+    match error(".macro INC\nM=M+1\n.endmacro\nINC 1\n") {
+        AsmError::InvalidMacro { line, text, .. } => {
+            assert_eq!((line, text.as_str()), (4, "INC 1"))
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    // a macro invoked before it is defined is not expanded
+    assert!(matches!(
+        error("INC\n.macro INC\nM=M+1\n.endmacro\n"),
+        AsmError::UnknownComp { line: 1, .. }
+    ));
+    match error(".macro INC\nM=M+1\n.endmacro\n.macro INC\nD=A\n.endmacro\n") {
+        AsmError::InvalidMacro { line, text, .. } => {
+            assert_eq!((line, text.as_str()), (4, ".macro INC"))
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    match error(".macro PING\nPONG\n.endmacro\n.macro PONG\nPING\n.endmacro\nPING\n") {
+        AsmError::RecursiveMacro { line, text, .. } => {
+            assert_eq!((line, text.as_str()), (5, "PING -> PONG -> PING"))
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    assert!(matches!(
+        error(".macro INC\nM=M+1\n"),
+        AsmError::UnterminatedMacro { line: 1, .. }
+    ));
+}
+
+#[test]
+fn test_org_word() {
+    let source = "@TABLE
+D=A
+.org 4
+(TABLE)
+.word 1, -1, TABLE+1
+.org 7
+.word 65535
+";
+    let program = assemble(source, "<source>", Mode::Standard).unwrap();
+    // the gap before .org is padded with zeros and labels point to the .org address
+    assert_eq!(
+        program.words,
+        vec![0x0004, 0xec10, 0x0000, 0x0000, 0x0001, 0xffff, 0x0005, 0xffff]
+    );
+    // .org to the current address adds no padding
+    assert!(program.listing.contains(
+        "    6  0000000000000101\n                         .org 7\n    7  1111111111111111  .word 65535\n"
+    ));
+
+    let error = |source: &str| assemble(source, "<source>", Mode::Standard).unwrap_err();
+    // .org can't move back over emitted words
+    match error("@1\nD=A\n.org 1\n") {
+        AsmError::OrgOverlap {
+            line,
+            address,
+            current,
+            ..
+        } => assert_eq!((line, address, current), (3, 1, 2)),
+        other => panic!("unexpected error {:?}", other),
+    }
+    match error(".org 5\n.org 3\n") {
+        AsmError::OrgOverlap {
+            line,
+            address,
+            current,
+            ..
+        } => assert_eq!((line, address, current), (2, 3, 5)),
+        other => panic!("unexpected error {:?}", other),
+    }
+    assert!(matches!(
+        error(".org 40000\n"),
+        AsmError::InvalidDirective { line: 1, .. }
+    ));
+    assert!(matches!(
+        error(".word\n"),
+        AsmError::InvalidDirective { line: 1, .. }
+    ));
+    assert!(matches!(
+        error(".word 1, 70000\n"),
+        AsmError::ConstantOutOfRange { value: 70000, .. }
+    ));
+}
+
+#[test]
+fn test_warnings_and_listing() {
+    let source = "(START)
+@count // written but never read
+M=1
+@total
+D=M
+(UNUSED)
+@START
+0;JMP
+(TABLE)
+.word TABLE, total+1
+";
+    let program = assemble(source, "<source>", Mode::Standard).unwrap();
+    let warnings: Vec<String> = program.warnings.iter().map(|w| w.to_string()).collect();
+    // labels in .word values count as referenced
+    assert_eq!(
+        warnings,
+        [
+            "label \"UNUSED\" at 4 is never referenced",
+            "variable \"count\" at 16 is never read"
+        ]
+    );
+    // address and encoding of each word next to its source line
+    assert_eq!(
+        program.listing,
+        "                         (START)
+    0  0000000000010000  @count // written but never read
+    1  1110111111001000  M=1
+    2  0000000000010001  @total
+    3  1111110000010000  D=M
+                         (UNUSED)
+    4  0000000000000000  @START
+    5  1110101010000111  0;JMP
+                         (TABLE)
+    6  0000000000000110  .word TABLE, total+1
+    7  0000000000010010
+"
+    );
+    assert!(assemble("@START\n(START)\n", "<source>", Mode::Standard)
+        .unwrap()
+        .warnings
+        .is_empty());
+    // only the address of buf is used, and its memory is never written directly
+    let address_only = "@buf\nD=A\n@ptr\nM=D\n@ptr\nA=M\nM=1\n";
+    assert!(assemble(address_only, "<source>", Mode::Standard)
+        .unwrap()
+        .warnings
+        .is_empty());
+    // symbols in expressions must be defined since they can't be allocated as variables
+    match assemble("@LIMIT-1\n", "<source>", Mode::Standard) {
+        Err(AsmError::UndefinedSymbol { line, text, .. }) => {
+            assert_eq!((line, text.as_str()), (1, "LIMIT"))
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[cfg(feature = "shift")]
+#[test]
+fn test_shift() {
+    let words = assemble_source("D=D<<\nM=M>>\n").unwrap();
+    assert_eq!(words, vec![0xac10, 0xb008]);
+    let hack: String = words.iter().map(|w| hackasm::to_binary_text(*w)).collect();
+    assert_eq!(disassemble(&hack, "<source>").unwrap(), "D=D<<\nM=M>>\n");
+    assert!(assemble("D=D<<\n", "<source>", Mode::Strict).is_err());
+}
@@ -0,0 +1,157 @@
+/// Width of a single hack instruction in bits
+const INSTRUCTION_WIDTH: usize = 16;
+/// Bit pattern marking a C instruction (the top three bits)
+const C_INSTRUCTION_PREFIX: u16 = 0b111 << 13;
+
+/// A single decoded hack instruction
+#[derive(Debug, Clone)]
+pub enum Instruction {
+    /// '@value'
+    A(u16),
+    /// 'dest=comp;jump'
+    C {
+        comp: &'static str,
+        dest: Option<&'static str>,
+        jump: Option<&'static str>,
+    },
+}
+
+impl Instruction {
+    /// Returns the A instruction value if this is an A instruction
+    pub fn value(&self) -> Option<u16> {
+        match self {
+            Instruction::A(v) => Some(*v),
+            Instruction::C { .. } => None,
+        }
+    }
+
+    /// Returns true if this is a C instruction jumping to the address held in A before execution.
+    /// Jumps that overwrite A (ex: 'A=M;JMP') go to a computed address instead.
+    pub fn is_direct_jump(&self) -> bool {
+        match self {
+            Instruction::C {
+                jump: Some(_),
+                dest,
+                ..
+            } => !matches!(dest, Some(d) if d.contains('A')),
+            _ => false,
+        }
+    }
+
+    /// Get asm text for this instruction
+    pub fn to_asm_text(&self) -> String {
+        match self {
+            Instruction::A(v) => format!("@{}", v),
+            Instruction::C { comp, dest, jump } => {
+                let mut text = String::new();
+                if let Some(d) = dest {
+                    text.push_str(d);
+                    text.push('=');
+                }
+                text.push_str(comp);
+                if let Some(j) = jump {
+                    text.push(';');
+                    text.push_str(j);
+                }
+                text
+            }
+        }
+    }
+}
+
+/// Decode the 7 bit 'a' + 'c' field of a C instruction
+fn decode_comp(bits: u16) -> Option<&'static str> {
+    let comp = match bits {
+        0b0101010 => "0",
+        0b0111111 => "1",
+        0b0111010 => "-1",
+        0b0001100 => "D",
+        0b0110000 => "A",
+        0b1110000 => "M",
+        0b0001101 => "!D",
+        0b0110001 => "!A",
+        0b1110001 => "!M",
+        0b0001111 => "-D",
+        0b0110011 => "-A",
+        0b1110011 => "-M",
+        0b0011111 => "D+1",
+        0b0110111 => "A+1",
+        0b1110111 => "M+1",
+        0b0001110 => "D-1",
+        0b0110010 => "A-1",
+        0b1110010 => "M-1",
+        0b0000010 => "D+A",
+        0b1000010 => "D+M",
+        0b0010011 => "D-A",
+        0b1010011 => "D-M",
+        0b0000111 => "A-D",
+        0b1000111 => "M-D",
+        0b0000000 => "D&A",
+        0b1000000 => "D&M",
+        0b0010101 => "D|A",
+        0b1010101 => "D|M",
+        _ => return None,
+    };
+    Some(comp)
+}
+
+fn decode_dest(bits: u16) -> Option<&'static str> {
+    match bits {
+        0b001 => Some("M"),
+        0b010 => Some("D"),
+        0b011 => Some("MD"),
+        0b100 => Some("A"),
+        0b101 => Some("AM"),
+        0b110 => Some("AD"),
+        0b111 => Some("AMD"),
+        _ => None,
+    }
+}
+
+fn decode_jump(bits: u16) -> Option<&'static str> {
+    match bits {
+        0b001 => Some("JGT"),
+        0b010 => Some("JEQ"),
+        0b011 => Some("JGE"),
+        0b100 => Some("JLT"),
+        0b101 => Some("JNE"),
+        0b110 => Some("JLE"),
+        0b111 => Some("JMP"),
+        _ => None,
+    }
+}
+
+/// Decode a single 16 bit word to an instruction
+pub fn decode(word: u16) -> Result<Instruction, String> {
+    if word & C_INSTRUCTION_PREFIX != C_INSTRUCTION_PREFIX {
+        // The top bit is 0 for A instructions.
+        // Words starting with 10 or 110 are not valid in the official spec
+        if word >> 15 == 0 {
+            return Ok(Instruction::A(word));
+        }
+        return Err(format!("Unknown instruction: {:016b}", word));
+    }
+    let comp_bits = (word >> 6) & 0b1111111;
+    let comp = match decode_comp(comp_bits) {
+        Some(c) => c,
+        None => return Err(format!("Unknown comp: {:07b}", comp_bits)),
+    };
+    Ok(Instruction::C {
+        comp,
+        dest: decode_dest((word >> 3) & 0b111),
+        jump: decode_jump(word & 0b111),
+    })
+}
+
+/// Parse a line of hack binary text ('0' and '1' characters) to a word
+pub fn parse_binary_text(line: &str) -> Result<u16, String> {
+    if line.len() != INSTRUCTION_WIDTH {
+        return Err(format!(
+            "Expected {} binary digits but got {}: {}",
+            INSTRUCTION_WIDTH,
+            line.len(),
+            line
+        ));
+    }
+    u16::from_str_radix(line, 2).map_err(|e| format!("{}: {}", e, line))
+}
@@ -0,0 +1,108 @@
+use clap::{AppSettings, Clap};
+use hackdis::decode::{self, Instruction};
+use hackdis::vm;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Clap)]
+#[clap(version = "1.0", author = "Masato Nakasaka <rillomas@gmail.com>")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    #[clap(short)]
+    input_file: String,
+    /// Only disassemble and skip reconstruction of VM commands
+    #[clap(long)]
+    no_vm: bool,
+}
+
+/// Suffix added to the output file name so we don't overwrite the original asm
+const OUTPUT_SUFFIX: &str = "dis";
+/// Column where the ROM address comments start
+const COMMENT_COLUMN: usize = 24;
+
+/// Collect all ROM addresses that are referred as jump targets.
+/// An A instruction followed by a jumping C instruction holds a ROM address.
+fn collect_address_refs(
+    instructions: &[Instruction],
+    commands: &[vm::VmCommand],
+) -> BTreeSet<usize> {
+    let mut refs = BTreeSet::new();
+    for (idx, pair) in instructions.windows(2).enumerate() {
+        if pair[0].value().is_some() && pair[1].is_direct_jump() {
+            refs.insert(idx);
+        }
+    }
+    for cmd in commands {
+        refs.extend(cmd.address_refs.iter());
+    }
+    refs
+}
+
+/// Generate annotated asm text for all instructions
+fn generate_asm(instructions: &[Instruction], commands: &[vm::VmCommand]) -> String {
+    let address_refs = collect_address_refs(instructions, commands);
+    let labels: BTreeSet<u16> = address_refs
+        .iter()
+        .filter_map(|idx| instructions[*idx].value())
+        .collect();
+    let command_at: HashMap<usize, &vm::VmCommand> =
+        commands.iter().map(|c| (c.start, c)).collect();
+    let mut output = String::new();
+    for (address, inst) in instructions.iter().enumerate() {
+        if let Some(cmd) = command_at.get(&address) {
+            output.push_str(&format!("// vm: {}\n", cmd.text));
+        }
+        if labels.contains(&(address as u16)) {
+            output.push_str(&format!("({})\n", vm::rom_label(address as u16)));
+        }
+        let text = match inst {
+            Instruction::A(value) if address_refs.contains(&address) => {
+                format!("@{}", vm::rom_label(*value))
+            }
+            _ => inst.to_asm_text(),
+        };
+        output.push_str(&format!(
+            "{:width$}// {}\n",
+            text,
+            address,
+            width = COMMENT_COLUMN
+        ));
+    }
+    // Labels may point right after the last instruction (ex: an infinite loop at the end)
+    for label in labels.range(instructions.len() as u16..) {
+        output.push_str(&format!("({})\n", vm::rom_label(*label)));
+    }
+    output
+}
+
+fn main() -> std::io::Result<()> {
+    let opts = Opts::parse();
+    let input_file_path = Path::new(&opts.input_file);
+    let mut output_file_path = PathBuf::from(input_file_path);
+    output_file_path.set_extension(format!("{}.asm", OUTPUT_SUFFIX));
+    println!("input: {}", input_file_path.display());
+    println!("output: {}", output_file_path.display());
+    let reader = BufReader::new(File::open(input_file_path)?);
+    let mut instructions = Vec::new();
+    for (idx, line) in reader.lines().enumerate() {
+        let line_text = line?;
+        let code = line_text.trim();
+        if code.is_empty() {
+            continue;
+        }
+        let inst = decode::parse_binary_text(code)
+            .and_then(decode::decode)
+            .unwrap_or_else(|e| panic!("{}:{}: {}", input_file_path.display(), idx + 1, e));
+        instructions.push(inst);
+    }
+    let commands = if opts.no_vm {
+        Vec::new()
+    } else {
+        vm::reconstruct(&instructions)
+    };
+    let mut out_file = File::create(output_file_path)?;
+    out_file.write_all(generate_asm(&instructions, &commands).as_bytes())?;
+    Ok(())
+}
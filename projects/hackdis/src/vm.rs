@@ -0,0 +1,300 @@
+use crate::decode::Instruction;
+
+/// Placeholder prefix for an A instruction value bound to a variable (ex: '@?0')
+const VALUE_PLACEHOLDER: &str = "@?";
+/// Placeholder prefix for an A instruction value bound to a variable that is a ROM address (ex: '@!0')
+const ADDRESS_PLACEHOLDER: &str = "@!";
+/// Number of variables a template can bind
+const MAX_VARIABLES: usize = 6;
+/// Number of words pushed by the calling convention besides the arguments
+/// (return address, LCL, ARG, THIS, THAT)
+const CALL_FRAME_SIZE: u16 = 5;
+
+/// Asm sequence emitted by the VM translator for a single VM command
+struct Template {
+    /// Instruction text of each line. A instructions may be placeholders.
+    asm: Vec<String>,
+    /// Generates the VM command text from the bound variables
+    describe: fn(&[u16]) -> String,
+}
+
+/// A run of instructions recognized as a single VM command
+#[derive(Debug)]
+pub struct VmCommand {
+    /// ROM address of the first instruction
+    pub start: usize,
+    /// Number of instructions used by the command
+    pub length: usize,
+    /// Reconstructed VM command text
+    pub text: String,
+    /// ROM address of instructions whose value points to another ROM address
+    pub address_refs: Vec<usize>,
+}
+
+/// Get label name for a ROM address
+pub fn rom_label(address: u16) -> String {
+    format!("ROM_{}", address)
+}
+
+fn to_strings(lines: &[&str]) -> Vec<String> {
+    lines.iter().map(|l| l.to_string()).collect()
+}
+
+/// Asm that pushes D on the global stack
+const PUSH_D: [&str; 5] = ["@0", "A=M", "M=D", "@0", "M=M+1"];
+
+fn push_template(head: &[&str], describe: fn(&[u16]) -> String) -> Template {
+    let mut asm = to_strings(head);
+    asm.extend(to_strings(&PUSH_D));
+    Template { asm, describe }
+}
+
+fn pop_template(base: &str, comp: &str, describe: fn(&[u16]) -> String) -> Template {
+    let asm = vec![
+        "@?0".to_string(),
+        "D=A".to_string(),
+        format!("@{}", base),
+        format!("D={}", comp),
+        "@?1".to_string(),
+        "M=D".to_string(),
+        "@0".to_string(),
+        "AM=M-1".to_string(),
+        "D=M".to_string(),
+        "@?1".to_string(),
+        "A=M".to_string(),
+        "M=D".to_string(),
+    ];
+    Template { asm, describe }
+}
+
+fn binary_template(comp: &str, describe: fn(&[u16]) -> String) -> Template {
+    let mut asm = to_strings(&["@0", "A=M", "A=A-1", "D=M", "A=A-1"]);
+    asm.push(format!("M={}", comp));
+    asm.extend(to_strings(&["D=A+1", "@0", "M=D"]));
+    Template { asm, describe }
+}
+
+/// gt and lt branch on the signs of x and y so x - y is only computed when it can't overflow
+fn signed_compare_template(jump: &str, describe: fn(&[u16]) -> String) -> Template {
+    // branch to !0 when y < 0, to !1 when only x < 0, and to !2 when the signs are the same
+    let mut asm = to_strings(&[
+        "@0", "AM=M-1", "D=M", "@!0", "D;JLT", "@0", "A=M-1", "D=M", "@!1", "D;JLT", "@!2", "0;JMP",
+    ]);
+    // !0: y < 0
+    asm.extend(to_strings(&["@0", "A=M-1", "D=M", "@!2", "D;JLT"]));
+    // !1: the signs differ so x with the lowest bit set decides
+    asm.extend(to_strings(&["@1", "D=D|A", "@!3", "0;JMP"]));
+    // !2: x - y
+    asm.extend(to_strings(&["@0", "A=M", "D=M", "A=A-1", "D=M-D"]));
+    // !3: D meets the condition at !4 (true), and the result is written at !5
+    asm.push("@!4".to_string());
+    asm.push(format!("D;{}", jump));
+    asm.extend(to_strings(&[
+        "D=0", "@!5", "0;JMP", "D=-1", "@0", "A=M-1", "M=D",
+    ]));
+    Template { asm, describe }
+}
+
+fn unary_template(comp: &str, describe: fn(&[u16]) -> String) -> Template {
+    let mut asm = to_strings(&["@0", "A=M", "A=A-1", "D=M"]);
+    asm.push(format!("M={}", comp));
+    asm.extend(to_strings(&["D=A+1", "@0", "M=D"]));
+    Template { asm, describe }
+}
+
+/// Templates matching the code generated by hacktrans with the default options
+/// (-O0, --codegen speed, and the standard memory layout).
+/// The round-trip tests check them against the output of the current translator
+fn templates() -> Vec<Template> {
+    let mut list = vec![
+        // bootstrap code setting up the stack pointer
+        Template {
+            asm: to_strings(&["@256", "D=A", "@0", "M=D"]),
+            describe: |_| String::from("bootstrap (SP=256)"),
+        },
+        push_template(&["@?0", "D=A"], |v| format!("push constant {}", v[0])),
+        push_template(&["@?0", "D=A", "@1", "A=D+M", "D=M"], |v| {
+            format!("push local {}", v[0])
+        }),
+        push_template(&["@?0", "D=A", "@2", "A=D+M", "D=M"], |v| {
+            format!("push argument {}", v[0])
+        }),
+        push_template(&["@?0", "D=A", "@3", "A=D+M", "D=M"], |v| {
+            format!("push this {}", v[0])
+        }),
+        push_template(&["@?0", "D=A", "@4", "A=D+M", "D=M"], |v| {
+            format!("push that {}", v[0])
+        }),
+        push_template(&["@?0", "D=A", "@5", "A=D+A", "D=M"], |v| {
+            format!("push temp {}", v[0])
+        }),
+        push_template(&["@?0", "D=A", "@3", "A=D+A", "D=M"], |v| {
+            format!("push pointer {}", v[0])
+        }),
+        push_template(&["@?0", "D=M"], |v| describe_static("push", v[0])),
+        pop_template("1", "D+M", |v| format!("pop local {}", v[0])),
+        pop_template("2", "D+M", |v| format!("pop argument {}", v[0])),
+        pop_template("3", "D+M", |v| format!("pop this {}", v[0])),
+        pop_template("4", "D+M", |v| format!("pop that {}", v[0])),
+        pop_template("5", "D+A", |v| format!("pop temp {}", v[0])),
+        pop_template("3", "D+A", |v| format!("pop pointer {}", v[0])),
+        Template {
+            asm: to_strings(&["@0", "AM=M-1", "D=M", "@?0", "M=D"]),
+            describe: |v| describe_static("pop", v[0]),
+        },
+        binary_template("D+M", |_| String::from("add")),
+        binary_template("M-D", |_| String::from("sub")),
+        binary_template("D&M", |_| String::from("and")),
+        binary_template("D|M", |_| String::from("or")),
+        unary_template("-M", |_| String::from("neg")),
+        unary_template("!M", |_| String::from("not")),
+        Template {
+            asm: to_strings(&[
+                "@0", "A=M", "A=A-1", "D=M", "A=A-1", "D=M-D", "@!0", "D;JEQ", "D=-1", "@0",
+                "A=M-1", "A=A-1", "M=!D", "D=A+1", "@0", "M=D",
+            ]),
+            describe: |_| String::from("eq"),
+        },
+        signed_compare_template("JLT", |_| String::from("lt")),
+        signed_compare_template("JGT", |_| String::from("gt")),
+        Template {
+            asm: to_strings(&["@0", "AM=M-1", "D=M", "@!0", "D;JNE"]),
+            describe: |v| format!("if-goto {}", rom_label(v[0])),
+        },
+        Template {
+            asm: to_strings(&["@!0", "0;JMP"]),
+            describe: |v| format!("goto {}", rom_label(v[0])),
+        },
+        Template {
+            asm: to_strings(&[
+                "@1", "D=M", "@5", "A=D-A", "D=M", "@?0", "M=D", "@0", "A=M-1", "D=M", "@2", "A=M",
+                "M=D", "D=A+1", "@0", "M=D", "@1", "A=M-1", "D=M", "@4", "M=D", "@1", "A=M-1",
+                "A=A-1", "D=M", "@3", "M=D", "@1", "D=M", "@3", "A=D-A", "D=M", "@2", "M=D", "@1",
+                "D=M", "@4", "A=D-A", "D=M", "@1", "M=D", "@?0", "A=M;JMP",
+            ]),
+            describe: |_| String::from("return"),
+        },
+    ];
+    // call saves the return address and the 4 segment pointers before jumping
+    let mut call = to_strings(&["@!0", "D=A", "@0", "A=M", "M=D", "D=A+1", "@0", "M=D"]);
+    for segment in 1..=4 {
+        call.push(format!("@{}", segment));
+        call.extend(to_strings(&[
+            "D=M", "@0", "A=M", "M=D", "D=A+1", "@0", "M=D",
+        ]));
+    }
+    call.extend(to_strings(&[
+        "@0", "D=M", "@?1", "D=D-A", "@2", "M=D", "@0", "D=M", "@1", "M=D", "@!2", "0;JMP",
+    ]));
+    list.push(Template {
+        asm: call,
+        describe: |v| {
+            format!(
+                "call {} {}",
+                rom_label(v[2]),
+                v[1].saturating_sub(CALL_FRAME_SIZE)
+            )
+        },
+    });
+    list
+}
+
+fn describe_static(command: &str, address: u16) -> String {
+    // The assembler allocates statics in order of appearance,
+    // so the original file and index can't be recovered. We only give the RAM address.
+    format!("{} static (RAM[{}])", command, address)
+}
+
+/// Try to match the template at the given position and return the VM command on success
+fn match_template(
+    template: &Template,
+    instructions: &[Instruction],
+    start: usize,
+) -> Option<VmCommand> {
+    if start + template.asm.len() > instructions.len() {
+        return None;
+    }
+    let mut bound: [Option<u16>; MAX_VARIABLES] = [None; MAX_VARIABLES];
+    let mut address_refs = Vec::new();
+    for (offset, expected) in template.asm.iter().enumerate() {
+        let inst = &instructions[start + offset];
+        let placeholder = if expected.starts_with(VALUE_PLACEHOLDER) {
+            Some(false)
+        } else if expected.starts_with(ADDRESS_PLACEHOLDER) {
+            Some(true)
+        } else {
+            None
+        };
+        match placeholder {
+            Some(is_address) => {
+                let value = inst.value()?;
+                let var: usize = expected[2..].parse().ok()?;
+                match bound[var] {
+                    Some(v) if v != value => return None,
+                    _ => bound[var] = Some(value),
+                }
+                if is_address {
+                    address_refs.push(start + offset);
+                }
+            }
+            None => {
+                if inst.to_asm_text() != *expected {
+                    return None;
+                }
+            }
+        }
+    }
+    let values: Vec<u16> = bound.iter().map(|b| b.unwrap_or(0)).collect();
+    Some(VmCommand {
+        start,
+        length: template.asm.len(),
+        text: (template.describe)(&values),
+        address_refs,
+    })
+}
+
+/// Match the variable length 'function f n' code which initializes n local variables
+fn match_function(instructions: &[Instruction], start: usize) -> Option<VmCommand> {
+    let text = |i: usize| instructions.get(i).map(|inst| inst.to_asm_text());
+    if text(start)? != "@0" || text(start + 1)? != "A=M" {
+        return None;
+    }
+    let mut idx = start + 2;
+    let mut local_num = 0;
+    while text(idx)? == "M=0" && text(idx + 1)? == "A=A+1" {
+        local_num += 1;
+        idx += 2;
+    }
+    if text(idx)? != "D=A" || text(idx + 1)? != "@0" || text(idx + 2)? != "M=D" {
+        return None;
+    }
+    Some(VmCommand {
+        start,
+        length: idx + 3 - start,
+        text: format!("function {} {}", rom_label(start as u16), local_num),
+        address_refs: Vec::new(),
+    })
+}
+
+/// Scan the instructions and reconstruct VM commands where known code patterns are found.
+/// Instructions that don't match any pattern are left out of the result.
+pub fn reconstruct(instructions: &[Instruction]) -> Vec<VmCommand> {
+    let templates = templates();
+    let mut commands = Vec::new();
+    let mut idx = 0;
+    while idx < instructions.len() {
+        let found = match_function(instructions, idx).or_else(|| {
+            templates
+                .iter()
+                .find_map(|t| match_template(t, instructions, idx))
+        });
+        match found {
+            Some(cmd) => {
+                idx += cmd.length;
+                commands.push(cmd);
+            }
+            None => idx += 1,
+        }
+    }
+    commands
+}
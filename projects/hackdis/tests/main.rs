@@ -0,0 +1,136 @@
+use hackdis::{decode, vm};
+use hacktrans::{Options, Translator};
+
+/// Translate VM code with hacktrans, assemble it, and reconstruct the VM commands from the machine code
+fn round_trip(vm_text: &str) -> Vec<String> {
+    let mut translator = Translator::new("Test", Options::default());
+    translator.add_source("Main", vm_text);
+    let words = translator
+        .translate_program()
+        .unwrap()
+        .assemble("Test.asm")
+        .unwrap();
+    let instructions: Vec<decode::Instruction> = words
+        .into_iter()
+        .map(|w| decode::decode(w).unwrap())
+        .collect();
+    vm::reconstruct(&instructions)
+        .into_iter()
+        .map(|c| c.text)
+        .collect()
+}
+
+#[test]
+fn test_compare_round_trip() {
+    let commands = round_trip(
+        "function Main.main 0
+push constant 1
+push constant 2
+gt
+push constant 3
+lt
+push constant 4
+eq
+return
+",
+    );
+    let compares: Vec<&str> = commands
+        .iter()
+        .map(|c| c.as_str())
+        .filter(|c| matches!(*c, "eq" | "gt" | "lt"))
+        .collect();
+    assert_eq!(compares, ["gt", "lt", "eq"]);
+}
+
+/// Replace ROM addresses, which change with the code size, with "ROM"
+fn without_addresses(command: &str) -> String {
+    command
+        .split(' ')
+        .map(|word| {
+            if word.starts_with("ROM_") {
+                "ROM"
+            } else {
+                word
+            }
+        })
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+#[test]
+fn test_round_trip() {
+    // every kind of command the translator generates with the default options
+    let commands = round_trip(
+        "function Sys.init 0
+call Main.main 0
+label END
+goto END
+function Main.main 2
+push constant 7
+pop local 1
+push local 1
+push argument 0
+pop argument 1
+push this 1
+pop that 2
+push that 0
+pop this 0
+push temp 3
+pop temp 4
+push pointer 1
+pop pointer 0
+push static 2
+pop static 1
+add
+sub
+and
+or
+neg
+not
+eq
+gt
+lt
+label LOOP
+if-goto LOOP
+return
+",
+    );
+    let commands: Vec<String> = commands.iter().map(|c| without_addresses(c)).collect();
+    assert_eq!(
+        commands,
+        [
+            "bootstrap (SP=256)",
+            "call ROM 0",
+            "function ROM 0",
+            "call ROM 0",
+            "goto ROM",
+            "function ROM 2",
+            "push constant 7",
+            "pop local 1",
+            "push local 1",
+            "push argument 0",
+            "pop argument 1",
+            "push this 1",
+            "pop that 2",
+            "push that 0",
+            "pop this 0",
+            "push temp 3",
+            "pop temp 4",
+            "push pointer 1",
+            "pop pointer 0",
+            "push static (RAM[16])",
+            "pop static (RAM[17])",
+            "add",
+            "sub",
+            "and",
+            "or",
+            "neg",
+            "not",
+            "eq",
+            "gt",
+            "lt",
+            "if-goto ROM",
+            "return",
+        ]
+    );
+}
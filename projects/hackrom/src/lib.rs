@@ -0,0 +1,178 @@
+use std::path::Path;
+
+/// Number of words in the hack ROM
+pub const ROM_SIZE: usize = 32768;
+/// Width of a single hack instruction in bits
+const INSTRUCTION_WIDTH: usize = 16;
+/// Magic bytes at the start of a raw binary image with a header
+pub const HEADER_MAGIC: &[u8; 4] = b"HROM";
+/// Version of the header layout
+const HEADER_VERSION: u16 = 1;
+
+/// File format of a ROM image
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    /// ASCII text with one 16 digit binary word per line (the .hack format)
+    Hack,
+    /// Raw big endian 16 bit words
+    Binary,
+    /// ASCII text with one 4 digit hex word per line
+    Hex,
+}
+
+impl Format {
+    /// Guess format from the file extension
+    pub fn from_path(path: &Path) -> Result<Format, String> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("hack") => Ok(Format::Hack),
+            Some("bin") => Ok(Format::Binary),
+            Some("hex") => Ok(Format::Hex),
+            _other => Err(format!("Unknown ROM format: {}", path.display())),
+        }
+    }
+}
+
+/// A block of words placed at a given ROM address
+#[derive(Debug)]
+pub struct Segment {
+    pub base: usize,
+    pub words: Vec<u16>,
+}
+
+/// Parse ASCII text where each non empty line is a single word in the given radix
+fn parse_text(data: &[u8], radix: u32, digits: usize) -> Result<Vec<u16>, String> {
+    let text = std::str::from_utf8(data).map_err(|e| e.to_string())?;
+    let mut words = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let code = line.trim();
+        if code.is_empty() {
+            continue;
+        }
+        if code.len() != digits {
+            return Err(format!(
+                "line {}: Expected {} digits but got {}: {}",
+                idx + 1,
+                digits,
+                code.len(),
+                code
+            ));
+        }
+        let word = u16::from_str_radix(code, radix)
+            .map_err(|e| format!("line {}: {}: {}", idx + 1, e, code))?;
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Length of the header in bytes for the given number of segments
+fn header_length(segment_num: usize) -> usize {
+    // magic, version, segment count, then base and length per segment
+    HEADER_MAGIC.len() + 4 + segment_num * 4
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, String> {
+    match data.get(offset..offset + 2) {
+        Some(b) => Ok(u16::from_be_bytes([b[0], b[1]])),
+        None => Err(String::from("Unexpected end of header")),
+    }
+}
+
+/// Parse raw binary words. The header is skipped if there is one.
+fn parse_binary(data: &[u8]) -> Result<Vec<u16>, String> {
+    let mut body = data;
+    if data.starts_with(HEADER_MAGIC) {
+        let version = read_u16(data, HEADER_MAGIC.len())?;
+        if version != HEADER_VERSION {
+            return Err(format!("Unsupported header version: {}", version));
+        }
+        let segment_num = read_u16(data, HEADER_MAGIC.len() + 2)? as usize;
+        let length = header_length(segment_num);
+        if data.len() < length {
+            return Err(String::from("Unexpected end of header"));
+        }
+        body = &data[length..];
+    }
+    let chunks = body.chunks_exact(2);
+    if !chunks.remainder().is_empty() {
+        return Err(format!(
+            "Odd number of bytes in binary image: {}",
+            body.len()
+        ));
+    }
+    Ok(chunks.map(|b| u16::from_be_bytes([b[0], b[1]])).collect())
+}
+
+/// Parse a ROM image of the given format to words
+pub fn parse(data: &[u8], format: Format) -> Result<Vec<u16>, String> {
+    let words = match format {
+        Format::Hack => parse_text(data, 2, INSTRUCTION_WIDTH)?,
+        Format::Hex => parse_text(data, 16, INSTRUCTION_WIDTH / 4)?,
+        Format::Binary => parse_binary(data)?,
+    };
+    if words.len() > ROM_SIZE {
+        return Err(format!(
+            "Image has {} words but ROM only has {}",
+            words.len(),
+            ROM_SIZE
+        ));
+    }
+    Ok(words)
+}
+
+/// Serialize words to a ROM image of the given format
+pub fn serialize(words: &[u16], format: Format) -> Vec<u8> {
+    match format {
+        Format::Hack => words
+            .iter()
+            .map(|w| format!("{:016b}\n", w))
+            .collect::<String>()
+            .into_bytes(),
+        Format::Hex => words
+            .iter()
+            .map(|w| format!("{:04X}\n", w))
+            .collect::<String>()
+            .into_bytes(),
+        Format::Binary => words.iter().flat_map(|w| w.to_be_bytes()).collect(),
+    }
+}
+
+/// Generate a header describing where each segment is placed in the image.
+/// The header is only supported for the binary format.
+pub fn generate_header(segments: &[Segment]) -> Vec<u8> {
+    let mut header = HEADER_MAGIC.to_vec();
+    header.extend(&HEADER_VERSION.to_be_bytes());
+    header.extend(&(segments.len() as u16).to_be_bytes());
+    for s in segments {
+        header.extend(&(s.base as u16).to_be_bytes());
+        header.extend(&(s.words.len() as u16).to_be_bytes());
+    }
+    header
+}
+
+/// Pack segments to a single image.
+/// Gaps between segments are filled with zeros.
+pub fn pack(segments: &[Segment]) -> Result<Vec<u16>, String> {
+    let mut image: Vec<u16> = Vec::new();
+    let mut used = vec![false; ROM_SIZE];
+    for s in segments {
+        let end = s.base + s.words.len();
+        if end > ROM_SIZE {
+            return Err(format!(
+                "Segment at {} with {} words exceeds ROM size",
+                s.base,
+                s.words.len()
+            ));
+        }
+        if let Some(addr) = (s.base..end).find(|a| used[*a]) {
+            return Err(format!("Segments overlap at address {}", addr));
+        }
+        for flag in &mut used[s.base..end] {
+            *flag = true;
+        }
+        if image.len() < end {
+            image.resize(end, 0);
+        }
+        image[s.base..end].copy_from_slice(&s.words);
+    }
+    Ok(image)
+}
@@ -0,0 +1,67 @@
+use clap::{AppSettings, Clap};
+use hackrom::{Format, Segment};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Clap)]
+#[clap(version = "1.0", author = "Masato Nakasaka <rillomas@gmail.com>")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    /// Input ROM images (.hack, .bin, or .hex).
+    /// Use '<file>@<address>' to place an image at a specific ROM address,
+    /// otherwise it is placed right after the previous image.
+    #[clap(short, required = true)]
+    input_file: Vec<String>,
+    /// Output ROM image. The format is selected from the extension (.hack, .bin, or .hex)
+    #[clap(short)]
+    output_file: String,
+    /// Write a header listing the segments at the start of the image (.bin only)
+    #[clap(long)]
+    header: bool,
+}
+
+/// Separator between the file name and the base address of a segment
+const ADDRESS_SEPARATOR: char = '@';
+
+/// Split '<file>@<address>' to the file path and an optional address
+fn parse_segment_spec(spec: &str) -> (&str, Option<usize>) {
+    if let Some(pos) = spec.rfind(ADDRESS_SEPARATOR) {
+        let (path, addr) = spec.split_at(pos);
+        if let Ok(base) = addr[1..].parse::<usize>() {
+            return (path, Some(base));
+        }
+    }
+    (spec, None)
+}
+
+fn main() -> std::io::Result<()> {
+    let opts = Opts::parse();
+    let output_path = Path::new(&opts.output_file);
+    let output_format = Format::from_path(output_path).unwrap_or_else(|e| panic!("{}", e));
+    if opts.header && output_format != Format::Binary {
+        panic!("Header is only supported for the .bin format");
+    }
+    let mut segments = Vec::new();
+    let mut next_base = 0;
+    for spec in &opts.input_file {
+        let (path_text, base) = parse_segment_spec(spec);
+        let path = Path::new(path_text);
+        println!("input: {}", path.display());
+        let format = Format::from_path(path).unwrap_or_else(|e| panic!("{}", e));
+        let data = std::fs::read(path)?;
+        let words =
+            hackrom::parse(&data, format).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        let base = base.unwrap_or(next_base);
+        next_base = base + words.len();
+        segments.push(Segment { base, words });
+    }
+    let image = hackrom::pack(&segments).unwrap_or_else(|e| panic!("{}", e));
+    println!("output: {}", output_path.display());
+    let mut out_file = File::create(output_path)?;
+    if opts.header {
+        out_file.write_all(&hackrom::generate_header(&segments))?;
+    }
+    out_file.write_all(&hackrom::serialize(&image, output_format))?;
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use hackrom::{generate_header, pack, parse, serialize, Format, Segment, HEADER_MAGIC, ROM_SIZE};
+
+const WORDS: [u16; 4] = [0x0002, 0xec10, 0x8000, 0xffff];
+
+#[test]
+fn test_round_trip() {
+    for format in [Format::Hack, Format::Hex, Format::Binary] {
+        let data = serialize(&WORDS, format);
+        assert_eq!(parse(&data, format).unwrap(), WORDS);
+    }
+    assert_eq!(
+        serialize(&WORDS[..2], Format::Hack),
+        b"0000000000000010\n1110110000010000\n"
+    );
+    assert_eq!(serialize(&WORDS[..2], Format::Hex), b"0002\nEC10\n");
+    assert_eq!(
+        serialize(&WORDS[..2], Format::Binary),
+        [0x00, 0x02, 0xec, 0x10]
+    );
+    // empty lines and surrounding spaces are ignored in text formats
+    assert_eq!(
+        parse(b"\n 0002 \r\n\nec10\n", Format::Hex).unwrap(),
+        [0x0002, 0xec10]
+    );
+}
+
+#[test]
+fn test_pack() {
+    let segments = [
+        Segment {
+            base: 4,
+            words: vec![3, 4],
+        },
+        Segment {
+            base: 0,
+            words: vec![1, 2],
+        },
+    ];
+    // gaps are filled with zeros and segments may be given in any order
+    assert_eq!(pack(&segments).unwrap(), [1, 2, 0, 0, 3, 4]);
+
+    let overlap = [
+        Segment {
+            base: 0,
+            words: vec![1, 2, 3],
+        },
+        Segment {
+            base: 2,
+            words: vec![4],
+        },
+    ];
+    assert_eq!(pack(&overlap).unwrap_err(), "Segments overlap at address 2");
+
+    let last = Segment {
+        base: ROM_SIZE - 1,
+        words: vec![1],
+    };
+    assert_eq!(pack(&[last]).unwrap().len(), ROM_SIZE);
+    let past_end = Segment {
+        base: ROM_SIZE - 1,
+        words: vec![1, 2],
+    };
+    assert_eq!(
+        pack(&[past_end]).unwrap_err(),
+        "Segment at 32767 with 2 words exceeds ROM size"
+    );
+}
+
+#[test]
+fn test_header() {
+    let segments = [
+        Segment {
+            base: 0,
+            words: vec![1, 2],
+        },
+        Segment {
+            base: 0x100,
+            words: vec![3],
+        },
+    ];
+    let header = generate_header(&segments);
+    // magic, version, segment count, then base and length of each segment
+    let mut expected = HEADER_MAGIC.to_vec();
+    expected.extend([0, 1, 0, 2, 0x00, 0x00, 0, 2, 0x01, 0x00, 0, 1]);
+    assert_eq!(header, expected);
+
+    // the header is skipped when reading the image
+    let words = pack(&segments).unwrap();
+    let mut image = header;
+    image.extend(serialize(&words, Format::Binary));
+    assert_eq!(parse(&image, Format::Binary).unwrap(), words);
+}
+
+#[test]
+fn test_errors() {
+    assert_eq!(
+        parse(&[0x00, 0x02, 0xec], Format::Binary).unwrap_err(),
+        "Odd number of bytes in binary image: 3"
+    );
+    let mut bad_version = HEADER_MAGIC.to_vec();
+    bad_version.extend([0, 2, 0, 0]);
+    assert_eq!(
+        parse(&bad_version, Format::Binary).unwrap_err(),
+        "Unsupported header version: 2"
+    );
+    let mut truncated = HEADER_MAGIC.to_vec();
+    truncated.extend([0, 1, 0, 1, 0, 0]);
+    assert_eq!(
+        parse(&truncated, Format::Binary).unwrap_err(),
+        "Unexpected end of header"
+    );
+    assert_eq!(
+        parse(b"0000000000000010\n111011000001000\n", Format::Hack).unwrap_err(),
+        "line 2: Expected 16 digits but got 15: 111011000001000"
+    );
+    assert_eq!(
+        parse(b"00002\n", Format::Hex).unwrap_err(),
+        "line 1: Expected 4 digits but got 5: 00002"
+    );
+    assert!(parse(b"0000000000000012\n", Format::Hack)
+        .unwrap_err()
+        .starts_with("line 1: "));
+    let too_large = serialize(&vec![0; ROM_SIZE + 1], Format::Binary);
+    assert_eq!(
+        parse(&too_large, Format::Binary).unwrap_err(),
+        "Image has 32769 words but ROM only has 32768"
+    );
+}
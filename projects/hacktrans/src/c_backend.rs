@@ -0,0 +1,404 @@
+use crate::command::{Command, Context};
+use std::collections::BTreeSet;
+
+/// Function called first when Sys.init is not defined by the program
+const MAIN_FUNCTION: &str = "Main.main";
+const SYS_INIT: &str = "Sys.init";
+
+/// OS functions implemented natively in the C runtime.
+/// These are used when the VM program doesn't define the function itself.
+pub const OS_SHIMS: [&str; 48] = [
+	"Math.abs",
+	"Math.multiply",
+	"Math.divide",
+	"Math.min",
+	"Math.max",
+	"Math.sqrt",
+	"Memory.peek",
+	"Memory.poke",
+	"Memory.alloc",
+	"Memory.deAlloc",
+	"Array.new",
+	"Array.dispose",
+	"String.new",
+	"String.dispose",
+	"String.length",
+	"String.charAt",
+	"String.setCharAt",
+	"String.appendChar",
+	"String.eraseLastChar",
+	"String.intValue",
+	"String.setInt",
+	"String.backSpace",
+	"String.doubleQuote",
+	"String.newLine",
+	"Output.init",
+	"Output.moveCursor",
+	"Output.printChar",
+	"Output.printString",
+	"Output.printInt",
+	"Output.println",
+	"Output.backSpace",
+	"Screen.init",
+	"Screen.clearScreen",
+	"Screen.setColor",
+	"Screen.drawPixel",
+	"Screen.drawLine",
+	"Screen.drawRectangle",
+	"Screen.drawCircle",
+	"Keyboard.init",
+	"Keyboard.keyPressed",
+	"Keyboard.readChar",
+	"Keyboard.readLine",
+	"Keyboard.readInt",
+	"Memory.init",
+	"Math.init",
+	"Sys.halt",
+	"Sys.error",
+	"Sys.wait",
+];
+
+/// Runtime shared by all generated programs.
+/// RAM follows the hack memory map so the VM segments behave the same as on the CPU emulator.
+/// Screen and keyboard are not emulated; output goes to stdout and input comes from stdin.
+const RUNTIME: &str = r#"#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* Only the OS functions the program calls are used */
+#pragma GCC diagnostic ignored "-Wunused-function"
+
+static int16_t RAM[32768];
+#define SP 0
+#define LCL 1
+#define ARG 2
+#define THIS 3
+#define THAT 4
+#define TEMP 5
+#define MEM(a) RAM[(uint16_t)(a) & 0x7FFF]
+#define PUSH(v) (RAM[(uint16_t)RAM[SP]] = (int16_t)(v), RAM[SP]++)
+#define POP() (RAM[(uint16_t)--RAM[SP]])
+#define BINARY(expr) do { int16_t y = POP(); int16_t x = POP(); PUSH(expr); } while (0)
+#define UNARY(expr) do { int16_t x = POP(); PUSH(expr); } while (0)
+#define CALL_NATIVE(f, n) do { int16_t r_ = f(&MEM(RAM[SP] - (n))); RAM[SP] -= (n); PUSH(r_); } while (0)
+
+/* Error codes of the Jack OS and their meaning */
+static const char *error_message(int16_t code) {
+	switch (code) {
+	case 1: return "Sys.wait: duration must be positive";
+	case 2: return "Array.new: array size must be positive";
+	case 3: return "Math.divide: division by zero";
+	case 4: return "Math.sqrt: cannot compute square root of a negative number";
+	case 5: return "Memory.alloc: allocated memory size must be positive";
+	case 6: return "Memory.alloc: heap overflow";
+	case 7: return "Screen.drawPixel: illegal pixel coordinates";
+	case 8: return "Screen.drawLine: illegal line coordinates";
+	case 9: return "Screen.drawRectangle: illegal rectangle coordinates";
+	case 12: return "Screen.drawCircle: illegal center coordinates";
+	case 13: return "Screen.drawCircle: illegal radius";
+	case 14: return "String.new: maximum length must be non-negative";
+	case 15: return "String.charAt: string index out of bounds";
+	case 16: return "String.setCharAt: string index out of bounds";
+	case 17: return "String.appendChar: string is full";
+	case 18: return "String.eraseLastChar: string is empty";
+	case 19: return "String.setInt: insufficient string capacity";
+	case 20: return "Output.moveCursor: illegal cursor location";
+	default: return "unknown error";
+	}
+}
+
+/* Stop the program like Sys.error, printing the decoded error code */
+static void fail(int16_t code) {
+	fflush(stdout);
+	fprintf(stderr, "ERR%d: %s\n", code, error_message(code));
+	exit(1);
+}
+
+/* Heap management (first fit over a free list, same layout as the Jack OS) */
+#define HEAP_BASE 2048
+#define HEAP_END 16384
+static int16_t free_list = -1;
+
+static int16_t os_Memory_init(int16_t *arg) {
+	(void)arg;
+	free_list = HEAP_BASE;
+	RAM[HEAP_BASE] = HEAP_END - HEAP_BASE;
+	RAM[HEAP_BASE + 1] = -1;
+	return 0;
+}
+
+static int16_t os_Memory_alloc(int16_t *arg) {
+	if (arg[0] <= 0) fail(5);
+	int16_t size = arg[0];
+	int16_t prev = -1;
+	for (int16_t seg = free_list; seg >= 0; prev = seg, seg = RAM[seg + 1]) {
+		if (RAM[seg] < size + 1) continue;
+		if (RAM[seg] >= size + 3) {
+			/* split the segment and hand out the tail */
+			RAM[seg] -= size + 1;
+			int16_t block = seg + RAM[seg];
+			RAM[block] = size + 1;
+			return block + 1;
+		}
+		if (prev < 0) free_list = RAM[seg + 1]; else RAM[prev + 1] = RAM[seg + 1];
+		return seg + 1;
+	}
+	fail(6);
+	return 0;
+}
+
+static int16_t os_Memory_deAlloc(int16_t *arg) {
+	int16_t seg = arg[0] - 1;
+	RAM[seg + 1] = free_list;
+	free_list = seg;
+	return 0;
+}
+
+static int16_t os_Memory_peek(int16_t *arg) { return MEM(arg[0]); }
+static int16_t os_Memory_poke(int16_t *arg) { MEM(arg[0]) = arg[1]; return 0; }
+static int16_t os_Array_new(int16_t *arg) {
+	if (arg[0] <= 0) fail(2);
+	return os_Memory_alloc(arg);
+}
+static int16_t os_Array_dispose(int16_t *arg) { return os_Memory_deAlloc(arg); }
+
+static int16_t os_Math_init(int16_t *arg) { (void)arg; return 0; }
+static int16_t os_Math_abs(int16_t *arg) { return (int16_t)(arg[0] < 0 ? -arg[0] : arg[0]); }
+static int16_t os_Math_multiply(int16_t *arg) { return (int16_t)(arg[0] * arg[1]); }
+static int16_t os_Math_divide(int16_t *arg) {
+	if (arg[1] == 0) fail(3);
+	return (int16_t)(arg[0] / arg[1]);
+}
+static int16_t os_Math_min(int16_t *arg) { return arg[0] < arg[1] ? arg[0] : arg[1]; }
+static int16_t os_Math_max(int16_t *arg) { return arg[0] > arg[1] ? arg[0] : arg[1]; }
+static int16_t os_Math_sqrt(int16_t *arg) {
+	if (arg[0] < 0) fail(4);
+	int32_t y = 0;
+	while ((y + 1) * (y + 1) <= arg[0]) y++;
+	return (int16_t)y;
+}
+
+/* String objects are laid out as [max length, length, chars...] */
+static int16_t os_String_new(int16_t *arg) {
+	if (arg[0] < 0) fail(14);
+	int16_t size = (int16_t)(arg[0] + 2);
+	int16_t s = os_Memory_alloc(&size);
+	MEM(s) = arg[0];
+	MEM(s + 1) = 0;
+	return s;
+}
+static int16_t os_String_dispose(int16_t *arg) { return os_Memory_deAlloc(arg); }
+static int16_t os_String_length(int16_t *arg) { return MEM(arg[0] + 1); }
+static int16_t os_String_charAt(int16_t *arg) {
+	if (arg[1] < 0 || arg[1] >= MEM(arg[0] + 1)) fail(15);
+	return MEM(arg[0] + 2 + arg[1]);
+}
+static int16_t os_String_setCharAt(int16_t *arg) {
+	if (arg[1] < 0 || arg[1] >= MEM(arg[0] + 1)) fail(16);
+	MEM(arg[0] + 2 + arg[1]) = arg[2];
+	return 0;
+}
+static int16_t os_String_appendChar(int16_t *arg) {
+	if (MEM(arg[0] + 1) >= MEM(arg[0])) fail(17);
+	MEM(arg[0] + 2 + MEM(arg[0] + 1)) = arg[1];
+	MEM(arg[0] + 1)++;
+	return arg[0];
+}
+static int16_t os_String_eraseLastChar(int16_t *arg) {
+	if (MEM(arg[0] + 1) == 0) fail(18);
+	MEM(arg[0] + 1)--;
+	return 0;
+}
+static int16_t os_String_intValue(int16_t *arg) {
+	int16_t len = MEM(arg[0] + 1);
+	int16_t value = 0;
+	int neg = len > 0 && MEM(arg[0] + 2) == '-';
+	for (int16_t i = neg ? 1 : 0; i < len; i++) {
+		int16_t c = MEM(arg[0] + 2 + i);
+		if (c < '0' || c > '9') break;
+		value = (int16_t)(value * 10 + (c - '0'));
+	}
+	return (int16_t)(neg ? -value : value);
+}
+static int16_t os_String_setInt(int16_t *arg) {
+	char buf[8];
+	int len = snprintf(buf, sizeof(buf), "%d", arg[1]);
+	if (len > MEM(arg[0])) fail(19);
+	MEM(arg[0] + 1) = 0;
+	for (int i = 0; i < len; i++) {
+		int16_t append[2] = {arg[0], buf[i]};
+		os_String_appendChar(append);
+	}
+	return 0;
+}
+static int16_t os_String_backSpace(int16_t *arg) { (void)arg; return 129; }
+static int16_t os_String_doubleQuote(int16_t *arg) { (void)arg; return 34; }
+static int16_t os_String_newLine(int16_t *arg) { (void)arg; return 128; }
+
+static int16_t os_Output_init(int16_t *arg) { (void)arg; return 0; }
+static int16_t os_Output_moveCursor(int16_t *arg) { (void)arg; return 0; }
+static int16_t os_Output_printChar(int16_t *arg) {
+	switch (arg[0]) {
+	case 128: putchar('\n'); break;
+	case 129: putchar('\b'); break;
+	default: putchar(arg[0]); break;
+	}
+	return 0;
+}
+static int16_t os_Output_printString(int16_t *arg) {
+	for (int16_t i = 0; i < MEM(arg[0] + 1); i++) putchar(MEM(arg[0] + 2 + i));
+	return 0;
+}
+static int16_t os_Output_printInt(int16_t *arg) { printf("%d", arg[0]); return 0; }
+static int16_t os_Output_println(int16_t *arg) { (void)arg; putchar('\n'); return 0; }
+static int16_t os_Output_backSpace(int16_t *arg) { (void)arg; putchar('\b'); return 0; }
+
+/* There is no display, so drawing is a no-op */
+static int16_t os_Screen_init(int16_t *arg) { (void)arg; return 0; }
+static int16_t os_Screen_clearScreen(int16_t *arg) { (void)arg; return 0; }
+static int16_t os_Screen_setColor(int16_t *arg) { (void)arg; return 0; }
+static int16_t os_Screen_drawPixel(int16_t *arg) { (void)arg; return 0; }
+static int16_t os_Screen_drawLine(int16_t *arg) { (void)arg; return 0; }
+static int16_t os_Screen_drawRectangle(int16_t *arg) { (void)arg; return 0; }
+static int16_t os_Screen_drawCircle(int16_t *arg) { (void)arg; return 0; }
+
+static int16_t os_Keyboard_init(int16_t *arg) { (void)arg; return 0; }
+static int16_t os_Keyboard_keyPressed(int16_t *arg) { (void)arg; return 0; }
+static int16_t os_Keyboard_readChar(int16_t *arg) {
+	(void)arg;
+	int c = getchar();
+	if (c == EOF) exit(0);
+	return (int16_t)(c == '\n' ? 128 : c);
+}
+static int16_t os_Keyboard_readLine(int16_t *arg) {
+	os_Output_printString(arg);
+	fflush(stdout);
+	int16_t max_len = 80;
+	int16_t s = os_String_new(&max_len);
+	for (int c = getchar(); c != EOF && c != '\n'; c = getchar()) {
+		if (MEM(s + 1) >= max_len) continue;
+		int16_t append[2] = {s, (int16_t)c};
+		os_String_appendChar(append);
+	}
+	return s;
+}
+static int16_t os_Keyboard_readInt(int16_t *arg) {
+	int16_t s = os_Keyboard_readLine(arg);
+	int16_t value = os_String_intValue(&s);
+	os_String_dispose(&s);
+	return value;
+}
+
+static int16_t os_Sys_halt(int16_t *arg) { (void)arg; fflush(stdout); exit(0); }
+static int16_t os_Sys_error(int16_t *arg) { fail(arg[0]); return 0; }
+static int16_t os_Sys_wait(int16_t *arg) {
+	if (arg[0] < 0) fail(1);
+	return 0;
+}
+"#;
+
+/// Mangle a VM symbol to a valid C identifier.
+/// Underscores are escaped first so different VM symbols never map to the same identifier.
+pub fn mangle(symbol: &str) -> String {
+	let mut mangled = String::new();
+	for c in symbol.chars() {
+		match c {
+			'_' => mangled.push_str("__"),
+			'.' => mangled.push_str("_D"),
+			'$' => mangled.push_str("_S"),
+			':' => mangled.push_str("_C"),
+			_other => mangled.push(_other),
+		}
+	}
+	mangled
+}
+
+/// C label for the start of a VM function
+pub fn function_label(name: &str) -> String {
+	format!("F_{}", mangle(name))
+}
+
+/// C label for a VM label (scoped per function)
+pub fn flow_label(func_name: &str, label: &str) -> String {
+	format!("L_{}_S{}", mangle(func_name), mangle(label))
+}
+
+/// C label to resume at after returning to the given call site
+pub fn return_label(call_site: u32) -> String {
+	format!("RET_{}", call_site)
+}
+
+/// C variable for a static segment entry
+pub fn static_variable(static_symbol: &str) -> String {
+	format!("S_{}", mangle(static_symbol))
+}
+
+/// C function implementing an OS function natively.
+/// OS function names don't contain underscores so we don't need the full mangling here.
+pub fn shim_function(name: &str) -> String {
+	format!("os_{}", name.replace('.', "_"))
+}
+
+/// Generate a single C file for the whole VM program.
+/// `context` must already know all functions defined by the program.
+pub fn generate_c_program(
+	commands: &[Box<dyn Command>],
+	context: &mut Context,
+) -> Result<String, String> {
+	let mut body = String::new();
+	let mut statics = BTreeSet::new();
+	for cmd in commands {
+		context.update(cmd);
+		if let Some(s) = cmd.static_symbol() {
+			statics.insert(s);
+		}
+		body.push_str(&cmd.to_c_text(context)?);
+	}
+	let entry = if context.is_defined(SYS_INIT) {
+		SYS_INIT
+	} else if context.is_defined(MAIN_FUNCTION) {
+		MAIN_FUNCTION
+	} else {
+		return Err(format!(
+			"Neither {} nor {} is defined",
+			SYS_INIT, MAIN_FUNCTION
+		));
+	};
+	let mut output = String::from(RUNTIME);
+	output.push('\n');
+	for s in &statics {
+		output.push_str(&format!("static int16_t {};\n", static_variable(s)));
+	}
+	// Call site 0 is reserved for the bootstrap call and returns to the exit code
+	let mut return_table = vec![format!("&&{}", return_label(0))];
+	for site in 1..=context.call_site_count() {
+		return_table.push(format!("&&{}", return_label(site)));
+	}
+	output.push_str(&format!(
+		"
+int main(void) {{
+	static void *const return_table[] = {{ {0} }};
+	os_Memory_init(NULL);
+	// Bootstrap: set stack pointer and call the entry function
+	RAM[SP] = 256;
+	PUSH(0);
+	PUSH(RAM[LCL]);
+	PUSH(RAM[ARG]);
+	PUSH(RAM[THIS]);
+	PUSH(RAM[THAT]);
+	RAM[ARG] = RAM[SP] - 5;
+	RAM[LCL] = RAM[SP];
+	goto {1};
+{2}:
+	fflush(stdout);
+	return 0;
+{3}}}
+",
+		return_table.join(", "),
+		function_label(entry),
+		return_label(0),
+		body
+	));
+	Ok(output)
+}
@@ -0,0 +1,51 @@
+use crate::command::{CommandType, SourceCommand};
+use std::collections::{BTreeMap, HashSet};
+
+/// Generate a call graph of the functions in DOT format.
+/// Edges are labeled with the number of call commands from the caller to the callee.
+/// Functions that are called but not defined (ex: OS functions that are not linked) are dashed.
+/// Calls before the first function have no caller and are left out
+pub fn generate_dot(commands: &[SourceCommand]) -> String {
+	let mut functions: Vec<&String> = Vec::new();
+	let mut calls: BTreeMap<(&String, &String), usize> = BTreeMap::new();
+	let mut caller: Option<&String> = None;
+	for cmd in commands {
+		match cmd.command.command_type() {
+			CommandType::Function => {
+				let name = cmd.command.symbol().unwrap();
+				functions.push(name);
+				caller = Some(name);
+			}
+			CommandType::Call => {
+				if let Some(caller) = caller {
+					*calls.entry((caller, cmd.command.symbol().unwrap())).or_insert(0) += 1;
+				}
+			}
+			_ => {}
+		}
+	}
+	let defined: HashSet<&String> = functions.iter().copied().collect();
+	let mut output = String::from("digraph calls {\n");
+	output.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+	for f in &functions {
+		output.push_str(&format!("    \"{}\";\n", f));
+	}
+	let mut undefined: Vec<&String> = calls
+		.keys()
+		.map(|(_, callee)| *callee)
+		.filter(|callee| !defined.contains(callee))
+		.collect();
+	undefined.sort();
+	undefined.dedup();
+	for f in undefined {
+		output.push_str(&format!("    \"{}\" [style=dashed];\n", f));
+	}
+	for ((caller, callee), count) in &calls {
+		output.push_str(&format!(
+			"    \"{}\" -> \"{}\" [label=\"{}\"];\n",
+			caller, callee, count
+		));
+	}
+	output.push_str("}\n");
+	output
+}
@@ -0,0 +1,226 @@
+use crate::command::{CommandType, SourceCommand, TranslateError};
+use std::collections::{HashMap, HashSet};
+
+/// Check that each function uses the stack consistently.
+/// Finds pops from an empty stack, returns with extra values left on the stack,
+/// and labels reached with different stack depths from different paths.
+/// Stack depth is relative to the start of each function.
+pub fn check_stack(commands: &[SourceCommand]) -> Vec<TranslateError> {
+	let starts: Vec<usize> = commands
+		.iter()
+		.enumerate()
+		.filter(|(_, c)| matches!(c.command.command_type(), CommandType::Function))
+		.map(|(idx, _)| idx)
+		.collect();
+	let mut errors = Vec::new();
+	for (n, start) in starts.iter().enumerate() {
+		let end = starts.get(n + 1).copied().unwrap_or(commands.len());
+		let mut function_errors = Vec::new();
+		check_function(&commands[*start..end], &mut function_errors);
+		// paths are followed in no particular order so report in order of lines
+		function_errors.sort_by_key(|e| e.line);
+		errors.append(&mut function_errors);
+	}
+	errors
+}
+
+/// First RAM address of the static variables (the variables of the assembler)
+pub const STATIC_BASE: u16 = 16;
+
+/// Counts the static variables in order of appearance to check that they fit in the RAM
+/// between STATIC_BASE and the stack (see check_statics).
+/// Commands can be added as they are parsed so the whole program is not needed at once
+pub struct StaticBudget {
+	stack_base: u16,
+	statics: HashSet<String>,
+	/// number of variables of each file in order of appearance
+	files: Vec<(String, usize)>,
+	/// Error location of the first command using a variable that does not fit, with the variable
+	overflow: Option<(TranslateError, String)>,
+}
+
+impl StaticBudget {
+	pub fn new(stack_base: u16) -> StaticBudget {
+		StaticBudget {
+			stack_base,
+			statics: HashSet::new(),
+			files: Vec::new(),
+			overflow: None,
+		}
+	}
+
+	/// Number of variables that fit
+	fn budget(&self) -> usize {
+		self.stack_base.saturating_sub(STATIC_BASE) as usize
+	}
+
+	/// Count the variable used by the command, if any
+	pub fn add(&mut self, cmd: &SourceCommand) {
+		let symbol = match cmd.command.static_symbol() {
+			Some(s) => s,
+			None => return,
+		};
+		if !self.statics.insert(symbol.clone()) {
+			return;
+		}
+		let (file, _index) = symbol.rsplit_once('.').unwrap();
+		match self.files.iter_mut().find(|(f, _)| f == file) {
+			Some((_, count)) => *count += 1,
+			None => self.files.push((file.to_string(), 1)),
+		}
+		if self.statics.len() > self.budget() && self.overflow.is_none() {
+			self.overflow = Some((cmd.error(String::new()), symbol));
+		}
+	}
+
+	/// Get the error if the variables counted so far do not fit
+	pub fn finish(self) -> Vec<TranslateError> {
+		let budget = self.budget();
+		match self.overflow {
+			Some((mut error, symbol)) => {
+				let breakdown: Vec<String> = self
+					.files
+					.iter()
+					.map(|(file, count)| format!("{}: {}", file, count))
+					.collect();
+				error.message = format!(
+					"Static variable {} does not fit in RAM {}-{}: {} static variables are used but only {} fit ({})",
+					symbol,
+					STATIC_BASE,
+					self.stack_base.saturating_sub(1),
+					self.statics.len(),
+					budget,
+					breakdown.join(", ")
+				);
+				vec![error]
+			}
+			None => Vec::new(),
+		}
+	}
+}
+
+/// Check that the static variables fit in the RAM between STATIC_BASE and the stack.
+/// The assembler allocates the variables in order of appearance so the error is reported
+/// at the first command using a variable that does not fit, with the number of variables of each file
+pub fn check_statics(commands: &[SourceCommand], stack_base: u16) -> Vec<TranslateError> {
+	let mut statics = StaticBudget::new(stack_base);
+	for cmd in commands {
+		statics.add(cmd);
+	}
+	statics.finish()
+}
+
+/// Check that each goto and if-goto jumps to a label defined in the same function,
+/// and that no label is defined twice in a function.
+/// Commands before the first function are checked like a function
+pub fn check_labels(commands: &[SourceCommand]) -> Vec<TranslateError> {
+	let mut errors = Vec::new();
+	let mut start = 0;
+	for end in 1..=commands.len() {
+		let function_ends = end == commands.len()
+			|| matches!(commands[end].command.command_type(), CommandType::Function);
+		if function_ends {
+			let mut function_errors = check_function_labels(&commands[start..end]);
+			function_errors.sort_by_key(|e| e.line);
+			errors.append(&mut function_errors);
+			start = end;
+		}
+	}
+	errors
+}
+
+/// Find duplicate labels and jumps to undefined labels in a function
+fn check_function_labels(commands: &[SourceCommand]) -> Vec<TranslateError> {
+	let mut errors = Vec::new();
+	let mut labels: HashMap<&String, &SourceCommand> = HashMap::new();
+	for cmd in commands {
+		if let CommandType::Label = cmd.command.command_type() {
+			let name = cmd.command.symbol().unwrap();
+			match labels.get(name) {
+				Some(first) => errors.push(cmd.error(format!(
+					"Duplicate label {} (first defined at line {})",
+					name, first.sources[0].number
+				))),
+				None => {
+					labels.insert(name, cmd);
+				}
+			}
+		}
+	}
+	for cmd in commands {
+		if let CommandType::GoTo | CommandType::If = cmd.command.command_type() {
+			let name = cmd.command.symbol().unwrap();
+			if !labels.contains_key(name) {
+				errors.push(cmd.error(format!("Undefined label {}", name)));
+			}
+		}
+	}
+	errors
+}
+
+/// Follow all paths of the function and track the stack depth of each command
+fn check_function(commands: &[SourceCommand], errors: &mut Vec<TranslateError>) {
+	let labels: HashMap<&String, usize> = commands
+		.iter()
+		.enumerate()
+		.filter(|(_, c)| matches!(c.command.command_type(), CommandType::Label))
+		.map(|(idx, c)| (c.command.symbol().unwrap(), idx))
+		.collect();
+	// Stack depth before each command. The last entry is for the end of the function
+	let mut depths: Vec<Option<i32>> = vec![None; commands.len() + 1];
+	let mut pending = vec![(0, 0)];
+	while let Some((idx, depth)) = pending.pop() {
+		match depths[idx] {
+			Some(known) if known == depth => continue,
+			Some(known) => {
+				if idx < commands.len() {
+					errors.push(commands[idx].error(format!(
+						"Reached with stack depth {} and {} from different paths",
+						known, depth
+					)));
+				}
+				continue;
+			}
+			None => depths[idx] = Some(depth),
+		}
+		if idx == commands.len() {
+			errors.push(commands[idx - 1].error(String::from(
+				"Function continues to the next function without return",
+			)));
+			continue;
+		}
+		let cmd = &commands[idx].command;
+		let (pops, pushes) = cmd.stack_effect();
+		if depth < pops as i32 {
+			errors.push(commands[idx].error(format!(
+				"Pops {} values from a stack with {} values",
+				pops, depth
+			)));
+			continue;
+		}
+		let next_depth = depth - pops as i32 + pushes as i32;
+		let target = cmd.symbol().and_then(|s| labels.get(s));
+		match cmd.command_type() {
+			CommandType::Return => {
+				if next_depth > 0 {
+					errors.push(commands[idx].error(format!(
+						"Returns with {} extra values on the stack",
+						next_depth
+					)));
+				}
+			}
+			CommandType::GoTo => {
+				if let Some(t) = target {
+					pending.push((*t, next_depth));
+				}
+			}
+			CommandType::If => {
+				if let Some(t) = target {
+					pending.push((*t, next_depth));
+				}
+				pending.push((idx + 1, next_depth));
+			}
+			_ => pending.push((idx + 1, next_depth)),
+		}
+	}
+}
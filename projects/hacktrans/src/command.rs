@@ -1,6 +1,58 @@
+use crate::c_backend;
+use std::collections::HashSet;
+
 type MemoryIndex = u32;
 type CommandID = u32;
 
+/// Largest value of the constant segment (the largest value an A instruction can load)
+pub const MAX_CONSTANT: MemoryIndex = 32767;
+/// Number of entries of the temp segment (R5-R12)
+const TEMP_SIZE: MemoryIndex = 8;
+/// Number of entries of the pointer segment (THIS and THAT)
+const POINTER_SIZE: MemoryIndex = 2;
+
+/// Addresses of the fixed memory areas of the target Hack platform.
+/// The base addresses of the this and that segments are kept in the first two entries of the pointer segment
+#[derive(Debug, Copy, Clone)]
+pub struct MemoryLayout {
+	/// Address the stack starts at
+	pub stack_base: u16,
+	/// Address of the first entry of the temp segment
+	pub temp_base: u16,
+	/// Address of the first entry of the pointer segment
+	pub pointer_base: u16,
+}
+
+impl Default for MemoryLayout {
+	fn default() -> MemoryLayout {
+		MemoryLayout {
+			stack_base: 256,
+			temp_base: 5,
+			pointer_base: 3,
+		}
+	}
+}
+
+impl MemoryLayout {
+	/// Symbol of the register holding the base address of the this (0) or that (1) segment.
+	/// The standard layout uses THIS and THAT of the assembler
+	fn pointer_symbol(&self, index: MemoryIndex) -> String {
+		if self.pointer_base == MemoryLayout::default().pointer_base {
+			return String::from(if index == 0 { "THIS" } else { "THAT" });
+		}
+		register_symbol(self.pointer_base as MemoryIndex + index)
+	}
+}
+
+/// Symbol of a RAM address. Addresses of the virtual registers use their names (R0 to R15)
+fn register_symbol(address: MemoryIndex) -> String {
+	if address < 16 {
+		format!("R{}", address)
+	} else {
+		address.to_string()
+	}
+}
+
 /// Type of arithmetic command
 #[derive(Debug, Copy, Clone)]
 pub enum ArithmeticType {
@@ -27,6 +79,8 @@ pub enum CommandType {
 	Function,
 	Return,
 	Call,
+	/// push immediately followed by pop (created by the optimizer)
+	Move,
 }
 
 /// Type of segment for VM memory access (push, pop)
@@ -42,6 +96,15 @@ pub enum SegmentType {
 	Temp,
 }
 
+/// Strategy for generating asm of commands that need many instructions (eq, gt, lt, call, return)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Codegen {
+	/// Expand the whole command inline each time
+	Speed,
+	/// Jump to a routine shared by all occurrences of the command
+	Size,
+}
+
 #[derive(Debug)]
 pub struct Arithmetic {
 	command: CommandType,
@@ -52,16 +115,84 @@ pub struct Arithmetic {
 	id: CommandID,
 }
 
+/// Line of VM source a command was parsed from
+#[derive(Debug, Clone)]
+pub struct SourceLine {
+	/// The origin input file name (without the .vm extension)
+	pub origin_name: String,
+	/// Line number starting from 1
+	pub number: usize,
+	/// Command without comments
+	pub text: String,
+}
+
+/// A command with the VM source lines it was created from.
+/// There can be multiple lines when the optimizer combines commands.
+#[derive(Debug)]
+pub struct SourceCommand {
+	pub sources: Vec<SourceLine>,
+	pub command: Box<dyn Command>,
+}
+
+impl SourceCommand {
+	/// Create an error at the first source line of the command
+	pub fn error(&self, message: String) -> TranslateError {
+		let source = &self.sources[0];
+		TranslateError {
+			origin_name: source.origin_name.clone(),
+			line: source.number,
+			text: source.text.clone(),
+			message,
+		}
+	}
+
+	/// Generate comments showing the source lines of the command
+	pub fn annotation(&self) -> String {
+		self.sources
+			.iter()
+			.map(|s| format!("// {}.vm:{}: {}\n", s.origin_name, s.number, s.text))
+			.collect()
+	}
+}
+
+/// Error in the VM source
+#[derive(Debug)]
+pub struct TranslateError {
+	/// The origin input file name (without the .vm extension)
+	pub origin_name: String,
+	/// Line number starting from 1
+	pub line: usize,
+	/// Text of the line without comments
+	pub text: String,
+	pub message: String,
+}
+
+impl std::fmt::Display for TranslateError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}.vm:{}: {}\n    {}", self.origin_name, self.line, self.message, self.text)
+	}
+}
+
 /// Counter for specific commands.
 /// We need to count the number to create a unique ID to use as jump labels in each command.
 /// Without this we will have clashing jump lables each time we use eq, gt, and lt.
+#[derive(Debug, Default)]
 pub struct Counter {
 	pub eq: CommandID,
 	pub gt: CommandID,
 	pub lt: CommandID,
 }
 
-#[derive(Debug)]
+impl Counter {
+	/// Add the counts of another counter (ex: of a file counted separately)
+	pub fn add(&mut self, other: &Counter) {
+		self.eq += other.eq;
+		self.gt += other.gt;
+		self.lt += other.lt;
+	}
+}
+
+#[derive(Debug, Clone)]
 pub struct MemoryAccess {
 	command: CommandType,
 	/// The origin input file name (without the .vm extension) of the command
@@ -71,6 +202,15 @@ pub struct MemoryAccess {
 	index: MemoryIndex,
 }
 
+/// Value pushed by a command moved directly to the target of the following pop.
+/// This skips the stack that is only used to pass the value.
+#[derive(Debug)]
+pub struct Move {
+	command: CommandType,
+	source: MemoryAccess,
+	target: MemoryAccess,
+}
+
 #[derive(Debug)]
 pub struct ProgramFlow {
 	command: CommandType,
@@ -89,12 +229,16 @@ pub struct Function {
 /// Needed to generate function call/return labels
 #[derive(Debug)]
 pub struct Context {
-	/// prefix is used as a unique string for marking labels unique to the output file
-	prefix: String,
-	/// Current function name
+	/// Current function name. Used to scope labels and return addresses to the function
 	func_name: String,
 	/// Number of functions called within function
 	func_call_count: u16,
+	/// Names of all functions defined in the program (used by the C backend)
+	defined_functions: HashSet<String>,
+	/// Number of call sites in the whole program (used by the C backend)
+	call_site_count: u32,
+	codegen: Codegen,
+	layout: MemoryLayout,
 }
 
 pub const NULL_ID: CommandID = 0;
@@ -165,7 +309,12 @@ M=D
 
 /// Generate asm for 'call f n'
 /// This is used in bootstrap code as well
-pub fn generate_call_asm(return_label: &str, arg_num: u16, func_name: &str) -> String {
+pub fn generate_call_asm(
+	return_label: &str,
+	arg_num: u16,
+	func_name: &str,
+	layout: &MemoryLayout,
+) -> String {
 	// ARG address offset can be calculated from Stack pointer - (1 return address and 4 register states) + number of args
 	let offset_to_arg = 5 + arg_num;
 	let str = format!(
@@ -196,7 +345,7 @@ D=A+1
 @SP
 M=D
 // Save THIS
-@THIS
+@{3}
 D=M
 @SP
 A=M
@@ -205,7 +354,7 @@ D=A+1
 @SP
 M=D
 // Save THAT
-@THAT
+@{4}
 D=M
 @SP
 A=M
@@ -231,20 +380,306 @@ M=D
 // Create return label
 ({0})
 ",
-		return_label, offset_to_arg, func_name
+		return_label,
+		offset_to_arg,
+		func_name,
+		layout.pointer_symbol(0),
+		layout.pointer_symbol(1)
 	);
 	return str;
 }
 
+/// Scratch register keeping the target address of a pop while the value is loaded
+const POP_ADDRESS_REGISTER: &str = "R13";
+/// Scratch register keeping the return address while restoring the caller's state
+const RETURN_ADDRESS_REGISTER: &str = "R14";
+
+/// Generate asm for 'return'
+fn generate_return_asm(layout: &MemoryLayout) -> String {
+	format!(
+		"@LCL
+D=M
+@5
+A=D-A
+D=M
+@{0}
+M=D
+@SP
+A=M-1
+D=M
+@ARG
+A=M
+M=D
+// reposition stack pointer
+D=A+1
+@SP
+M=D
+// restore segment address
+@LCL
+A=M-1
+D=M
+@{2}
+M=D
+@LCL
+A=M-1
+A=A-1
+D=M
+@{1}
+M=D
+@LCL
+D=M
+@3
+A=D-A
+D=M
+@ARG
+M=D
+@LCL
+D=M
+@4
+A=D-A
+D=M
+@LCL
+M=D
+// goto return address
+@{0}
+A=M;JMP
+",
+		RETURN_ADDRESS_REGISTER,
+		layout.pointer_symbol(0),
+		layout.pointer_symbol(1)
+	)
+}
+
+/// Label of the shared routine for the command with Codegen::Size
+const EQ_ROUTINE: &str = "__eq";
+const GT_ROUTINE: &str = "__gt";
+const LT_ROUTINE: &str = "__lt";
+const CALL_ROUTINE: &str = "__call";
+const RETURN_ROUTINE: &str = "__return";
+
+/// Generate asm replacing the top two values x and y of the stack with true (-1) if x - y meets the jump condition, and false (0) otherwise.
+/// x - y overflows when x and y have different signs, so the sign of x decides the result without subtracting in that case.
+/// `label` is used as the prefix of the jump labels and must be unique
+fn generate_signed_compare_asm(label: &str, jump: &str) -> String {
+	format!(
+		"@SP
+AM=M-1
+D=M
+@{0}.YNeg
+D;JLT
+@SP
+A=M-1
+D=M
+@{0}.SignDiffers
+D;JLT
+@{0}.Sub
+0;JMP
+({0}.YNeg)
+@SP
+A=M-1
+D=M
+@{0}.Sub
+D;JLT
+({0}.SignDiffers)
+// x with the lowest bit set is never 0 and has the sign of x - y
+@1
+D=D|A
+@{0}.Test
+0;JMP
+({0}.Sub)
+@SP
+A=M
+D=M
+A=A-1
+D=M-D
+({0}.Test)
+@{0}.True
+D;{1}
+D=0
+@{0}.Write
+0;JMP
+({0}.True)
+D=-1
+({0}.Write)
+@SP
+A=M-1
+M=D
+",
+		label, jump
+	)
+}
+
+/// Generate a shared routine comparing x and y for gt and lt.
+/// Returns to the address in R15
+fn generate_signed_compare_routine(label: &str, jump: &str) -> String {
+	format!(
+		"({0})
+{1}@R15
+A=M
+0;JMP
+",
+		label,
+		generate_signed_compare_asm(label, jump)
+	)
+}
+
+/// Generate a shared routine comparing the top two values of the stack.
+/// The difference of the values is compared so this is only used for eq, which is not affected by overflow.
+/// Returns to the address in R15
+fn generate_compare_routine(label: &str, jump: &str) -> String {
+	format!(
+		"({0})
+@SP
+AM=M-1
+D=M
+A=A-1
+D=M-D
+M=-1
+@{0}.true
+D;{1}
+@SP
+A=M-1
+M=0
+({0}.true)
+@R15
+A=M
+0;JMP
+",
+		label, jump
+	)
+}
+
+/// Generate the routines shared by all commands for Codegen::Size.
+/// Only the routines of commands used in the program are generated.
+/// These must be placed where they are never reached without a jump.
+pub fn generate_shared_routines(commands: &[SourceCommand], layout: &MemoryLayout) -> String {
+	let uses_arithmetic = |arithmetic: fn(ArithmeticType) -> bool| {
+		commands.iter().any(|c| c.command.arithmetic().is_some_and(arithmetic))
+	};
+	let uses_command = |command: fn(CommandType) -> bool| {
+		commands.iter().any(|c| command(c.command.command_type()))
+	};
+	let mut str = String::new();
+	if uses_arithmetic(|a| matches!(a, ArithmeticType::Eq)) {
+		str.push_str(&generate_compare_routine(EQ_ROUTINE, "JEQ"));
+	}
+	if uses_arithmetic(|a| matches!(a, ArithmeticType::Gt)) {
+		str.push_str(&generate_signed_compare_routine(GT_ROUTINE, "JGT"));
+	}
+	if uses_arithmetic(|a| matches!(a, ArithmeticType::Lt)) {
+		str.push_str(&generate_signed_compare_routine(LT_ROUTINE, "JLT"));
+	}
+	if uses_command(|c| matches!(c, CommandType::Call)) {
+		str.push_str(&generate_call_routine(layout));
+	}
+	if uses_command(|c| matches!(c, CommandType::Return)) {
+		str.push_str(&format!("({})\n", RETURN_ROUTINE));
+		str.push_str(&generate_return_asm(layout));
+	}
+	str
+}
+
+/// Generate the routine shared by all calls for Codegen::Size.
+/// D has the number of arguments, R14 the function address, and R15 the return address
+fn generate_call_routine(layout: &MemoryLayout) -> String {
+	let mut str = String::new();
+	str.push_str(&format!("({})\n", CALL_ROUTINE));
+	str.push_str(
+		"@5
+D=D+A
+@R13
+M=D
+@R15
+D=M
+@SP
+AM=M+1
+A=A-1
+M=D
+",
+	);
+	let this = layout.pointer_symbol(0);
+	let that = layout.pointer_symbol(1);
+	for register in &["LCL", "ARG", &this, &that] {
+		str.push_str(&format!(
+			"// Save {}
+@{0}
+D=M
+@SP
+AM=M+1
+A=A-1
+M=D
+",
+			register
+		));
+	}
+	str.push_str(
+		"// Reposition ARG
+@SP
+D=M
+@R13
+D=D-M
+@ARG
+M=D
+// Reposition LCL
+@SP
+D=M
+@LCL
+M=D
+// Goto Function
+@R14
+A=M
+0;JMP
+",
+	);
+	str
+}
+
+/// Generate asm jumping to a shared routine that returns to the address in R15
+fn generate_routine_call(routine: &str, return_label: &str) -> String {
+	format!(
+		"@{1}
+D=A
+@R15
+M=D
+@{0}
+0;JMP
+({1})
+",
+		routine, return_label
+	)
+}
+
 impl Context {
-	pub fn new(prefix: String) -> Context {
+	pub fn new(codegen: Codegen, layout: MemoryLayout) -> Context {
 		Context {
-			prefix: prefix,
 			func_name: String::from("root"),
 			func_call_count: 0,
+			defined_functions: HashSet::new(),
+			call_site_count: 0,
+			codegen,
+			layout,
+		}
+	}
+
+	/// Register all functions defined in the given commands
+	pub fn define_functions(&mut self, commands: &[Box<dyn Command>]) {
+		for cmd in commands {
+			if let CommandType::Function = cmd.command_type() {
+				self.defined_functions.insert(cmd.symbol().unwrap().clone());
+			}
 		}
 	}
 
+	/// Returns true if the function is defined in the program
+	pub fn is_defined(&self, func_name: &str) -> bool {
+		self.defined_functions.contains(func_name)
+	}
+
+	/// Returns number of call sites seen so far
+	pub fn call_site_count(&self) -> u32 {
+		self.call_site_count
+	}
+
 	/// Update context based on given current command
 	pub fn update(&mut self, command: &Box<dyn Command>) {
 		match command.command_type() {
@@ -256,6 +691,7 @@ impl Context {
 			CommandType::Call => {
 				// Update call count
 				self.func_call_count += 1;
+				self.call_site_count += 1;
 			}
 			_ => {}
 		}
@@ -268,12 +704,32 @@ impl Context {
 }
 
 /// General interface for all commands in VM
-pub trait Command: std::fmt::Debug {
+/// Commands are Send and Sync so files and functions can be translated on separate threads
+pub trait Command: std::fmt::Debug + Send + Sync {
 	/// Returns current command's command type
 	fn command_type(&self) -> CommandType;
 	/// Returns a symbol or function name for commands that uses it
 	fn symbol(&self) -> Option<&String>;
 	fn to_asm_text(&self, context: &Context) -> Result<String, String>;
+	/// Generate C statements for the command (see c_backend.rs for the runtime)
+	fn to_c_text(&self, context: &Context) -> Result<String, String>;
+	/// Returns the static variable symbol for commands accessing the static segment
+	fn static_symbol(&self) -> Option<String> {
+		None
+	}
+	/// Returns the memory access for push and pop commands
+	fn memory_access(&self) -> Option<&MemoryAccess> {
+		None
+	}
+	/// Returns the type of arithmetic commands
+	fn arithmetic(&self) -> Option<ArithmeticType> {
+		None
+	}
+	/// Shift the ID of the command by the number of commands of its type counted before it.
+	/// Makes the IDs of files parsed separately unique in the whole program
+	fn offset_id(&mut self, _counter: &Counter) {}
+	/// Returns the number of values the command pops from the stack and pushes to the stack
+	fn stack_effect(&self) -> (u16, u16);
 }
 
 impl ProgramFlow {
@@ -293,8 +749,16 @@ impl Command for ProgramFlow {
 		Some(&self.symbol)
 	}
 
+	fn stack_effect(&self) -> (u16, u16) {
+		match self.command {
+			CommandType::If => (1, 0),
+			_ => (0, 0),
+		}
+	}
+
 	fn to_asm_text(&self, context: &Context) -> Result<String, String> {
-		let target_label = format!("{}.{}", context.prefix, self.symbol);
+		// labels are scoped to the enclosing function so the same label can be used in different functions
+		let target_label = format!("{}${}", context.func_name, self.symbol);
 		match self.command {
 			CommandType::Label => {
 				let str = format!("({})\n", target_label);
@@ -326,6 +790,16 @@ D;JNE
 			_other => Err(format!("Unsupported CommandType: {:?}", _other)),
 		}
 	}
+
+	fn to_c_text(&self, context: &Context) -> Result<String, String> {
+		let target_label = c_backend::flow_label(&context.func_name, &self.symbol);
+		match self.command {
+			CommandType::Label => Ok(format!("{}:;\n", target_label)),
+			CommandType::GoTo => Ok(format!("\tgoto {};\n", target_label)),
+			CommandType::If => Ok(format!("\tif (POP() != 0) goto {};\n", target_label)),
+			_other => Err(format!("Unsupported ProgramFlow command: {:?}", _other)),
+		}
+	}
 }
 
 impl Function {
@@ -345,6 +819,14 @@ impl Command for Function {
 	fn symbol(&self) -> Option<&String> {
 		self.name.as_ref()
 	}
+
+	fn stack_effect(&self) -> (u16, u16) {
+		match self.command {
+			CommandType::Call => (self.arg_or_var_num.unwrap(), 1),
+			CommandType::Return => (1, 0),
+			_ => (0, 0),
+		}
+	}
 	fn to_asm_text(&self, context: &Context) -> Result<String, String> {
 		match self.command {
 			CommandType::Function => {
@@ -374,79 +856,121 @@ M=D
 				Ok(str)
 			}
 			CommandType::Return => {
-				let return_address = format!("{}.ret", context.prefix);
+				if context.codegen == Codegen::Size {
+					return Ok(format!("@{}\n0;JMP\n", RETURN_ROUTINE));
+				}
 				// store return address,
 				// push return value,
 				// reposition stack pointer
 				// restore segment address values
 				// and jump to return address
-				let str = format!(
-					"@LCL
-D=M
-@5
-A=D-A
-D=M
-@{0}
-M=D
-@SP
-A=M-1
-D=M
-@ARG
-A=M
-M=D
-// reposition stack pointer
-D=A+1
-@SP
-M=D
-// restore segment address
-@LCL
-A=M-1
-D=M
-@THAT
-M=D
-@LCL
-A=M-1
-A=A-1
-D=M
-@THIS
-M=D
-@LCL
-D=M
-@3
-A=D-A
-D=M
-@ARG
+				Ok(generate_return_asm(&context.layout))
+			}
+			CommandType::Call if context.codegen == Codegen::Size => {
+				// R15 has the return address
+				Ok(format!(
+					"@{0}
+D=A
+@R15
 M=D
-@LCL
-D=M
-@4
-A=D-A
-D=M
-@LCL
+@{1}
+D=A
+@R14
 M=D
-// goto return address
-@{0}
-A=M;JMP
+@{2}
+D=A
+@{3}
+0;JMP
+({0})
 ",
-					return_address
-				);
-				Ok(str)
+					context.return_label(),
+					self.name.as_ref().unwrap(),
+					self.arg_or_var_num.unwrap(),
+					CALL_ROUTINE
+				))
 			}
 			CommandType::Call => {
 				let str = generate_call_asm(
 					&context.return_label(),
 					self.arg_or_var_num.unwrap(),
 					self.name.as_ref().unwrap(),
+					&context.layout,
 				);
 				Ok(str)
 			}
 			_other => Err(format!("Unsupported Function command: {:?}", _other)),
 		}
 	}
+
+	fn to_c_text(&self, context: &Context) -> Result<String, String> {
+		match self.command {
+			CommandType::Function => {
+				let mut str = format!("{}:\n", c_backend::function_label(self.name.as_ref().unwrap()));
+				// initialize local variables to zero
+				for _ in 0..self.arg_or_var_num.unwrap() {
+					str.push_str("\tPUSH(0);\n");
+				}
+				Ok(str)
+			}
+			CommandType::Return => Ok(String::from(
+				"\t{
+		int16_t frame = RAM[LCL];
+		int16_t ret = MEM(frame - 5);
+		MEM(RAM[ARG]) = POP();
+		RAM[SP] = RAM[ARG] + 1;
+		RAM[THAT] = MEM(frame - 1);
+		RAM[THIS] = MEM(frame - 2);
+		RAM[ARG] = MEM(frame - 3);
+		RAM[LCL] = MEM(frame - 4);
+		goto *return_table[ret];
+	}
+",
+			)),
+			CommandType::Call => {
+				let name = self.name.as_ref().unwrap();
+				let arg_num = self.arg_or_var_num.unwrap();
+				if context.is_defined(name) {
+					// same calling convention as the asm code, with the call site as return address
+					Ok(format!(
+						"\tPUSH({0});
+	PUSH(RAM[LCL]);
+	PUSH(RAM[ARG]);
+	PUSH(RAM[THIS]);
+	PUSH(RAM[THAT]);
+	RAM[ARG] = RAM[SP] - {1};
+	RAM[LCL] = RAM[SP];
+	goto {2};
+{3}:;
+",
+						context.call_site_count,
+						arg_num + 5,
+						c_backend::function_label(name),
+						c_backend::return_label(context.call_site_count)
+					))
+				} else if c_backend::OS_SHIMS.contains(&name.as_str()) {
+					// the return label is unused but keeps the return table dense
+					Ok(format!(
+						"\tCALL_NATIVE({}, {});\n{}:;\n",
+						c_backend::shim_function(name),
+						arg_num,
+						c_backend::return_label(context.call_site_count)
+					))
+				} else {
+					Err(format!("Undefined function called: {}", name))
+				}
+			}
+			_other => Err(format!("Unsupported Function command: {:?}", _other)),
+		}
+	}
 }
 
 impl MemoryAccess {
-	pub fn new(command: CommandType, origin_name: &str, segment: &str, index: &str) -> MemoryAccess {
+	pub fn new(
+		command: CommandType,
+		origin_name: &str,
+		segment: &str,
+		index: &str,
+	) -> Result<MemoryAccess, String> {
 		let seg = match segment {
 			"argument" => SegmentType::Argument,
 			"local" => SegmentType::Local,
@@ -456,14 +980,77 @@ impl MemoryAccess {
 			"that" => SegmentType::That,
 			"temp" => SegmentType::Temp,
 			"pointer" => SegmentType::Pointer,
-			_other => panic!("Unknown segment specified: {:?}", _other),
+			_other => return Err(format!("Unknown segment: {}", _other)),
 		};
-		let idx = str::parse::<MemoryIndex>(index);
-		MemoryAccess {
+		if let (CommandType::Pop, SegmentType::Constant) = (command, seg) {
+			return Err(String::from("Cannot pop to constant segment"));
+		}
+		let idx = str::parse::<MemoryIndex>(index).map_err(|_| format!("Invalid index: {}", index))?;
+		let max_index = match seg {
+			SegmentType::Temp => Some(TEMP_SIZE - 1),
+			SegmentType::Pointer => Some(POINTER_SIZE - 1),
+			SegmentType::Constant => Some(MAX_CONSTANT),
+			_ => None,
+		};
+		if let Some(max) = max_index.filter(|max| idx > *max) {
+			return Err(format!("Index {} is out of range of {} (0-{})", idx, segment, max));
+		}
+		Ok(MemoryAccess {
 			command: command,
 			origin_name: origin_name.to_string(),
 			segment: seg,
-			index: idx.unwrap(),
+			index: idx,
+		})
+	}
+
+	/// Push of a constant value
+	pub fn push_constant(origin_name: &str, value: MemoryIndex) -> MemoryAccess {
+		MemoryAccess {
+			command: CommandType::Push,
+			origin_name: origin_name.to_string(),
+			segment: SegmentType::Constant,
+			index: value,
+		}
+	}
+
+	/// Returns the value pushed by a push of the constant segment
+	pub fn constant(&self) -> Option<MemoryIndex> {
+		match (self.command, self.segment) {
+			(CommandType::Push, SegmentType::Constant) => Some(self.index),
+			_ => None,
+		}
+	}
+
+	/// Symbol of the register holding the base address for the segments located by a pointer
+	fn base_register(&self, layout: &MemoryLayout) -> Option<String> {
+		match self.segment {
+			SegmentType::Local => Some(String::from("LCL")),
+			SegmentType::Argument => Some(String::from("ARG")),
+			SegmentType::This => Some(layout.pointer_symbol(0)),
+			SegmentType::That => Some(layout.pointer_symbol(1)),
+			_ => None,
+		}
+	}
+
+	/// Symbol of the fixed address for temp, pointer, and static segments
+	fn fixed_symbol(&self, layout: &MemoryLayout) -> Option<String> {
+		match self.segment {
+			SegmentType::Temp => Some(register_symbol(layout.temp_base as MemoryIndex + self.index)),
+			SegmentType::Pointer => Some(register_symbol(layout.pointer_base as MemoryIndex + self.index)),
+			SegmentType::Static => self.static_symbol(),
+			_ => None,
+		}
+	}
+
+	/// Generate asm that loads the value of the segment entry to D
+	fn load_asm(&self, layout: &MemoryLayout) -> String {
+		if let SegmentType::Constant = self.segment {
+			return format!("@{}\nD=A\n", self.index);
+		}
+		match (self.base_register(layout), self.fixed_symbol(layout)) {
+			(Some(base), _) => format!("@{}\nD=A\n@{}\nA=D+M\nD=M\n", self.index, base),
+			(_, Some(symbol)) => format!("@{}\nD=M\n", symbol),
+			_ => unreachable!(),
 		}
 	}
 }
@@ -478,8 +1065,9 @@ impl Command for MemoryAccess {
 	}
 
 	fn to_asm_text(&self, context: &Context) -> Result<String, String> {
-		let tmp_symbol = format!("{}.tmp", context.prefix);
 		let static_symbol = format!("{}.{}", self.origin_name, self.index);
+		let temp_base = register_symbol(context.layout.temp_base as MemoryIndex);
+		let pointer_base = register_symbol(context.layout.pointer_base as MemoryIndex);
 		match self.command {
 			CommandType::Push => match self.segment {
 				SegmentType::Constant => {
@@ -538,7 +1126,7 @@ M=M+1
 					let str = format!(
 						"@{}
 D=A
-@THIS
+@{}
 A=D+M
 D=M
 @SP
@@ -547,7 +1135,8 @@ M=D
 @SP
 M=M+1
 ",
-						self.index
+						self.index,
+						context.layout.pointer_symbol(0)
 					);
 					Ok(str)
 				}
@@ -556,7 +1145,7 @@ M=M+1
 					let str = format!(
 						"@{}
 D=A
-@THAT
+@{}
 A=D+M
 D=M
 @SP
@@ -565,7 +1154,8 @@ M=D
 @SP
 M=M+1
 ",
-						self.index
+						self.index,
+						context.layout.pointer_symbol(1)
 					);
 					Ok(str)
 				}
@@ -574,7 +1164,7 @@ M=M+1
 					let str = format!(
 						"@{}
 D=A
-@R5
+@{}
 A=D+A
 D=M
 @SP
@@ -583,7 +1173,7 @@ M=D
 @SP
 M=M+1
 ",
-						self.index
+						self.index, temp_base
 					);
 					Ok(str)
 				}
@@ -592,7 +1182,7 @@ M=M+1
 					let str = format!(
 						"@{}
 D=A
-@R3
+@{}
 A=D+A
 D=M
 @SP
@@ -601,7 +1191,7 @@ M=D
 @SP
 M=M+1
 ",
-						self.index
+						self.index, pointer_base
 					);
 					Ok(str)
 				}
@@ -638,7 +1228,7 @@ D=M
 A=M
 M=D
 ",
-						self.index, tmp_symbol
+						self.index, POP_ADDRESS_REGISTER
 					);
 					Ok(str)
 				}
@@ -658,16 +1248,16 @@ D=M
 A=M
 M=D
 ",
-						self.index, tmp_symbol
+						self.index, POP_ADDRESS_REGISTER
 					);
 					Ok(str)
 				}
 				SegmentType::This => {
 					// move value from global stack to this segment
 					let str = format!(
-						"@{}
+						"@{0}
 D=A
-@THIS
+@{2}
 D=D+M
 @{1}
 M=D
@@ -678,16 +1268,18 @@ D=M
 A=M
 M=D
 ",
-						self.index, tmp_symbol
+						self.index,
+						POP_ADDRESS_REGISTER,
+						context.layout.pointer_symbol(0)
 					);
 					Ok(str)
 				}
 				SegmentType::That => {
 					// move value from global stack to that segment
 					let str = format!(
-						"@{}
+						"@{0}
 D=A
-@THAT
+@{2}
 D=D+M
 @{1}
 M=D
@@ -698,16 +1290,18 @@ D=M
 A=M
 M=D
 ",
-						self.index, tmp_symbol
+						self.index,
+						POP_ADDRESS_REGISTER,
+						context.layout.pointer_symbol(1)
 					);
 					Ok(str)
 				}
 				SegmentType::Temp => {
-					// move value from global stack to temp segment (R5 to R12)
+					// move value from global stack to temp segment (R5 to R12 by default)
 					let str = format!(
 						"@{}
 D=A
-@R5
+@{2}
 D=D+A
 @{1}
 M=D
@@ -718,16 +1312,16 @@ D=M
 A=M
 M=D
 ",
-						self.index, tmp_symbol
+						self.index, POP_ADDRESS_REGISTER, temp_base
 					);
 					Ok(str)
 				}
 				SegmentType::Pointer => {
-					// move value from global stack to pointer segment (R3 to R4)
+					// move value from global stack to pointer segment (R3 to R4 by default)
 					let str = format!(
 						"@{}
 D=A
-@R3
+@{2}
 D=D+A
 @{1}
 M=D
@@ -738,7 +1332,7 @@ D=M
 A=M
 M=D
 ",
-						self.index, tmp_symbol
+						self.index, POP_ADDRESS_REGISTER, pointer_base
 					);
 					Ok(str)
 				}
@@ -760,6 +1354,104 @@ M=D
 			_other => Err(format!("Unsupported MemoryAccessCommand: {:?}", _other)),
 		}
 	}
+
+	fn static_symbol(&self) -> Option<String> {
+		match self.segment {
+			SegmentType::Static => Some(format!("{}.{}", self.origin_name, self.index)),
+			_ => None,
+		}
+	}
+
+	fn memory_access(&self) -> Option<&MemoryAccess> {
+		Some(self)
+	}
+
+	fn stack_effect(&self) -> (u16, u16) {
+		match self.command {
+			CommandType::Pop => (1, 0),
+			_ => (0, 1),
+		}
+	}
+
+	fn to_c_text(&self, _context: &Context) -> Result<String, String> {
+		// C expression for the memory location of the segment entry
+		let location = match self.segment {
+			SegmentType::Argument => format!("MEM(RAM[ARG] + {})", self.index),
+			SegmentType::Local => format!("MEM(RAM[LCL] + {})", self.index),
+			SegmentType::This => format!("MEM(RAM[THIS] + {})", self.index),
+			SegmentType::That => format!("MEM(RAM[THAT] + {})", self.index),
+			SegmentType::Pointer => format!("RAM[THIS + {}]", self.index),
+			SegmentType::Temp => format!("RAM[TEMP + {}]", self.index),
+			SegmentType::Static => c_backend::static_variable(&self.static_symbol().unwrap()),
+			SegmentType::Constant => self.index.to_string(),
+		};
+		match (self.command, self.segment) {
+			(CommandType::Push, _) => Ok(format!("\tPUSH({});\n", location)),
+			(CommandType::Pop, SegmentType::Constant) => {
+				Err(String::from("Cannot pop to constant segment"))
+			}
+			(CommandType::Pop, _) => Ok(format!("\t{} = POP();\n", location)),
+			_other => Err(format!("Unsupported MemoryAccessCommand: {:?}", _other)),
+		}
+	}
+}
+
+impl Move {
+	/// Create a move for a push followed by a pop.
+	/// Returns None if the commands are not a push and a pop
+	pub fn new(source: &MemoryAccess, target: &MemoryAccess) -> Option<Move> {
+		match (source.command, target.command, target.segment) {
+			(_, _, SegmentType::Constant) => None,
+			(CommandType::Push, CommandType::Pop, _) => Some(Move {
+				command: CommandType::Move,
+				source: source.clone(),
+				target: target.clone(),
+			}),
+			_ => None,
+		}
+	}
+}
+
+impl Command for Move {
+	fn command_type(&self) -> CommandType {
+		self.command
+	}
+
+	fn symbol(&self) -> Option<&String> {
+		None
+	}
+
+	fn stack_effect(&self) -> (u16, u16) {
+		(0, 0)
+	}
+
+	fn to_asm_text(&self, context: &Context) -> Result<String, String> {
+		let load = self.source.load_asm(&context.layout);
+		match (self.target.base_register(&context.layout), self.target.fixed_symbol(&context.layout)) {
+			(Some(base), _) => {
+				// calculate the target address first because loading the value overwrites D
+				Ok(format!(
+					"@{0}
+D=A
+@{1}
+D=D+M
+@{2}
+M=D
+{3}@{2}
+A=M
+M=D
+",
+					self.target.index, base, POP_ADDRESS_REGISTER, load
+				))
+			}
+			(_, Some(symbol)) => Ok(format!("{}@{}\nM=D\n", load, symbol)),
+			_ => Err(format!("Unsupported memory segment for Pop: {:?}", self.target.segment)),
+		}
+	}
+
+	fn to_c_text(&self, _context: &Context) -> Result<String, String> {
+		Err(String::from("Optimized commands are only supported for the asm target"))
+	}
 }
 
 impl Arithmetic {
@@ -781,7 +1473,41 @@ impl Command for Arithmetic {
 		None
 	}
 
-	fn to_asm_text(&self, _context: &Context) -> Result<String, String> {
+	fn arithmetic(&self) -> Option<ArithmeticType> {
+		Some(self.arithmetic)
+	}
+
+	fn offset_id(&mut self, counter: &Counter) {
+		self.id += match self.arithmetic {
+			ArithmeticType::Eq => counter.eq,
+			ArithmeticType::Gt => counter.gt,
+			ArithmeticType::Lt => counter.lt,
+			_ => 0,
+		};
+	}
+
+	fn stack_effect(&self) -> (u16, u16) {
+		match self.arithmetic {
+			ArithmeticType::Neg | ArithmeticType::Not => (1, 1),
+			_ => (2, 1),
+		}
+	}
+
+	fn to_asm_text(&self, context: &Context) -> Result<String, String> {
+		if context.codegen == Codegen::Size {
+			match self.arithmetic {
+				ArithmeticType::Eq => {
+					return Ok(generate_routine_call(EQ_ROUTINE, &format!("EqReturn.{}", self.id)))
+				}
+				ArithmeticType::Gt => {
+					return Ok(generate_routine_call(GT_ROUTINE, &format!("GtReturn.{}", self.id)))
+				}
+				ArithmeticType::Lt => {
+					return Ok(generate_routine_call(LT_ROUTINE, &format!("LtReturn.{}", self.id)))
+				}
+				_ => {}
+			}
+		}
 		match self.arithmetic {
 			ArithmeticType::Add => Ok(ADD_ASM.to_string()),
 			ArithmeticType::Sub => Ok(SUB_ASM.to_string()),
@@ -811,58 +1537,24 @@ M=D
 ",
                 self.id
             )),
-			ArithmeticType::Lt => Ok(format!(
-                // use the ID to create a unique jump label for each command
-                "@SP
-A=M
-A=A-1
-D=M
-A=A-1
-D=M-D
-@IsGe.{0}
-D;JGE
-D=-1
-@WriteLtOutput.{0}
-0;JMP
-(IsGe.{0})
-D=0
-(WriteLtOutput.{0})
-@SP
-A=M-1
-A=A-1
-M=D
-D=A+1
-@SP
-M=D
-",
-                self.id
-            )),
-			ArithmeticType::Gt => Ok(format!(
-                // use the ID to create a unique jump label for each command
-                "@SP
-A=M
-A=A-1
-D=M
-A=A-1
-D=M-D
-@IsGt.{0}
-D;JGT
-D=0
-@WriteGtOutput.{0}
-0;JMP
-(IsGt.{0})
-D=-1
-(WriteGtOutput.{0})
-@SP
-A=M-1
-A=A-1
-M=D
-D=A+1
-@SP
-M=D
-",
-                self.id
-            )),
+			ArithmeticType::Lt => Ok(generate_signed_compare_asm(&format!("Lt.{}", self.id), "JLT")),
+			ArithmeticType::Gt => Ok(generate_signed_compare_asm(&format!("Gt.{}", self.id), "JGT")),
 		}
 	}
+
+	fn to_c_text(&self, _context: &Context) -> Result<String, String> {
+		// comparisons result in -1 (true) or 0 (false) like the hack platform
+		let statement = match self.arithmetic {
+			ArithmeticType::Add => "BINARY(x + y)",
+			ArithmeticType::Sub => "BINARY(x - y)",
+			ArithmeticType::And => "BINARY(x & y)",
+			ArithmeticType::Or => "BINARY(x | y)",
+			ArithmeticType::Eq => "BINARY(x == y ? -1 : 0)",
+			ArithmeticType::Gt => "BINARY(x > y ? -1 : 0)",
+			ArithmeticType::Lt => "BINARY(x < y ? -1 : 0)",
+			ArithmeticType::Neg => "UNARY(-x)",
+			ArithmeticType::Not => "UNARY(~x)",
+		};
+		Ok(format!("\t{};\n", statement))
+	}
 }
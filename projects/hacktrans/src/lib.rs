@@ -0,0 +1,606 @@
+//! Translator of VM code to hack asm
+pub mod c_backend;
+pub mod callgraph;
+pub mod checker;
+pub mod command;
+pub mod linker;
+pub mod optimizer;
+pub mod report;
+pub mod source_map;
+use command::Arithmetic;
+use command::ArithmeticType;
+use command::Command;
+use command::CommandType;
+use command::Function;
+use command::MemoryAccess;
+use command::ProgramFlow;
+use command::SourceCommand;
+use command::SourceLine;
+use command::NULL_ID;
+pub use command::{Codegen, MemoryLayout, TranslateError};
+use report::{FunctionCode, Statistics};
+use source_map::SourceMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+const COMMENT_SYMBOL: &str = "//";
+
+/// Remove the comment from a line of VM code
+fn remove_comment(line: &str) -> &str {
+    match line.find(COMMENT_SYMBOL) {
+        Some(pos) => {
+            // create substr based on comment position
+            let (first, _last) = line.split_at(pos);
+            first
+        }
+        // No comment so we just use the original line
+        None => line,
+    }
+}
+
+/// Parse a line of VM code.
+/// Returns None for lines without a command
+fn parse_line(
+    line: &str,
+    origin_name: &str,
+    line_number: usize,
+    counter: &mut command::Counter,
+) -> Result<Option<Box<dyn Command>>, TranslateError> {
+    let code = remove_comment(line).trim();
+    if code.is_empty() {
+        // is comment line
+        return Ok(None);
+    }
+    let error = |message: String| TranslateError {
+        origin_name: origin_name.to_string(),
+        line: line_number,
+        text: code.to_string(),
+        message,
+    };
+    let mut itr = code.split_whitespace();
+    // We should always have a valid first clause
+    let command = itr.next().unwrap();
+    let args: Vec<&str> = itr.collect();
+    let arg_num = match command {
+        "push" | "pop" | "function" | "call" => 2,
+        "label" | "goto" | "if-goto" => 1,
+        "add" | "sub" | "neg" | "eq" | "gt" | "lt" | "and" | "or" | "not" | "return" => 0,
+        _other => return Err(error(format!("Unknown command: {}", _other))),
+    };
+    if args.len() > arg_num {
+        return Err(error(format!("Unexpected argument: {}", args[arg_num])));
+    }
+    let arg = |idx: usize, name: &str| {
+        args.get(idx)
+            .copied()
+            .ok_or_else(|| error(format!("Missing {}", name)))
+    };
+    let number = |idx: usize, name: &str| {
+        let a = arg(idx, name)?;
+        str::parse::<u16>(a).map_err(|_| error(format!("Invalid {}: {}", name, a)))
+    };
+    let cmd: Box<dyn Command> = match command {
+        "push" => Box::new(
+            MemoryAccess::new(
+                CommandType::Push,
+                origin_name,
+                arg(0, "segment")?,
+                arg(1, "index")?,
+            )
+            .map_err(error)?,
+        ),
+        "pop" => Box::new(
+            MemoryAccess::new(
+                CommandType::Pop,
+                origin_name,
+                arg(0, "segment")?,
+                arg(1, "index")?,
+            )
+            .map_err(error)?,
+        ),
+        "add" => Box::new(Arithmetic::new(ArithmeticType::Add, NULL_ID)),
+        "sub" => Box::new(Arithmetic::new(ArithmeticType::Sub, NULL_ID)),
+        "neg" => Box::new(Arithmetic::new(ArithmeticType::Neg, NULL_ID)),
+        "eq" => {
+            counter.eq += 1; // We increment first because 0 is reserved for null
+            Box::new(Arithmetic::new(ArithmeticType::Eq, counter.eq))
+        }
+        "gt" => {
+            counter.gt += 1; // We increment first because 0 is reserved for null
+            Box::new(Arithmetic::new(ArithmeticType::Gt, counter.gt))
+        }
+        "lt" => {
+            counter.lt += 1; // We increment first because 0 is reserved for null
+            Box::new(Arithmetic::new(ArithmeticType::Lt, counter.lt))
+        }
+        "and" => Box::new(Arithmetic::new(ArithmeticType::And, NULL_ID)),
+        "or" => Box::new(Arithmetic::new(ArithmeticType::Or, NULL_ID)),
+        "not" => Box::new(Arithmetic::new(ArithmeticType::Not, NULL_ID)),
+        "label" => Box::new(ProgramFlow::new(
+            CommandType::Label,
+            arg(0, "label")?.to_string(),
+        )),
+        "goto" => Box::new(ProgramFlow::new(
+            CommandType::GoTo,
+            arg(0, "label")?.to_string(),
+        )),
+        "if-goto" => Box::new(ProgramFlow::new(
+            CommandType::If,
+            arg(0, "label")?.to_string(),
+        )),
+        "function" => Box::new(Function::new(
+            CommandType::Function,
+            Some(arg(0, "function name")?.to_string()),
+            Some(number(1, "number of local variables")?),
+        )),
+        "return" => Box::new(Function::new(CommandType::Return, None, None)),
+        "call" => Box::new(Function::new(
+            CommandType::Call,
+            Some(arg(0, "function name")?.to_string()),
+            Some(number(1, "number of arguments")?),
+        )),
+        _other => unreachable!(),
+    };
+    Ok(Some(cmd))
+}
+
+/// Parse a line to a command with the source line it came from
+fn parse_source_line(
+    line_text: &str,
+    origin_name: &str,
+    line_number: usize,
+    counter: &mut command::Counter,
+) -> Result<Option<SourceCommand>, TranslateError> {
+    let command = parse_line(line_text, origin_name, line_number, counter)?;
+    Ok(command.map(|command| SourceCommand {
+        sources: vec![SourceLine {
+            origin_name: origin_name.to_string(),
+            number: line_number,
+            text: remove_comment(line_text).trim().to_string(),
+        }],
+        command,
+    }))
+}
+
+/// Parse the lines of a source.
+/// IDs of the commands are counted from the start of the source, which are returned with the counts
+fn parse_source(
+    source: &linker::Unit,
+) -> (Vec<SourceCommand>, Vec<TranslateError>, command::Counter) {
+    let mut commands = vec![];
+    let mut counter = command::Counter::default();
+    let mut errors = Vec::new();
+    for (idx, line_text) in source.text.lines().enumerate() {
+        match parse_source_line(line_text, &source.origin_name, idx + 1, &mut counter) {
+            Ok(Some(cmd)) => commands.push(cmd),
+            Ok(None) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+    (commands, errors, counter)
+}
+
+/// Apply `f` to each item on as many threads as the machine runs in parallel.
+/// Results are in order of the items so the output does not depend on the scheduling
+fn parallel_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = std::cmp::max(1, items.len().div_ceil(threads));
+    let f = &f;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Vec<R>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    })
+}
+
+/// Options of the asm translation
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub codegen: Codegen,
+    /// Optimization level. 1 enables peephole optimizations that shrink the output,
+    /// 2 also folds arithmetic on constants in the VM code before translating
+    pub optimize: u8,
+    /// Write each VM command with its file and line number as a comment before the asm it produced
+    pub annotate: bool,
+    /// Keep functions that are never called from Sys.init
+    pub keep_all_functions: bool,
+    pub layout: MemoryLayout,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            codegen: Codegen::Speed,
+            optimize: 0,
+            annotate: false,
+            keep_all_functions: false,
+            layout: MemoryLayout::default(),
+        }
+    }
+}
+
+impl Options {
+    /// Returns true if each function can be translated without the rest of the program (see translate_stream).
+    /// Removing uncalled functions and choosing the shared routines of Codegen::Size need the whole program
+    pub fn is_streamable(&self) -> bool {
+        self.keep_all_functions && self.codegen == Codegen::Speed
+    }
+}
+
+/// Translated asm program
+pub struct Program {
+    /// Bootstrap code (and shared routines) before the first function
+    pub bootstrap: String,
+    /// Code of each function in order of the input
+    pub functions: Vec<FunctionCode>,
+    /// Functions removed because they are never called
+    pub removed_functions: Vec<String>,
+    /// ROM address ranges of the VM commands
+    pub source_map: SourceMap,
+    /// Statistics of the translated VM commands
+    pub statistics: Statistics,
+}
+
+impl Program {
+    /// Get asm of the whole program
+    pub fn text(&self) -> String {
+        let mut asm = self.bootstrap.clone();
+        for f in &self.functions {
+            asm.push_str(&f.asm);
+        }
+        asm
+    }
+
+    /// Write the asm of the whole program without joining it to a single string first
+    pub fn write_asm(&self, out: &mut impl Write) -> std::io::Result<()> {
+        out.write_all(self.bootstrap.as_bytes())?;
+        for f in &self.functions {
+            out.write_all(f.asm.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Assemble the program to ROM words with the hackasm library.
+    /// `file` is the name of the asm used in error messages.
+    /// Errors are reported at the VM command the failing asm line was generated from
+    pub fn assemble(&self, file: &str) -> Result<Vec<u16>, TranslateError> {
+        let text = self.text();
+        let error = match hackasm::assemble(&text, file, hackasm::Mode::Standard) {
+            Ok(program) => return Ok(program.words),
+            Err(e) => e,
+        };
+        // ROM address of the failing line is the number of instructions before it
+        let address = error.line().map(|line| {
+            text.lines()
+                .take(line - 1)
+                .filter(|l| report::is_instruction(l))
+                .count()
+        });
+        match address.and_then(|a| self.source_map.find(a)) {
+            Some(entry) => Err(TranslateError {
+                origin_name: entry.source.origin_name.clone(),
+                line: entry.source.number,
+                text: entry.source.text.clone(),
+                message: format!("Generated asm failed to assemble: {}", error),
+            }),
+            // the bootstrap code and shared routines are not generated from the input
+            None => panic!("Failed to assemble the bootstrap code: {}", error),
+        }
+    }
+}
+
+/// Asm code to set the stack pointer to its initial position and call Sys.init
+fn bootstrap_asm(prefix: &str, options: &Options) -> String {
+    let return_label = format!("{}$ret.1", prefix);
+    let call = command::generate_call_asm(&return_label, 0, "Sys.init", &options.layout);
+    format!(
+        "@{}
+D=A
+@SP
+M=D
+{}",
+        options.layout.stack_base, call
+    )
+}
+
+/// Generate the asm of consecutive commands of a single function.
+/// With `first_index`, the asm of each command starts with a source map marker of its index in the program
+fn generate_asm(
+    commands: &[SourceCommand],
+    first_index: Option<usize>,
+    options: &Options,
+) -> (String, Vec<TranslateError>) {
+    let mut context = command::Context::new(options.codegen, options.layout);
+    let mut code = String::new();
+    let mut errors = Vec::new();
+    for (offset, cmd) in commands.iter().enumerate() {
+        context.update(&cmd.command);
+        if let Some(first_index) = first_index {
+            code.push_str(&format!("{}{}\n", source_map::MARKER, first_index + offset));
+        }
+        if options.annotate {
+            code.push_str(&cmd.annotation());
+        }
+        match cmd.command.to_asm_text(&context) {
+            Ok(asm) => code.push_str(&asm),
+            Err(message) => errors.push(cmd.error(message)),
+        }
+    }
+    (code, errors)
+}
+
+/// Translates multiple VM files into a single asm program
+pub struct Translator {
+    /// Unique string for marking labels of the output (ex: the input directory name)
+    prefix: String,
+    options: Options,
+    sources: Vec<linker::Unit>,
+}
+
+impl Translator {
+    pub fn new(prefix: &str, options: Options) -> Translator {
+        Translator {
+            prefix: prefix.to_string(),
+            options,
+            sources: Vec::new(),
+        }
+    }
+
+    /// Add a VM file. Statics of the file are named after the file name
+    pub fn add_file(&mut self, path: &Path) -> std::io::Result<()> {
+        let text = std::fs::read_to_string(path)?;
+        let origin_name = path.file_stem().unwrap().to_string_lossy();
+        self.add_source(&origin_name, &text);
+        Ok(())
+    }
+
+    /// Add VM code of a file named `origin_name` (without the .vm extension)
+    pub fn add_source(&mut self, origin_name: &str, text: &str) {
+        self.sources.push(linker::Unit {
+            origin_name: origin_name.to_string(),
+            text: text.to_string(),
+        });
+    }
+
+    /// Parse all sources to a list of commands.
+    /// Labels are checked when all sources are parsed (see checker::check_labels).
+    /// Returns the errors of all sources if any
+    pub fn parse(&self) -> Result<Vec<SourceCommand>, Vec<TranslateError>> {
+        let mut commands = vec![];
+        let mut counter = command::Counter::default();
+        let mut errors = Vec::new();
+        // Sources are parsed in parallel so the IDs are shifted by the counts of the sources before
+        // to make them the same as parsing all sources in order
+        for (mut source_commands, mut source_errors, source_counter) in
+            parallel_map(&self.sources, parse_source)
+        {
+            for cmd in &mut source_commands {
+                cmd.command.offset_id(&counter);
+            }
+            counter.add(&source_counter);
+            commands.append(&mut source_commands);
+            errors.append(&mut source_errors);
+        }
+        if errors.is_empty() {
+            errors = checker::check_labels(&commands);
+        }
+        if errors.is_empty() {
+            Ok(commands)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Check the stack usage of each function (see checker::check_stack)
+    pub fn check(&self) -> Result<(), Vec<TranslateError>> {
+        let errors = checker::check_stack(&self.parse()?);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Translate all sources to asm, keeping the code of each function separate
+    pub fn translate_program(&self) -> Result<Program, Vec<TranslateError>> {
+        let mut commands = self.parse()?;
+        let mut removed_functions = Vec::new();
+        if !self.options.keep_all_functions {
+            let (kept, removed) = optimizer::remove_dead_functions(commands);
+            commands = kept;
+            removed_functions = removed;
+        }
+        // statics of the removed functions are never allocated
+        let errors = checker::check_statics(&commands, self.options.layout.stack_base);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let statistics = Statistics::new(&commands);
+        let mut bootstrap = bootstrap_asm(&self.prefix, &self.options);
+        if self.options.optimize > 1 {
+            commands = optimizer::fold_constants(commands);
+        }
+        if self.options.optimize > 0 {
+            commands = optimizer::fuse_moves(commands);
+        }
+        if self.options.codegen == Codegen::Size {
+            // Sys.init never returns so the routines are not reached without a jump
+            bootstrap.push_str(&command::generate_shared_routines(
+                &commands,
+                &self.options.layout,
+            ));
+        }
+        // Source line and function of each command marked for the source map
+        let mut origins = Vec::new();
+        // Ranges of the commands of each function, and of the commands before the first function
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        let mut function = None;
+        for (idx, cmd) in commands.iter().enumerate() {
+            if let CommandType::Function = cmd.command.command_type() {
+                function = cmd.command.symbol().cloned();
+                ranges.push((idx, idx));
+            } else if ranges.is_empty() {
+                ranges.push((idx, idx));
+            }
+            ranges.last_mut().unwrap().1 = idx + 1;
+            origins.push((cmd.sources[0].clone(), function.clone()));
+        }
+        // Each function only depends on its own commands so they are generated in parallel
+        let codes = parallel_map(&ranges, |(start, end)| {
+            generate_asm(&commands[*start..*end], Some(*start), &self.options)
+        });
+        let mut functions: Vec<FunctionCode> = Vec::new();
+        let mut errors = Vec::new();
+        for ((start, _end), (code, mut code_errors)) in ranges.iter().zip(codes) {
+            match origins[*start].1.clone() {
+                Some(name) => functions.push(FunctionCode { name, asm: code }),
+                None => bootstrap.push_str(&code),
+            }
+            errors.append(&mut code_errors);
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        if self.options.optimize > 0 {
+            // Functions start with a label that no optimization crosses so each can be optimized separately
+            bootstrap = optimizer::peephole(&bootstrap);
+            let asm: Vec<String> = parallel_map(&functions, |f| optimizer::peephole(&f.asm));
+            for (f, asm) in functions.iter_mut().zip(asm) {
+                f.asm = asm;
+            }
+        }
+        let source_map = SourceMap::extract(&mut bootstrap, &mut functions, &origins);
+        Ok(Program {
+            bootstrap,
+            functions,
+            removed_functions,
+            source_map,
+            statistics,
+        })
+    }
+
+    /// Translate all sources to a single asm program
+    pub fn translate(&self) -> Result<String, Vec<TranslateError>> {
+        Ok(self.translate_program()?.text())
+    }
+}
+
+/// Translate the VM code of a single file named `name` (without the .vm extension) to asm
+/// without reading or writing any files.
+/// Returns the first error if the code has any
+pub fn translate_str(
+    name: &str,
+    source: &str,
+    options: &Options,
+) -> Result<String, TranslateError> {
+    let mut translator = Translator::new(name, options.clone());
+    translator.add_source(name, source);
+    translator
+        .translate()
+        .map_err(|errors| errors.into_iter().next().unwrap())
+}
+
+/// Reader of the VM code of a source
+pub type SourceInput<'a> = Box<dyn BufRead + 'a>;
+
+/// Writes the asm of each function of a streaming translation as soon as its commands are read
+struct FunctionWriter<'a, W: Write> {
+    options: &'a Options,
+    out: &'a mut W,
+    /// Bootstrap code until it is written with the commands before the first function
+    bootstrap: Option<String>,
+    statics: checker::StaticBudget,
+    size: report::SizeReport,
+    errors: Vec<TranslateError>,
+}
+
+impl<'a, W: Write> FunctionWriter<'a, W> {
+    /// Check, optimize, and write the commands of a function, or the commands before the first function
+    fn write(&mut self, mut commands: Vec<SourceCommand>) -> std::io::Result<()> {
+        self.errors.append(&mut checker::check_labels(&commands));
+        for cmd in &commands {
+            self.statics.add(cmd);
+        }
+        if self.options.optimize > 1 {
+            commands = optimizer::fold_constants(commands);
+        }
+        if self.options.optimize > 0 {
+            commands = optimizer::fuse_moves(commands);
+        }
+        let (code, mut errors) = generate_asm(&commands, None, self.options);
+        self.errors.append(&mut errors);
+        let is_bootstrap = self.bootstrap.is_some();
+        let mut asm = self.bootstrap.take().unwrap_or_default();
+        asm.push_str(&code);
+        if self.options.optimize > 0 {
+            asm = optimizer::peephole(&asm);
+        }
+        let count = report::count_instructions(&asm);
+        match commands.first().and_then(|cmd| cmd.command.symbol()) {
+            Some(name) if !is_bootstrap => self.size.functions.push((name.clone(), count)),
+            _ => self.size.bootstrap = count,
+        }
+        self.out.write_all(asm.as_bytes())
+    }
+}
+
+/// Translate the sources to asm in a single pass, writing the asm of each function as soon as it is read.
+/// Only the commands of the current function are kept in memory so large programs do not need to fit at once.
+/// `sources` are the file names (without the .vm extension) and readers of each file, which are read in order.
+/// The options must be streamable (see Options::is_streamable), and the output is the same as Translator::translate.
+/// Translation errors do not stop the output, so it is incomplete when errors are returned
+pub fn translate_stream<'a>(
+    prefix: &str,
+    options: &Options,
+    sources: impl IntoIterator<Item = std::io::Result<(String, SourceInput<'a>)>>,
+    out: &mut impl Write,
+) -> std::io::Result<Result<report::SizeReport, Vec<TranslateError>>> {
+    assert!(options.is_streamable(), "Options need the whole program");
+    let mut writer = FunctionWriter {
+        options,
+        out,
+        bootstrap: Some(bootstrap_asm(prefix, options)),
+        statics: checker::StaticBudget::new(options.layout.stack_base),
+        size: report::SizeReport {
+            bootstrap: 0,
+            functions: Vec::new(),
+        },
+        errors: Vec::new(),
+    };
+    // IDs are counted through all sources in order, the same as parsing them separately
+    let mut counter = command::Counter::default();
+    let mut function = Vec::new();
+    for source in sources {
+        let (origin_name, input) = source?;
+        for (idx, line_text) in input.lines().enumerate() {
+            let cmd = match parse_source_line(&line_text?, &origin_name, idx + 1, &mut counter) {
+                Ok(Some(cmd)) => cmd,
+                Ok(None) => continue,
+                Err(e) => {
+                    writer.errors.push(e);
+                    continue;
+                }
+            };
+            if let CommandType::Function = cmd.command.command_type() {
+                writer.write(std::mem::take(&mut function))?;
+            }
+            function.push(cmd);
+        }
+    }
+    // commands before the first function are written with the bootstrap code even if there are none
+    if !function.is_empty() || writer.bootstrap.is_some() {
+        writer.write(function)?;
+    }
+    let mut errors = writer.errors;
+    errors.append(&mut writer.statics.finish());
+    if errors.is_empty() {
+        Ok(Ok(writer.size))
+    } else {
+        Ok(Err(errors))
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+
+/// Functions used as the program entry, in order of priority
+const ENTRY_FUNCTIONS: [&str; 2] = ["Sys.init", "Main.main"];
+const COMMENT_SYMBOL: &str = "//";
+/// Extension of the linked output
+pub const LINKED_EXTENSION: &str = "linked.vm";
+
+/// Code of a single VM file
+pub struct Unit {
+	/// File name without the .vm extension. Used to tell statics of each file apart.
+	pub origin_name: String,
+	pub text: String,
+}
+
+/// A VM function and its code
+struct VmFunction {
+	origin_name: String,
+	name: String,
+	/// Commands without comments, including the 'function' command itself
+	lines: Vec<String>,
+	/// Line number in the file of each command
+	line_numbers: Vec<usize>,
+	/// Names of functions called within this function
+	calls: Vec<String>,
+}
+
+/// Result of linking
+pub struct Linked {
+	/// Linked VM program
+	pub text: String,
+	/// Kept functions of each input file with their original static indices.
+	/// Translating these instead of `text` keeps the static symbols named after each file.
+	/// Commands stay on the same line number as in the file.
+	pub units: Vec<Unit>,
+	/// Functions that were removed because nothing refers to them
+	pub stripped: Vec<String>,
+	/// Functions that are called but not defined in any of the inputs
+	pub undefined: Vec<String>,
+}
+
+/// Read a single .vm file or all .vm files in a directory
+pub fn read_units(path: &Path) -> std::io::Result<Vec<Unit>> {
+	let mut paths = Vec::new();
+	if path.is_dir() {
+		for entry in std::fs::read_dir(path)? {
+			let p = entry?.path();
+			let name = p.file_name().unwrap().to_string_lossy();
+			// skip output of previous links so we don't link the same functions twice
+			if name.ends_with(".vm") && !name.ends_with(LINKED_EXTENSION) {
+				paths.push(p);
+			}
+		}
+		// sort so the output doesn't depend on the directory order
+		paths.sort();
+	} else {
+		paths.push(path.to_path_buf());
+	}
+	let mut units = Vec::new();
+	for p in paths {
+		units.push(Unit {
+			origin_name: p.file_stem().unwrap().to_string_lossy().into_owned(),
+			text: std::fs::read_to_string(&p)?,
+		});
+	}
+	Ok(units)
+}
+
+/// Split a unit to functions
+fn split_functions(unit: &Unit) -> Result<Vec<VmFunction>, String> {
+	let mut functions: Vec<VmFunction> = Vec::new();
+	for (idx, line) in unit.text.lines().enumerate() {
+		let code = match line.find(COMMENT_SYMBOL) {
+			Some(pos) => &line[..pos],
+			None => line,
+		}
+		.trim();
+		if code.is_empty() {
+			continue;
+		}
+		let mut itr = code.split_whitespace();
+		let command = itr.next().unwrap();
+		if command == "function" {
+			let name = itr
+				.next()
+				.ok_or(format!("{}:{}: Missing function name", unit.origin_name, idx + 1))?;
+			functions.push(VmFunction {
+				origin_name: unit.origin_name.clone(),
+				name: name.to_string(),
+				lines: Vec::new(),
+				line_numbers: Vec::new(),
+				calls: Vec::new(),
+			});
+		}
+		let current = functions.last_mut().ok_or(format!(
+			"{}:{}: Command outside of a function: {}",
+			unit.origin_name,
+			idx + 1,
+			code
+		))?;
+		if command == "call" {
+			let callee = itr
+				.next()
+				.ok_or(format!("{}:{}: Missing function name", unit.origin_name, idx + 1))?;
+			current.calls.push(callee.to_string());
+		}
+		current.lines.push(code.to_string());
+		current.line_numbers.push(idx + 1);
+	}
+	Ok(functions)
+}
+
+/// Replace static indices so statics of different files don't clash in a single file
+fn renumber_static(
+	line: &str,
+	origin_name: &str,
+	statics: &mut HashMap<(String, String), usize>,
+) -> String {
+	let words: Vec<&str> = line.split_whitespace().collect();
+	match words.as_slice() {
+		[command, "static", index] => {
+			let next = statics.len();
+			let new_index = *statics
+				.entry((origin_name.to_string(), index.to_string()))
+				.or_insert(next);
+			format!("{} static {}", command, new_index)
+		}
+		_ => line.to_string(),
+	}
+}
+
+/// Link all units into a single VM program.
+/// Functions defined more than once are an error.
+/// Functions not reachable from the entry function (Sys.init, or Main.main) are removed.
+/// If there is no entry function or `keep_all` is set, all functions are kept.
+pub fn link(units: &[Unit], keep_all: bool) -> Result<Linked, String> {
+	let mut functions = Vec::new();
+	for u in units {
+		functions.append(&mut split_functions(u)?);
+	}
+	let mut index_of: HashMap<&str, usize> = HashMap::new();
+	for (idx, f) in functions.iter().enumerate() {
+		if let Some(prev) = index_of.insert(&f.name, idx) {
+			return Err(format!(
+				"Function {} is defined in both {} and {}",
+				f.name, functions[prev].origin_name, f.origin_name
+			));
+		}
+	}
+	// Walk the call graph from the entry, or from every function when all are kept
+	let mut queue: VecDeque<usize> = match ENTRY_FUNCTIONS.iter().find(|e| index_of.contains_key(*e)) {
+		Some(entry) if !keep_all => VecDeque::from([index_of[entry]]),
+		_ => (0..functions.len()).collect(),
+	};
+	let mut reachable: HashSet<usize> = queue.iter().copied().collect();
+	let mut undefined = Vec::new();
+	while let Some(idx) = queue.pop_front() {
+		for callee in &functions[idx].calls {
+			match index_of.get(callee.as_str()) {
+				Some(c) => {
+					if reachable.insert(*c) {
+						queue.push_back(*c);
+					}
+				}
+				None => {
+					if !undefined.contains(callee) {
+						undefined.push(callee.clone());
+					}
+				}
+			}
+		}
+	}
+	let mut text = String::new();
+	let mut stripped = Vec::new();
+	let mut statics = HashMap::new();
+	let mut kept_units: Vec<Unit> = Vec::new();
+	// number of lines in the last unit
+	let mut unit_lines = 0;
+	for (idx, f) in functions.iter().enumerate() {
+		if !reachable.contains(&idx) {
+			stripped.push(f.name.clone());
+			continue;
+		}
+		text.push_str(&format!("// {} ({})\n", f.name, f.origin_name));
+		for line in &f.lines {
+			text.push_str(&renumber_static(line, &f.origin_name, &mut statics));
+			text.push('\n');
+		}
+		// functions of a file are next to each other so we only need to check the last unit
+		if kept_units.last().map(|u| &u.origin_name) != Some(&f.origin_name) {
+			kept_units.push(Unit {
+				origin_name: f.origin_name.clone(),
+				text: String::new(),
+			});
+			unit_lines = 0;
+		}
+		let unit = kept_units.last_mut().unwrap();
+		for (line, number) in f.lines.iter().zip(&f.line_numbers) {
+			// pad with empty lines for the removed lines
+			while unit_lines + 1 < *number {
+				unit.text.push('\n');
+				unit_lines += 1;
+			}
+			unit.text.push_str(line);
+			unit.text.push('\n');
+			unit_lines += 1;
+		}
+	}
+	Ok(Linked {
+		text,
+		units: kept_units,
+		stripped,
+		undefined,
+	})
+}
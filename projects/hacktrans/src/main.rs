@@ -1,16 +1,11 @@
 use clap::{AppSettings, Clap};
+use hacktrans::command::{Codegen, Command, Context};
+use hacktrans::{
+    c_backend, callgraph, linker, report, MemoryLayout, Options, SourceInput, Translator,
+};
 use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
-mod command;
-use command::Arithmetic;
-use command::ArithmeticType;
-use command::Command;
-use command::CommandType;
-use command::Function;
-use command::MemoryAccess;
-use command::ProgramFlow;
-use command::NULL_ID;
 
 #[derive(Clap)]
 #[clap(version = "1.0", author = "Masato Nakasaka <rillomas@gmail.com>")]
@@ -18,195 +13,321 @@ use command::NULL_ID;
 struct Opts {
     #[clap(short)]
     input_file_or_dir: String,
+    /// Output file. Use "-" to write to stdout.
+    /// Defaults to the input file, or a file next to the input directory, with the extension of the target
+    #[clap(short)]
+    output_file: Option<String>,
+    /// Output target. "asm" for hack asm, "c" for a single C source file
+    #[clap(long, default_value = "asm", possible_values = &["asm", "c"])]
+    target: String,
+    /// Output of the asm target. "hack" assembles the generated asm to a .hack file
+    #[clap(long, default_value = "asm", possible_values = &["asm", "hack"])]
+    emit: String,
+    /// VM file or directory of VM files (ex: the OS) to link with the input.
+    /// Can be specified multiple times.
+    #[clap(short, long)]
+    library: Vec<String>,
+    /// Only link the input and libraries and write the linked VM program (<name>.linked.vm)
+    #[clap(long)]
+    link_only: bool,
+    /// Optimization level of the asm target. 1 enables peephole optimizations that shrink the output,
+    /// 2 also folds arithmetic on constants in the VM code
+    #[clap(short = 'O', default_value = "0", possible_values = &["0", "1", "2"])]
+    optimize: u8,
+    /// Code generation of the asm target. "speed" expands every command inline,
+    /// "size" jumps to routines shared by all eq, gt, lt, call, and return commands
+    #[clap(long, default_value = "speed", possible_values = &["speed", "size"])]
+    codegen: String,
+    /// Write each VM command with its file and line number as a comment before the asm it produced
+    #[clap(long)]
+    annotate: bool,
+    /// Order of the files in a directory. "sys-first" puts Sys.vm first and the rest by name, "name" sorts all by name.
+    /// When linking, "sys-first" puts Sys.vm of the input or a library first and keeps the rest in order
+    #[clap(long, default_value = "sys-first", possible_values = &["sys-first", "name"])]
+    order: String,
+    /// Keep functions that are never called from Sys.init instead of removing them.
+    /// With --codegen speed, the asm is then written while the files are read so large programs use little memory
+    #[clap(long)]
+    keep_all: bool,
+    /// Address the stack starts at in the asm target
+    #[clap(long, default_value = "256")]
+    stack_base: u16,
+    /// Address of the first entry of the temp segment in the asm target
+    #[clap(long, default_value = "5")]
+    temp_base: u16,
+    /// Address of the first entry of the pointer segment in the asm target (the bases of the this and that segments)
+    #[clap(long, default_value = "3")]
+    pointer_base: u16,
+    /// Check that each function uses the stack consistently before translating
+    #[clap(long)]
+    check: bool,
+    /// Print a summary of the VM commands, functions, instructions, and static variables
+    #[clap(long)]
+    stats: bool,
+    /// Also write the number of asm instructions of each function to a JSON file
+    #[clap(long)]
+    report: Option<String>,
+    /// Also write a graph of which functions call which, with the number of calls, in DOT format
+    #[clap(long)]
+    emit_callgraph: Option<String>,
+    /// Also write a source map from the ROM addresses of the output to the VM commands to a JSON file
+    #[clap(long)]
+    map: Option<String>,
 }
-const COMMENT_SYMBOL: &str = "//";
+/// File with the entry function Sys.init
+const SYS_FILE: &str = "Sys";
 
-struct Reader {
-    reader: BufReader<std::fs::File>,
-    origin_name: String,
-}
+/// Output path that writes to stdout
+const STDOUT_PATH: &str = "-";
 
-fn remove_comment(line: &str) -> &str {
-    match line.find(COMMENT_SYMBOL) {
-        Some(pos) => {
-            // create substr based on comment position
-            let (first, _last) = line.split_at(pos);
-            first
+impl Opts {
+    /// Print progress.
+    /// Goes to stderr when the output is written to stdout so it does not mix with the output
+    fn report(&self, message: &str) {
+        if self.output_file.as_deref() == Some(STDOUT_PATH) {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
         }
-        // No comment so we just use the original line
-        None => line,
     }
 }
 
-fn parse_line(
-    line: &str,
-    origin_name: &str,
-    counter: &mut command::Counter,
-) -> Option<Box<dyn Command>> {
-    let mut code = remove_comment(line);
-    code = code.trim();
-    if code.is_empty() {
-        // is comment line
-        return None;
-    }
-    let mut itr = code.split_whitespace();
-    // We should always have a valid first clause
-    let command = itr.next().unwrap();
-    match command {
-        "push" => Some(Box::new(command::MemoryAccess::new(
-            CommandType::Push,
-            origin_name,
-            itr.next().unwrap(),
-            itr.next().unwrap(),
-        ))),
-        "pop" => Some(Box::new(MemoryAccess::new(
-            CommandType::Pop,
-            origin_name,
-            itr.next().unwrap(),
-            itr.next().unwrap(),
-        ))),
-        "add" => Some(Box::new(Arithmetic::new(ArithmeticType::Add, NULL_ID))),
-        "sub" => Some(Box::new(Arithmetic::new(ArithmeticType::Sub, NULL_ID))),
-        "neg" => Some(Box::new(Arithmetic::new(ArithmeticType::Neg, NULL_ID))),
-        "eq" => {
-            counter.eq += 1; // We increment first because 0 is reserved for null
-            Some(Box::new(Arithmetic::new(ArithmeticType::Eq, counter.eq)))
-        }
-        "gt" => {
-            counter.gt += 1; // We increment first because 0 is reserved for null
-            Some(Box::new(Arithmetic::new(ArithmeticType::Gt, counter.gt)))
-        }
-        "lt" => {
-            counter.lt += 1; // We increment first because 0 is reserved for null
-            Some(Box::new(Arithmetic::new(ArithmeticType::Lt, counter.lt)))
-        }
-        "and" => Some(Box::new(Arithmetic::new(ArithmeticType::And, NULL_ID))),
-        "or" => Some(Box::new(Arithmetic::new(ArithmeticType::Or, NULL_ID))),
-        "not" => Some(Box::new(Arithmetic::new(ArithmeticType::Not, NULL_ID))),
-        "label" => Some(Box::new(ProgramFlow::new(
-            CommandType::Label,
-            itr.next().unwrap().to_string(),
-        ))),
-        "goto" => Some(Box::new(ProgramFlow::new(
-            CommandType::GoTo,
-            itr.next().unwrap().to_string(),
-        ))),
-        "if-goto" => Some(Box::new(ProgramFlow::new(
-            CommandType::If,
-            itr.next().unwrap().to_string(),
-        ))),
-        "function" => Some(Box::new(Function::new(
-            CommandType::Function,
-            Some(itr.next().unwrap().to_string()),
-            Some(str::parse::<u16>(itr.next().unwrap()).unwrap()),
-        ))),
-        "return" => Some(Box::new(Function::new(CommandType::Return, None, None))),
-        "call" => Some(Box::new(Function::new(
-            CommandType::Call,
-            Some(itr.next().unwrap().to_string()),
-            Some(str::parse::<u16>(itr.next().unwrap()).unwrap()),
-        ))),
-        _ => None,
+/// Open the output file, or stdout for STDOUT_PATH
+fn create_output(path: &Path) -> std::io::Result<Box<dyn Write>> {
+    if path == Path::new(STDOUT_PATH) {
+        Ok(Box::new(std::io::stdout()))
+    } else {
+        Ok(Box::new(File::create(path)?))
+    }
+}
+
+/// Print all errors and stop
+fn exit_with_errors(errors: &[hacktrans::TranslateError]) -> ! {
+    for e in errors {
+        eprintln!("{}", e);
+    }
+    eprintln!("{} errors found", errors.len());
+    std::process::exit(1);
+}
+
+/// Print the number of instructions, and write the size report if requested
+fn report_size(opts: &Opts, size: &report::SizeReport) -> std::io::Result<()> {
+    opts.report(&format!("instructions: {}", size.total()));
+    for (name, count) in &size.functions {
+        opts.report(&format!("  {}: {}", name, count));
     }
+    if size.total() > report::ROM_SIZE {
+        eprintln!(
+            "warning: {} instructions do not fit in the ROM of {} instructions",
+            size.total(),
+            report::ROM_SIZE
+        );
+    }
+    if let Some(report_path) = &opts.report {
+        opts.report(&format!("report: {}", report_path));
+        std::fs::write(report_path, size.to_json())?;
+    }
+    Ok(())
 }
 
 fn main() -> std::io::Result<()> {
     let opts = Opts::parse();
     let input_path = Path::new(&opts.input_file_or_dir);
-    println!("input: {}", input_path.display());
-    let mut output_file_path: PathBuf;
-    let mut readers = Vec::new();
+    opts.report(&format!("input: {}", input_path.display()));
+    let prefix = input_path
+        .file_stem()
+        .unwrap()
+        .to_os_string()
+        .into_string()
+        .unwrap();
+    let codegen = match opts.codegen.as_str() {
+        "size" => Codegen::Size,
+        _ => Codegen::Speed,
+    };
+    let options = Options {
+        codegen,
+        optimize: opts.optimize,
+        annotate: opts.annotate,
+        keep_all_functions: opts.keep_all,
+        layout: MemoryLayout {
+            stack_base: opts.stack_base,
+            temp_base: opts.temp_base,
+            pointer_base: opts.pointer_base,
+        },
+    };
+    // VM files to translate in order, and the default output path without the extension
+    let mut paths = Vec::new();
+    let output_base_path: PathBuf;
     if input_path.is_file() {
-        // load single file by single reader
-        let file = File::open(input_path)?;
-        let reader = Reader {
-            reader: BufReader::new(file),
-            origin_name: input_path
-                .file_stem()
-                .unwrap()
-                .to_os_string()
-                .into_string()
-                .unwrap(),
-        };
-        readers.push(reader);
-        output_file_path = PathBuf::from(input_path);
-        output_file_path.set_extension("asm");
+        paths.push(input_path.to_path_buf());
+        output_base_path = input_path.with_extension("");
     } else if input_path.is_dir() {
-        // load all files by multiple reader
+        // use all vm files in the directory.
+        // Sort them so the output doesn't depend on the directory order
         for entry in std::fs::read_dir(input_path)? {
-            let path = entry.unwrap().path();
-            if path.extension().unwrap() == "vm" {
-                // only look at vm files
-                let origin_name = path
-                    .file_stem()
-                    .unwrap()
-                    .to_os_string()
-                    .into_string()
-                    .unwrap();
-                let file = File::open(path)?;
-                let reader = Reader {
-                    reader: BufReader::new(file),
-                    origin_name: origin_name,
-                };
-                readers.push(reader);
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "vm") {
+                paths.push(path);
             }
         }
-        // set output file name as "<input directory name>.asm"
-        output_file_path = PathBuf::from(input_path);
-        let dir_name = output_file_path.file_name().unwrap();
-        let output_file_name = PathBuf::from(format!("{}.{}", dir_name.to_str().unwrap(), "asm"));
-        output_file_path = output_file_path.join(output_file_name);
+        paths.sort();
+        if opts.order == "sys-first" {
+            // stable sort keeps the rest in order of names
+            paths.sort_by_key(|p| p.file_stem().unwrap() != SYS_FILE);
+        }
+        // Output is "<input directory name>.asm" next to the directory so we don't add files to the input
+        output_base_path = input_path.with_file_name(input_path.file_name().unwrap());
     } else {
         panic!("Unsupported path specified");
     }
-    println!("output: {}", output_file_path.display());
-    let mut commands = vec![];
-    let mut counter = command::Counter {
-        eq: 0,
-        lt: 0,
-        gt: 0,
+    let output_path = |extension: &str| match &opts.output_file {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let mut path = output_base_path.clone().into_os_string();
+            path.push(format!(".{}", extension));
+            PathBuf::from(path)
+        }
     };
-    // Read all files to list of commands
-    for reader in readers {
-        for line in reader.reader.lines() {
-            let line_text = line.unwrap();
-            let command = parse_line(&line_text, &reader.origin_name, &mut counter);
-            if command.is_some() {
-                let cmd = command.unwrap();
-                commands.push(cmd);
+    // Translate while reading the files when nothing needs the whole program at once
+    let stream = opts.target == "asm"
+        && opts.emit == "asm"
+        && options.is_streamable()
+        && opts.library.is_empty()
+        && !opts.link_only
+        && !opts.check
+        && !opts.stats
+        && opts.emit_callgraph.is_none()
+        && opts.map.is_none();
+    if stream {
+        let output_file_path = output_path("asm");
+        opts.report(&format!("output: {}", output_file_path.display()));
+        let sources = paths
+            .iter()
+            .map(|path| -> std::io::Result<(String, SourceInput)> {
+                let origin_name = path.file_stem().unwrap().to_string_lossy().into_owned();
+                Ok((origin_name, Box::new(BufReader::new(File::open(path)?))))
+            });
+        let mut out_file = BufWriter::new(create_output(&output_file_path)?);
+        let result = hacktrans::translate_stream(&prefix, &options, sources, &mut out_file)?;
+        out_file.flush()?;
+        let size = result.unwrap_or_else(|errors| {
+            // Do not leave the incomplete output behind
+            drop(out_file);
+            if output_file_path != Path::new(STDOUT_PATH) {
+                let _ = std::fs::remove_file(&output_file_path);
             }
+            exit_with_errors(&errors)
+        });
+        return report_size(&opts, &size);
+    }
+    let mut translator = Translator::new(&prefix, options.clone());
+    for path in &paths {
+        translator.add_file(path)?;
+    }
+    if opts.link_only || !opts.library.is_empty() {
+        // Link everything to a single VM program and translate that instead
+        let mut units = linker::read_units(input_path)?;
+        for l in &opts.library {
+            opts.report(&format!("library: {}", l));
+            units.append(&mut linker::read_units(Path::new(l))?);
+        }
+        if opts.order == "sys-first" {
+            // stable sort keeps the rest in order of the inputs
+            units.sort_by_key(|u| u.origin_name != SYS_FILE);
+        }
+        let linked = linker::link(&units, opts.keep_all).unwrap_or_else(|e| {
+            eprintln!("Link failed: {}", e);
+            std::process::exit(1);
+        });
+        for f in &linked.stripped {
+            opts.report(&format!("stripped: {}", f));
+        }
+        for f in &linked.undefined {
+            opts.report(&format!("undefined: {}", f));
         }
+        if opts.link_only {
+            let output_file_path = output_path(linker::LINKED_EXTENSION);
+            opts.report(&format!("output: {}", output_file_path.display()));
+            let mut out_file = create_output(&output_file_path)?;
+            out_file.write_all(linked.text.as_bytes())?;
+            return Ok(());
+        }
+        // Translate each file separately so statics are named <FileName>.<index>
+        translator = Translator::new(&prefix, options);
+        for u in &linked.units {
+            translator.add_source(&u.origin_name, &u.text);
+        }
+    }
+    if opts.check {
+        translator
+            .check()
+            .unwrap_or_else(|errors| exit_with_errors(&errors));
+        opts.report("check: OK");
     }
+    if let Some(graph_path) = &opts.emit_callgraph {
+        let commands = translator
+            .parse()
+            .unwrap_or_else(|errors| exit_with_errors(&errors));
+        opts.report(&format!("callgraph: {}", graph_path));
+        std::fs::write(graph_path, callgraph::generate_dot(&commands))?;
+    }
+    let emit_hack = opts.target == "asm" && opts.emit == "hack";
+    let output_file_path = output_path(if emit_hack { "hack" } else { &opts.target });
+    opts.report(&format!("output: {}", output_file_path.display()));
 
+    if opts.target == "c" {
+        // Generate a single C file that can be compiled to a native executable
+        let commands = translator
+            .parse()
+            .unwrap_or_else(|errors| exit_with_errors(&errors));
+        let commands: Vec<Box<dyn Command>> = commands.into_iter().map(|c| c.command).collect();
+        // The C runtime has its own memory with the standard layout
+        let mut context = Context::new(codegen, MemoryLayout::default());
+        context.define_functions(&commands);
+        let program = c_backend::generate_c_program(&commands, &mut context).unwrap_or_else(|e| {
+            eprintln!("Failed to generate C code: {}", e);
+            std::process::exit(1);
+        });
+        let mut out_file = create_output(&output_file_path)?;
+        out_file.write_all(program.as_bytes())?;
+        return Ok(());
+    }
     // convert VM commands to hack asm
-    let mut out_file = File::create(output_file_path).unwrap();
-    let prefix = input_path
-        .file_stem()
-        .unwrap()
-        .to_os_string()
-        .into_string()
-        .unwrap();
-    let mut context = command::Context::new(prefix.clone());
-    // Bootstrap asm code to set stackpointer to initial position and call Sys.init
-    let return_label = format!("{}$ret.1", prefix);
+    let program = translator
+        .translate_program()
+        .unwrap_or_else(|errors| exit_with_errors(&errors));
+    for f in &program.removed_functions {
+        opts.report(&format!("stripped: {}", f));
+    }
+    // Assemble before creating the output so a failure does not leave an empty file
+    let words = if emit_hack {
+        let words = program
+            .assemble(&format!("{}.asm", prefix))
+            .unwrap_or_else(|e| exit_with_errors(&[e]));
+        Some(words)
+    } else {
+        None
+    };
+    // Write each part as it is so the whole output is never copied to a single string
+    let mut out_file = BufWriter::new(create_output(&output_file_path)?);
+    match words {
+        Some(words) => {
+            for w in words {
+                out_file.write_all(hackasm::to_binary_text(w).as_bytes())?;
+            }
+        }
+        None => program.write_asm(&mut out_file)?,
+    }
+    out_file.flush()?;
 
-    let call = command::generate_call_asm(&return_label, 0, "Sys.init");
-    let bootstrap = format!(
-        "@256
-D=A
-@SP
-M=D
-{}",
-        call
-    );
-    let _written = out_file.write(bootstrap.as_bytes());
-    for cmd in commands {
-        context.update(&cmd);
-        // println!("{:?}", cmd);
-        // println!("{:?}", context);
-        let _written = out_file
-            .write(cmd.to_asm_text(&context).unwrap().as_bytes())
-            .unwrap();
+    let size = report::SizeReport::new(&program.bootstrap, &program.functions);
+    report_size(&opts, &size)?;
+    if opts.stats {
+        opts.report(program.statistics.to_table(&size).trim_end());
+    }
+    if let Some(map_path) = &opts.map {
+        opts.report(&format!("map: {}", map_path));
+        std::fs::write(map_path, program.source_map.to_json())?;
     }
     Ok(())
 }
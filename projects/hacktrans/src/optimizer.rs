@@ -0,0 +1,300 @@
+use crate::command::{ArithmeticType, CommandType, MemoryAccess, Move, SourceCommand, MAX_CONSTANT};
+use std::collections::{HashMap, VecDeque};
+
+/// Function the program starts from
+const ENTRY_FUNCTION: &str = "Sys.init";
+
+/// Remove functions that can't be reached by calls from Sys.init.
+/// Commands before the first function are kept. If there is no Sys.init all functions are kept.
+/// Returns the kept commands and the names of the removed functions
+pub fn remove_dead_functions(commands: Vec<SourceCommand>) -> (Vec<SourceCommand>, Vec<String>) {
+	// Commands of each function. The first entry is for commands before the first function
+	let mut functions: Vec<(String, Vec<SourceCommand>)> = vec![(String::new(), Vec::new())];
+	for cmd in commands {
+		if let CommandType::Function = cmd.command.command_type() {
+			functions.push((cmd.command.symbol().unwrap().clone(), Vec::new()));
+		}
+		functions.last_mut().unwrap().1.push(cmd);
+	}
+	let index_of: HashMap<&String, usize> = functions
+		.iter()
+		.enumerate()
+		.skip(1)
+		.map(|(idx, (name, _))| (name, idx))
+		.collect();
+	let mut reachable = vec![false; functions.len()];
+	reachable[0] = true;
+	match functions.iter().position(|(name, _)| name == ENTRY_FUNCTION) {
+		Some(entry) => {
+			// Walk the call graph from the entry
+			let mut queue = VecDeque::new();
+			queue.push_back(entry);
+			reachable[entry] = true;
+			while let Some(idx) = queue.pop_front() {
+				for cmd in &functions[idx].1 {
+					if let CommandType::Call = cmd.command.command_type() {
+						if let Some(callee) = index_of.get(cmd.command.symbol().unwrap()) {
+							if !reachable[*callee] {
+								reachable[*callee] = true;
+								queue.push_back(*callee);
+							}
+						}
+					}
+				}
+			}
+		}
+		None => reachable = vec![true; functions.len()],
+	}
+	let mut kept = Vec::new();
+	let mut removed = Vec::new();
+	for ((name, mut commands), keep) in functions.into_iter().zip(reachable) {
+		if keep {
+			kept.append(&mut commands);
+		} else {
+			removed.push(name);
+		}
+	}
+	(kept, removed)
+}
+
+/// Value pushed by the nth command from the end if it is a push constant
+fn constant_from_end(commands: &[SourceCommand], n: usize) -> Option<u32> {
+	commands
+		.len()
+		.checked_sub(n)
+		.and_then(|idx| commands[idx].command.memory_access())
+		.and_then(|m| m.constant())
+}
+
+/// Fold arithmetic on constants into a single push constant, and remove arithmetic that leaves the value as it is.
+/// Results that can't be pushed as a constant (negative or larger than 32767) are not folded
+pub fn fold_constants(commands: Vec<SourceCommand>) -> Vec<SourceCommand> {
+	let mut optimized: Vec<SourceCommand> = Vec::new();
+	for cmd in commands {
+		let arithmetic = match cmd.command.arithmetic() {
+			Some(arithmetic) => arithmetic,
+			None => {
+				optimized.push(cmd);
+				continue;
+			}
+		};
+		let x = constant_from_end(&optimized, 2);
+		let y = constant_from_end(&optimized, 1);
+		// Number of commands before this one that are replaced, and the value pushed instead
+		let (replaced, folded) = match (arithmetic, x, y) {
+			(ArithmeticType::Add, Some(x), Some(y)) if x + y <= MAX_CONSTANT => (2, Some(x + y)),
+			(ArithmeticType::Sub, Some(x), Some(y)) if x >= y => (2, Some(x - y)),
+			(ArithmeticType::And, Some(x), Some(y)) => (2, Some(x & y)),
+			(ArithmeticType::Or, Some(x), Some(y)) => (2, Some(x | y)),
+			// only false (0) can be pushed as a constant
+			(ArithmeticType::Eq, Some(x), Some(y)) if x != y => (2, Some(0)),
+			(ArithmeticType::Gt, Some(x), Some(y)) if x <= y => (2, Some(0)),
+			(ArithmeticType::Lt, Some(x), Some(y)) if x >= y => (2, Some(0)),
+			(ArithmeticType::Neg, _, Some(0)) => (1, Some(0)),
+			// x + 0, x - 0, and x | 0 are x
+			(ArithmeticType::Add | ArithmeticType::Sub | ArithmeticType::Or, _, Some(0)) => (1, None),
+			_ => (0, None),
+		};
+		if replaced == 0 {
+			optimized.push(cmd);
+			continue;
+		}
+		let mut sources = Vec::new();
+		for replaced_cmd in optimized.drain(optimized.len() - replaced..) {
+			sources.extend(replaced_cmd.sources);
+		}
+		sources.extend(cmd.sources);
+		match (folded, optimized.last_mut()) {
+			(Some(value), _) => {
+				let origin_name = sources[0].origin_name.clone();
+				optimized.push(SourceCommand {
+					sources,
+					command: Box::new(MemoryAccess::push_constant(&origin_name, value)),
+				});
+			}
+			// Keep the lines of removed commands with the command that pushed the value
+			(None, Some(last)) => last.sources.extend(sources),
+			(None, None) => {}
+		}
+	}
+	optimized
+}
+
+/// Replace each push immediately followed by a pop with a single move
+pub fn fuse_moves(commands: Vec<SourceCommand>) -> Vec<SourceCommand> {
+	let mut optimized: Vec<SourceCommand> = Vec::new();
+	for cmd in commands {
+		let fused = match (
+			optimized.last().and_then(|c| c.command.memory_access()),
+			cmd.command.memory_access(),
+		) {
+			(Some(source), Some(target)) => Move::new(source, target),
+			_ => None,
+		};
+		match fused {
+			Some(m) => {
+				let mut sources = optimized.pop().unwrap().sources;
+				sources.extend(cmd.sources);
+				optimized.push(SourceCommand {
+					sources,
+					command: Box::new(m),
+				});
+			}
+			None => optimized.push(cmd),
+		}
+	}
+	optimized
+}
+
+/// Returns true if the line is a C instruction that writes to the A register
+fn writes_a(line: &str) -> bool {
+	match line.find('=') {
+		Some(pos) => line[..pos].contains('A'),
+		None => false,
+	}
+}
+
+/// Rewrite a pair of adjacent instructions to an equivalent that is shorter or can be combined further
+fn rewrite_pair(first: &str, second: &str) -> Option<Vec<&'static str>> {
+	match (first, second) {
+		// increment and decrement cancel out
+		("M=M+1", "AM=M-1") => Some(vec!["A=M"]),
+		// the value just written is already in D
+		("M=D", "A=M") => Some(vec!["M=D", "A=D"]),
+		("M=D", "D=M") => Some(vec!["M=D"]),
+		// the first write is overwritten right away
+		("M=D", "AM=M-1") => Some(vec!["AM=D-1"]),
+		("A=M", "A=A-1") => Some(vec!["A=M-1"]),
+		("A=M", "A=A+1") => Some(vec!["A=M+1"]),
+		("A=D", "A=A-1") => Some(vec!["A=D-1"]),
+		("A=D", "A=A+1") => Some(vec!["A=D+1"]),
+		_ => None,
+	}
+}
+
+/// Iterate over the instructions in the lines, skipping comments
+fn following_instructions<'a>(lines: &'a [&'a str]) -> impl Iterator<Item = &'a str> + 'a {
+	lines.iter().copied().filter(|l| !l.starts_with("//"))
+}
+
+/// Returns true if A is loaded again before it is used
+fn a_unused(lines: &[&str]) -> bool {
+	following_instructions(lines)
+		.next()
+		.is_some_and(|l| l.starts_with('@'))
+}
+
+/// Returns true if D is written before it is read
+fn d_unused(lines: &[&str]) -> bool {
+	for line in following_instructions(lines) {
+		if line.starts_with('@') {
+			continue;
+		}
+		// labels can be jumped to from anywhere
+		if line.starts_with('(') {
+			return false;
+		}
+		let (dest, rest) = match line.find('=') {
+			Some(pos) => (&line[..pos], &line[pos + 1..]),
+			None => ("", line),
+		};
+		let comp = rest.split(';').next().unwrap();
+		return !comp.contains('D') && dest.contains('D');
+	}
+	false
+}
+
+/// Read the first or second entry of a segment without calculating the offset in D.
+/// The offset in D is overwritten right away so we can add it in A instead
+fn short_segment_read<'a>(lines: &[&'a str]) -> Option<[&'a str; 3]> {
+	match lines {
+		[offset @ ("@0" | "@1"), "D=A", base, "A=D+M", "D=M", ..] if base.starts_with('@') => {
+			let read = if *offset == "@0" { "A=M" } else { "A=M+1" };
+			Some([base, read, "D=M"])
+		}
+		_ => None,
+	}
+}
+
+/// Shorten pushes of D to the stack, and reads of the first two entries of a segment.
+/// These leave A (and D) with different values so they can only be used when those are not used afterwards.
+fn rewrite_sequences(lines: Vec<&str>) -> Vec<&str> {
+	const PUSH: [&str; 5] = ["@SP", "A=M", "M=D", "@SP", "M=M+1"];
+	const PUSH_WITH_ADDRESS: [&str; 6] = ["@SP", "A=M", "M=D", "D=A+1", "@SP", "M=D"];
+	const SHORT_PUSH: [&str; 4] = ["@SP", "AM=M+1", "A=A-1", "M=D"];
+	let mut optimized = Vec::new();
+	let mut idx = 0;
+	while idx < lines.len() {
+		let rest = &lines[idx..];
+		if rest.starts_with(&PUSH) && a_unused(&rest[PUSH.len()..]) {
+			optimized.extend(&SHORT_PUSH);
+			idx += PUSH.len();
+		} else if rest.starts_with(&PUSH_WITH_ADDRESS)
+			&& a_unused(&rest[PUSH_WITH_ADDRESS.len()..])
+			&& d_unused(&rest[PUSH_WITH_ADDRESS.len()..])
+		{
+			optimized.extend(&SHORT_PUSH);
+			idx += PUSH_WITH_ADDRESS.len();
+		} else if let Some(read) = short_segment_read(rest) {
+			optimized.extend(&read);
+			idx += 5;
+		} else {
+			optimized.push(lines[idx]);
+			idx += 1;
+		}
+	}
+	optimized
+}
+
+/// Remove A instructions loading the value A already has.
+/// Labels can be jumped to from anywhere so nothing is known about A after them.
+fn remove_redundant_loads(lines: Vec<&str>) -> Vec<&str> {
+	let mut optimized = Vec::new();
+	let mut current_a: Option<&str> = None;
+	for line in lines {
+		if line.starts_with("//") {
+			optimized.push(line);
+			continue;
+		}
+		if line.starts_with('@') {
+			if current_a == Some(line) {
+				continue;
+			}
+			current_a = Some(line);
+		} else if line.starts_with('(') || writes_a(line) {
+			current_a = None;
+		}
+		optimized.push(line);
+	}
+	optimized
+}
+
+/// Apply peephole optimizations to the generated asm until nothing changes
+pub fn peephole(asm: &str) -> String {
+	let mut lines: Vec<&str> = asm.lines().collect();
+	loop {
+		let before = lines.len();
+		lines = remove_redundant_loads(lines);
+		lines = rewrite_sequences(lines);
+		let mut rewritten: Vec<&str> = Vec::new();
+		for line in lines {
+			// comments and source map markers between the pair are kept before the replacement
+			let last = rewritten.iter().rposition(|l| !l.starts_with("//"));
+			let pair = last.and_then(|idx| rewrite_pair(rewritten[idx], line).map(|r| (idx, r)));
+			match pair {
+				Some((idx, replacement)) => {
+					rewritten.remove(idx);
+					rewritten.extend(replacement);
+				}
+				None => rewritten.push(line),
+			}
+		}
+		lines = rewritten;
+		if lines.len() == before {
+			break;
+		}
+	}
+	let mut optimized = lines.join("\n");
+	optimized.push('\n');
+	optimized
+}
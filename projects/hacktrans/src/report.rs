@@ -0,0 +1,163 @@
+use crate::command::{CommandType, SourceCommand};
+use std::collections::{HashMap, HashSet};
+
+/// Number of instructions that fit in the hack ROM
+pub const ROM_SIZE: usize = 32768;
+
+/// Generated asm of a single VM function
+pub struct FunctionCode {
+	pub name: String,
+	pub asm: String,
+}
+
+/// Number of instructions generated for the program
+pub struct SizeReport {
+	/// Instructions before the first function (bootstrap code and shared routines)
+	pub bootstrap: usize,
+	/// Instructions of each function in order of output
+	pub functions: Vec<(String, usize)>,
+}
+
+/// Returns true if the asm line is an instruction (not a comment, label, or empty line)
+pub fn is_instruction(line: &str) -> bool {
+	let line = line.trim();
+	!line.is_empty() && !line.starts_with("//") && !line.starts_with('(')
+}
+
+/// Count instructions in asm, skipping comments, labels, and empty lines
+pub fn count_instructions(asm: &str) -> usize {
+	asm.lines().filter(|l| is_instruction(l)).count()
+}
+
+/// Quote a string for JSON
+pub fn json_string(s: &str) -> String {
+	let mut quoted = String::from("\"");
+	for c in s.chars() {
+		match c {
+			'"' => quoted.push_str("\\\""),
+			'\\' => quoted.push_str("\\\\"),
+			c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+			c => quoted.push(c),
+		}
+	}
+	quoted.push('"');
+	quoted
+}
+
+impl SizeReport {
+	/// Count instructions of the bootstrap code and each function
+	pub fn new(bootstrap: &str, functions: &[FunctionCode]) -> SizeReport {
+		SizeReport {
+			bootstrap: count_instructions(bootstrap),
+			functions: functions
+				.iter()
+				.map(|f| (f.name.clone(), count_instructions(&f.asm)))
+				.collect(),
+		}
+	}
+
+	/// Total number of instructions in the program
+	pub fn total(&self) -> usize {
+		self.bootstrap + self.functions.iter().map(|(_, count)| count).sum::<usize>()
+	}
+
+	/// Generate the report in JSON
+	pub fn to_json(&self) -> String {
+		let functions: Vec<String> = self
+			.functions
+			.iter()
+			.map(|(name, count)| {
+				format!(
+					"    {{ \"name\": {}, \"instructions\": {} }}",
+					json_string(name),
+					count
+				)
+			})
+			.collect();
+		format!(
+			"{{\n  \"total\": {},\n  \"rom_size\": {},\n  \"bootstrap\": {},\n  \"functions\": [\n{}\n  ]\n}}\n",
+			self.total(),
+			ROM_SIZE,
+			self.bootstrap,
+			functions.join(",\n")
+		)
+	}
+}
+
+/// Keyword of the VM command type
+fn command_keyword(command: CommandType) -> &'static str {
+	match command {
+		CommandType::Arithmetic => "arithmetic",
+		CommandType::Push => "push",
+		CommandType::Pop => "pop",
+		CommandType::Label => "label",
+		CommandType::GoTo => "goto",
+		CommandType::If => "if-goto",
+		CommandType::Function => "function",
+		CommandType::Return => "return",
+		CommandType::Call => "call",
+		CommandType::Move => "move",
+	}
+}
+
+/// Command types in the order of the summary
+const SUMMARY_COMMANDS: [CommandType; 9] = [
+	CommandType::Push,
+	CommandType::Pop,
+	CommandType::Arithmetic,
+	CommandType::Label,
+	CommandType::GoTo,
+	CommandType::If,
+	CommandType::Function,
+	CommandType::Call,
+	CommandType::Return,
+];
+
+/// Statistics of the translated VM commands
+#[derive(Debug, Clone)]
+pub struct Statistics {
+	/// Number of commands of each type in order of SUMMARY_COMMANDS
+	pub commands: Vec<(&'static str, usize)>,
+	pub functions: usize,
+	/// Number of different static variables. Each takes a RAM address from 16
+	pub statics: usize,
+}
+
+impl Statistics {
+	/// Count the commands before optimization
+	pub fn new(commands: &[SourceCommand]) -> Statistics {
+		let mut counts: HashMap<&'static str, usize> = HashMap::new();
+		let mut statics = HashSet::new();
+		for cmd in commands {
+			*counts.entry(command_keyword(cmd.command.command_type())).or_insert(0) += 1;
+			if let Some(symbol) = cmd.command.static_symbol() {
+				statics.insert(symbol);
+			}
+		}
+		let commands: Vec<(&'static str, usize)> = SUMMARY_COMMANDS
+			.iter()
+			.map(|c| command_keyword(*c))
+			.map(|keyword| (keyword, counts.get(keyword).copied().unwrap_or(0)))
+			.collect();
+		Statistics {
+			functions: counts.get("function").copied().unwrap_or(0),
+			commands,
+			statics: statics.len(),
+		}
+	}
+
+	/// Generate a summary table with the instructions of the program
+	pub fn to_table(&self, size: &SizeReport) -> String {
+		let mut table = String::from("statistics:\n");
+		for (keyword, count) in &self.commands {
+			table.push_str(&format!("  {:<18}{:>8}\n", keyword, count));
+		}
+		table.push_str(&format!("  {:<18}{:>8}\n", "functions", self.functions));
+		table.push_str(&format!("  {:<18}{:>8}\n", "instructions", size.total()));
+		if let Some((name, count)) = size.functions.iter().max_by_key(|(_, count)| *count) {
+			table.push_str(&format!("  {:<18}{:>8} ({})\n", "largest function", count, name));
+		}
+		table.push_str(&format!("  {:<18}{:>8}\n", "static variables", self.statics));
+		table
+	}
+}
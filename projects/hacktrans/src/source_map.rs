@@ -0,0 +1,103 @@
+use crate::command::SourceLine;
+use crate::report::{self, FunctionCode};
+
+/// Comment marking the start of the asm of a VM command, followed by the index of the command.
+/// These are only used while translating and are removed from the output
+pub const MARKER: &str = "//@";
+
+/// Range of ROM addresses generated from a VM command
+#[derive(Debug, Clone)]
+pub struct MapEntry {
+	/// ROM address of the first instruction
+	pub start: usize,
+	/// ROM address after the last instruction
+	pub end: usize,
+	/// Line the command was parsed from (the first line for commands combined by the optimizer)
+	pub source: SourceLine,
+	/// Function containing the command. None for commands before the first function
+	pub function: Option<String>,
+}
+
+/// Map from ROM addresses of the asm program to the VM commands they were generated from
+#[derive(Debug, Default)]
+pub struct SourceMap {
+	/// Entries in order of ROM address. Addresses of the bootstrap code and shared routines are not included
+	pub entries: Vec<MapEntry>,
+}
+
+impl SourceMap {
+	/// Remove the markers from the asm and map the ROM address range following each marker.
+	/// `origins` has the source line and function of the command for each marker index
+	pub fn extract(
+		bootstrap: &mut String,
+		functions: &mut [FunctionCode],
+		origins: &[(SourceLine, Option<String>)],
+	) -> SourceMap {
+		let mut entries = Vec::new();
+		let mut address = 0;
+		for asm in std::iter::once(bootstrap).chain(functions.iter_mut().map(|f| &mut f.asm)) {
+			// marker index and ROM address of the command being read
+			let mut current: Option<(usize, usize)> = None;
+			let mut add_entry = |current: Option<(usize, usize)>, end: usize| {
+				if let Some((index, start)) = current {
+					// commands like labels have no instructions
+					if start < end {
+						let (source, function) = &origins[index];
+						entries.push(MapEntry {
+							start,
+							end,
+							source: source.clone(),
+							function: function.clone(),
+						});
+					}
+				}
+			};
+			let mut stripped = String::with_capacity(asm.len());
+			for line in asm.lines() {
+				if let Some(index) = line.strip_prefix(MARKER) {
+					add_entry(current, address);
+					current = Some((index.parse().unwrap(), address));
+					continue;
+				}
+				if report::is_instruction(line) {
+					address += 1;
+				}
+				stripped.push_str(line);
+				stripped.push('\n');
+			}
+			add_entry(current, address);
+			*asm = stripped;
+		}
+		SourceMap { entries }
+	}
+
+	/// Find the entry containing the ROM address
+	pub fn find(&self, address: usize) -> Option<&MapEntry> {
+		let idx = self.entries.partition_point(|e| e.end <= address);
+		self.entries.get(idx).filter(|e| e.start <= address)
+	}
+
+	/// Generate the map in JSON
+	pub fn to_json(&self) -> String {
+		let entries: Vec<String> = self
+			.entries
+			.iter()
+			.map(|e| {
+				let function = match &e.function {
+					Some(name) => report::json_string(name),
+					None => String::from("null"),
+				};
+				format!(
+					"    {{ \"start\": {}, \"end\": {}, \"file\": {}, \"line\": {}, \"function\": {}, \"command\": {} }}",
+					e.start,
+					e.end,
+					report::json_string(&format!("{}.vm", e.source.origin_name)),
+					e.source.number,
+					function,
+					report::json_string(&e.source.text)
+				)
+			})
+			.collect();
+		format!("{{\n  \"entries\": [\n{}\n  ]\n}}\n", entries.join(",\n"))
+	}
+}
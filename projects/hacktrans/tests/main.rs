@@ -0,0 +1,803 @@
+use hacktrans::command::{Command, Context};
+use hacktrans::{c_backend, translate_str, Codegen, MemoryLayout, Options, Translator};
+
+const MAIN_VM: &str = "function Main.main 1
+push constant 7
+pop local 0
+label LOOP
+push local 0
+push constant 1
+sub
+pop local 0
+push local 0
+if-goto LOOP
+call Counter.next 0
+return
+";
+
+const COUNTER_VM: &str = "// increments a static counter
+function Counter.next 0
+push static 0
+push constant 1
+add
+pop static 0
+push static 0
+return
+";
+
+fn translator(options: Options) -> Translator {
+    let mut translator = Translator::new("Test", options);
+    translator.add_source("Main", MAIN_VM);
+    translator.add_source("Counter", COUNTER_VM);
+    translator
+}
+
+#[test]
+fn test_translate() {
+    let asm = translator(Options::default()).translate().unwrap();
+    // bootstrap calls Sys.init
+    assert!(asm.starts_with("@256\nD=A\n@SP\nM=D\n"));
+    assert!(asm.contains("@Sys.init\n0;JMP\n"));
+    assert!(asm.contains("(Main.main)\n"));
+    assert!(asm.contains("(Counter.next)\n"));
+    // labels are scoped to the function and statics to the file
+    assert!(asm.contains("(Main.main$LOOP)\n"));
+    assert!(asm.contains("@Counter.0\n"));
+    assert!(asm.contains("(Main.main$ret.1)\n"));
+    // scratch values use R13-R15 instead of variables
+    assert!(asm.contains("@R13\n"));
+    assert!(!asm.contains("@Test.tmp\n") && !asm.contains("@Test.ret\n"));
+}
+
+#[test]
+fn test_translate_str() {
+    let asm = translate_str("Counter", COUNTER_VM, &Options::default()).unwrap();
+    let mut translator = Translator::new("Counter", Options::default());
+    translator.add_source("Counter", COUNTER_VM);
+    assert_eq!(asm, translator.translate().unwrap());
+    assert!(asm.contains("@Counter.0\n"));
+    // the first error is returned
+    let error = translate_str("Main", "push local\npop\n", &Options::default()).unwrap_err();
+    assert_eq!((error.line, error.message.as_str()), (1, "Missing index"));
+}
+
+#[test]
+fn test_translate_options() {
+    let program = translator(Options::default()).translate_program().unwrap();
+    let names: Vec<&str> = program.functions.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, ["Main.main", "Counter.next"]);
+
+    let annotated = translator(Options {
+        annotate: true,
+        ..Options::default()
+    })
+    .translate()
+    .unwrap();
+    assert!(annotated.contains("// Counter.vm:4: push constant 1\n"));
+
+    let default_size = program.text().len();
+    let small = translator(Options {
+        codegen: Codegen::Size,
+        optimize: 1,
+        ..Options::default()
+    })
+    .translate()
+    .unwrap();
+    assert!(small.len() < default_size);
+}
+
+#[test]
+fn test_translate_errors() {
+    let mut translator = Translator::new("Test", Options::default());
+    translator.add_source(
+        "Main",
+        "function Main.main 0\npush nowhere 1\npop constant 0\npop temp 8\npush pointer 2\npush constant 32768\npush constant 32767\n",
+    );
+    translator.add_source("Other", "function Other.f\n");
+    let errors = translator.translate().unwrap_err();
+    let lines: Vec<(&str, usize)> = errors
+        .iter()
+        .map(|e| (e.origin_name.as_str(), e.line))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            ("Main", 2),
+            ("Main", 3),
+            ("Main", 4),
+            ("Main", 5),
+            ("Main", 6),
+            ("Other", 1)
+        ]
+    );
+    assert_eq!(errors[0].text, "push nowhere 1");
+    assert_eq!(errors[2].message, "Index 8 is out of range of temp (0-7)");
+}
+
+#[test]
+fn test_remove_dead_functions() {
+    let sys_vm = "function Sys.init 0\ncall Main.main 0\nlabel HALT\ngoto HALT\n";
+    let mut translator = translator(Options::default());
+    translator.add_source("Sys", sys_vm);
+    translator.add_source(
+        "Unused",
+        "function Unused.f 0\ncall Counter.next 0\nreturn\n",
+    );
+    let program = translator.translate_program().unwrap();
+    let names: Vec<&str> = program.functions.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, ["Main.main", "Counter.next", "Sys.init"]);
+    assert_eq!(program.removed_functions, ["Unused.f"]);
+
+    let mut translator = self::translator(Options {
+        keep_all_functions: true,
+        ..Options::default()
+    });
+    translator.add_source("Sys", sys_vm);
+    translator.add_source(
+        "Unused",
+        "function Unused.f 0\ncall Counter.next 0\nreturn\n",
+    );
+    let program = translator.translate_program().unwrap();
+    assert_eq!(program.functions.len(), 4);
+    assert!(program.removed_functions.is_empty());
+}
+
+#[test]
+fn test_fold_constants() {
+    let source = "function Main.main 0
+push constant 2
+push constant 3
+add
+push constant 4
+add
+push local 0
+push constant 0
+sub
+push constant 2
+push constant 3
+sub
+return
+";
+    let translate = |optimize| {
+        let mut translator = Translator::new(
+            "Test",
+            Options {
+                optimize,
+                annotate: true,
+                ..Options::default()
+            },
+        );
+        translator.add_source("Main", source);
+        translator.translate().unwrap()
+    };
+    let folded = translate(2);
+    assert!(folded.contains("// Main.vm:6: add\n@9\n"));
+    // x - 0 is removed and 2 - 3 can't be pushed as a constant
+    assert!(folded.contains("// Main.vm:9: sub\n@LCL\n"));
+    assert!(folded.contains("// Main.vm:12: sub\n"));
+    assert!(folded.len() < translate(1).len());
+}
+
+#[test]
+fn test_annotate_keeps_optimization() {
+    // comments must not block peephole rewrites
+    for codegen in [Codegen::Speed, Codegen::Size] {
+        let assemble = |annotate| {
+            translator(Options {
+                codegen,
+                optimize: 1,
+                annotate,
+                ..Options::default()
+            })
+            .translate_program()
+            .unwrap()
+            .assemble("Test.asm")
+            .unwrap()
+        };
+        assert_eq!(assemble(false), assemble(true));
+    }
+}
+
+#[test]
+fn test_source_map() {
+    for optimize in 0..3 {
+        let program = translator(Options {
+            optimize,
+            ..Options::default()
+        })
+        .translate_program()
+        .unwrap();
+        let entries = &program.source_map.entries;
+        let bootstrap_size = hacktrans::report::count_instructions(&program.bootstrap);
+        assert_eq!(entries[0].start, bootstrap_size);
+        assert_eq!(
+            (
+                entries[0].source.origin_name.as_str(),
+                entries[0].source.number
+            ),
+            ("Main", 1)
+        );
+        // ranges cover the whole program without gaps
+        for pair in entries.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        let total = hacktrans::report::count_instructions(&program.text());
+        assert_eq!(entries.last().unwrap().end, total);
+        let last = entries.last().unwrap();
+        assert_eq!(last.function.as_deref(), Some("Counter.next"));
+        assert_eq!(last.source.text, "return");
+        assert!(!program.text().contains(hacktrans::source_map::MARKER));
+    }
+    // tabs inside a command are escaped in the JSON
+    let mut translator = Translator::new("Test", Options::default());
+    translator.add_source("Main", "function Main.main 0\npush\tconstant 1\nreturn\n");
+    let json = translator.translate_program().unwrap().source_map.to_json();
+    assert!(json.contains("\"command\": \"push\\u0009constant 1\" }"));
+    assert!(!json.contains('\t'));
+    assert_eq!(
+        hacktrans::report::json_string("a\"b\\c\n"),
+        "\"a\\\"b\\\\c\\u000a\""
+    );
+}
+
+#[test]
+fn test_assemble() {
+    let program = translator(Options::default()).translate_program().unwrap();
+    let words = program.assemble("Test.asm").unwrap();
+    assert_eq!(
+        words.len(),
+        hacktrans::report::count_instructions(&program.text())
+    );
+
+    // errors of the assembler are reported at the VM command
+    let mut source = String::from("function Main.main 0\n");
+    source.push_str(&"push constant 1\n".repeat(5000));
+    source.push_str("label END\ngoto END\n");
+    let mut translator = Translator::new("Test", Options::default());
+    translator.add_source("Main", &source);
+    let error = translator
+        .translate_program()
+        .unwrap()
+        .assemble("Test.asm")
+        .unwrap_err();
+    assert_eq!(error.origin_name, "Main");
+    assert_eq!(error.text, "push constant 1");
+    assert!(error.message.contains("ROM size"));
+}
+
+#[test]
+fn test_memory_layout() {
+    let mut translator = Translator::new(
+        "Test",
+        Options {
+            layout: MemoryLayout {
+                stack_base: 300,
+                temp_base: 20,
+                pointer_base: 30,
+            },
+            ..Options::default()
+        },
+    );
+    translator.add_source(
+        "Main",
+        "function Main.main 0\npush temp 1\npop pointer 1\npush constant 0\nreturn\n",
+    );
+    let asm = translator.translate().unwrap();
+    assert!(asm.starts_with("@300\nD=A\n@SP\nM=D\n"));
+    // temp and pointer entries are addressed from the bases of the layout
+    assert!(asm.contains("@1\nD=A\n@20\nA=D+A\n"));
+    assert!(asm.contains("@1\nD=A\n@30\nD=D+A\n"));
+    assert!(!asm.contains("@R5\n") && !asm.contains("@R3\n"));
+}
+
+/// Run Hack machine code for the given number of steps and return the RAM
+fn run_hack(words: &[u16], steps: usize) -> Vec<i16> {
+    let mut ram = vec![0i16; 32768];
+    let (mut a, mut d, mut pc) = (0i16, 0i16, 0usize);
+    for _ in 0..steps {
+        let Some(&word) = words.get(pc) else {
+            break;
+        };
+        pc += 1;
+        if word & 0x8000 == 0 {
+            a = word as i16;
+            continue;
+        }
+        let bit = |n: u16| (word >> n) & 1 == 1;
+        let mut x = d;
+        let mut y = if bit(12) { ram[a as u16 as usize] } else { a };
+        if bit(11) {
+            x = 0;
+        }
+        if bit(10) {
+            x = !x;
+        }
+        if bit(9) {
+            y = 0;
+        }
+        if bit(8) {
+            y = !y;
+        }
+        let mut out = if bit(7) { x.wrapping_add(y) } else { x & y };
+        if bit(6) {
+            out = !out;
+        }
+        if bit(3) {
+            ram[a as u16 as usize] = out;
+        }
+        if bit(5) {
+            a = out;
+        }
+        if bit(4) {
+            d = out;
+        }
+        // jumps go to the updated A like the CPU emulator, so A=M;JMP works
+        let jump = (bit(2) && out < 0) || (bit(1) && out == 0) || (bit(0) && out > 0);
+        if jump {
+            pc = a as u16 as usize;
+        }
+    }
+    ram
+}
+
+#[test]
+fn test_memory_layout_run() {
+    let layout = MemoryLayout {
+        stack_base: 300,
+        temp_base: 20,
+        pointer_base: 30,
+    };
+    for codegen in [Codegen::Speed, Codegen::Size] {
+        let mut translator = Translator::new(
+            "Test",
+            Options {
+                codegen,
+                layout,
+                ..Options::default()
+            },
+        );
+        translator.add_source(
+            "Sys",
+            "function Sys.init 0
+push constant 3000
+pop pointer 0
+push constant 42
+pop this 2
+push constant 4000
+pop pointer 1
+push constant 7
+pop that 1
+call Main.f 0
+pop temp 1
+push this 2
+push that 1
+add
+pop temp 0
+label END
+goto END
+",
+        );
+        // the callee moves this and that, which the return must restore
+        translator.add_source(
+            "Main",
+            "function Main.f 0
+push constant 5000
+pop pointer 0
+push constant 1
+pop this 0
+push constant 6000
+pop pointer 1
+push constant 2
+pop that 0
+push constant 0
+return
+",
+        );
+        let words = translator
+            .translate_program()
+            .unwrap()
+            .assemble("Test.asm")
+            .unwrap();
+        let ram = run_hack(&words, 10000);
+        assert_eq!((ram[3002], ram[4001]), (42, 7));
+        assert_eq!((ram[5000], ram[6000]), (1, 2));
+        // this and that are read through the restored pointer segment
+        assert_eq!((ram[30], ram[31]), (3000, 4000));
+        assert_eq!(ram[20], 49);
+    }
+}
+
+#[test]
+fn test_callgraph() {
+    let mut translator = translator(Options::default());
+    translator.add_source(
+        "Sys",
+        "function Sys.init 0\ncall Main.main 0\ncall Main.main 0\ncall Output.printInt 1\nreturn\n",
+    );
+    let dot = hacktrans::callgraph::generate_dot(&translator.parse().unwrap());
+    assert!(dot.starts_with("digraph calls {\n"));
+    assert!(dot.contains("    \"Sys.init\" -> \"Main.main\" [label=\"2\"];\n"));
+    assert!(dot.contains("    \"Main.main\" -> \"Counter.next\" [label=\"1\"];\n"));
+    // OS functions that are not linked are dashed
+    assert!(dot.contains("    \"Output.printInt\" [style=dashed];\n"));
+    assert!(!dot.contains("\"Counter.next\" [style=dashed]"));
+}
+
+#[test]
+fn test_signed_compare() {
+    let mut translator = Translator::new("Test", Options::default());
+    translator.add_source(
+        "Main",
+        "function Main.main 0\npush constant 1\npush constant 2\ngt\npush constant 3\nlt\nreturn\n",
+    );
+    let asm = translator.translate().unwrap();
+    // the sign of x decides the result when the signs differ instead of the overflowing x - y
+    assert!(asm.contains("@1\nD=D|A\n@Gt.1.Test\n0;JMP\n"));
+    assert!(asm.contains("(Lt.1.SignDiffers)\n"));
+    assert!(asm.contains("(Gt.1.Test)\n@Gt.1.True\nD;JGT\n"));
+}
+
+#[test]
+fn test_ids_across_files() {
+    let mut translator = Translator::new("Test", Options::default());
+    for name in &["A", "B", "C"] {
+        let vm = format!(
+            "function {}.f 0\npush constant 1\npush constant 2\neq\npush constant 3\neq\nreturn\n",
+            name
+        );
+        translator.add_source(name, &vm);
+    }
+    let asm = translator.translate().unwrap();
+    // files are parsed separately but the IDs are numbered in order of the files
+    let labels: Vec<&str> = asm.lines().filter(|l| l.starts_with("(IsEq.")).collect();
+    assert_eq!(
+        labels,
+        ["(IsEq.1)", "(IsEq.2)", "(IsEq.3)", "(IsEq.4)", "(IsEq.5)", "(IsEq.6)"]
+    );
+}
+
+#[test]
+fn test_label_errors() {
+    let mut translator = Translator::new("Test", Options::default());
+    translator.add_source(
+        "Main",
+        "function Main.f 0
+label A
+label A
+goto B
+if-goto A
+return
+function Main.g 0
+goto A
+return
+",
+    );
+    let errors = translator.translate().unwrap_err();
+    let messages: Vec<(usize, &str)> = errors
+        .iter()
+        .map(|e| (e.line, e.message.as_str()))
+        .collect();
+    // labels are scoped to the function so A is undefined in Main.g
+    assert_eq!(
+        messages,
+        [
+            (3, "Duplicate label A (first defined at line 2)"),
+            (4, "Undefined label B"),
+            (8, "Undefined label A")
+        ]
+    );
+}
+
+#[test]
+fn test_static_budget() {
+    // 200 statics in Main and 41 in Counter is one more than RAM 16-255 holds
+    let translator = |stack_base| {
+        let layout = MemoryLayout {
+            stack_base,
+            ..MemoryLayout::default()
+        };
+        let mut translator = Translator::new(
+            "Test",
+            Options {
+                layout,
+                ..Options::default()
+            },
+        );
+        for (name, statics) in &[("Main", 200), ("Counter", 41)] {
+            let mut vm = format!("function {}.f 0\n", name);
+            for i in 0..*statics {
+                vm.push_str(&format!("push constant 0\npop static {}\n", i));
+            }
+            vm.push_str("push constant 0\nreturn\n");
+            translator.add_source(name, &vm);
+        }
+        translator
+    };
+    let errors = translator(256).translate().unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        (errors[0].origin_name.as_str(), errors[0].line),
+        ("Counter", 83)
+    );
+    assert_eq!(
+        errors[0].message,
+        "Static variable Counter.40 does not fit in RAM 16-255: \
+         241 static variables are used but only 240 fit (Main: 200, Counter: 41)"
+    );
+    // fits when the stack starts higher
+    assert!(translator(257).translate().is_ok());
+}
+
+#[test]
+fn test_statistics() {
+    let program = translator(Options::default()).translate_program().unwrap();
+    let stats = &program.statistics;
+    let count = |keyword| {
+        stats
+            .commands
+            .iter()
+            .find(|(k, _)| *k == keyword)
+            .unwrap()
+            .1
+    };
+    assert_eq!(
+        (count("push"), count("pop"), count("arithmetic")),
+        (7, 3, 2)
+    );
+    assert_eq!(
+        (count("if-goto"), count("call"), count("return")),
+        (1, 1, 2)
+    );
+    assert_eq!(stats.functions, 2);
+    assert_eq!(stats.statics, 1);
+    let size = hacktrans::report::SizeReport::new(&program.bootstrap, &program.functions);
+    let table = stats.to_table(&size);
+    assert!(table.contains("  push                     7\n"));
+    assert!(table.contains(&format!("  {:<18}{:>8}\n", "instructions", size.total())));
+    assert!(table.contains("  largest function"));
+}
+
+#[test]
+fn test_check_stack() {
+    assert!(translator(Options::default()).check().is_ok());
+
+    let mut translator = Translator::new("Test", Options::default());
+    translator.add_source(
+        "Main",
+        "function Main.a 0
+pop local 0
+push constant 0
+return
+function Main.b 1
+push local 0
+if-goto SKIP
+push constant 5
+label SKIP
+push constant 0
+return
+",
+    );
+    let errors = translator.check().unwrap_err();
+    let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+    // empty pop, label reached with different depths, and return with an extra value
+    assert_eq!(lines, [2, 9, 11]);
+}
+
+#[test]
+fn test_link_keep_all() {
+    let units = [
+        hacktrans::linker::Unit {
+            origin_name: String::from("Sys"),
+            text: String::from("function Sys.init 0\ncall Main.main 0\nreturn\n"),
+        },
+        hacktrans::linker::Unit {
+            origin_name: String::from("Main"),
+            text: String::from(
+                "function Main.main 0\npush constant 0\nreturn\nfunction Main.unused 0\ncall Math.abs 1\nreturn\n",
+            ),
+        },
+    ];
+    let linked = hacktrans::linker::link(&units, false).unwrap();
+    assert_eq!(linked.stripped, ["Main.unused"]);
+    assert!(linked.undefined.is_empty());
+    // calls of the kept functions are still checked
+    let linked = hacktrans::linker::link(&units, true).unwrap();
+    assert!(linked.stripped.is_empty());
+    assert!(linked.text.contains("function Main.unused 0\n"));
+    assert_eq!(linked.undefined, ["Math.abs"]);
+}
+
+#[test]
+fn test_translate_stream() {
+    let stream = |options: &Options, sources: &[(&str, &'static str)]| {
+        let inputs = sources.iter().map(|(name, vm)| {
+            let input: hacktrans::SourceInput = Box::new(vm.as_bytes());
+            Ok((name.to_string(), input))
+        });
+        let mut out = Vec::new();
+        let result = hacktrans::translate_stream("Test", options, inputs, &mut out).unwrap();
+        (String::from_utf8(out).unwrap(), result)
+    };
+    for optimize in 0..3 {
+        for annotate in [false, true] {
+            let options = Options {
+                optimize,
+                annotate,
+                keep_all_functions: true,
+                ..Options::default()
+            };
+            let program = translator(options.clone()).translate_program().unwrap();
+            let (asm, size) = stream(&options, &[("Main", MAIN_VM), ("Counter", COUNTER_VM)]);
+            assert_eq!(asm, program.text());
+            let expected =
+                hacktrans::report::SizeReport::new(&program.bootstrap, &program.functions);
+            assert_eq!(size.unwrap().to_json(), expected.to_json());
+        }
+    }
+    assert!(!Options::default().is_streamable());
+    // errors of all functions are returned after the output is written
+    let options = Options {
+        keep_all_functions: true,
+        ..Options::default()
+    };
+    let (asm, result) = stream(
+        &options,
+        &[(
+            "Main",
+            "function Main.a 0\ngoto END\nfunction Main.b 0\npush local\nreturn\n",
+        )],
+    );
+    assert!(asm.contains("(Main.b)\n"));
+    let lines: Vec<usize> = result.err().unwrap().iter().map(|e| e.line).collect();
+    assert_eq!(lines, [2, 4]);
+}
+
+/// Generate the C program for the VM sources like `--target c`
+fn c_program(sources: &[(&str, &str)]) -> Result<String, String> {
+    let mut translator = Translator::new("Test", Options::default());
+    for (name, vm) in sources {
+        translator.add_source(name, vm);
+    }
+    let commands: Vec<Box<dyn Command>> = translator
+        .parse()
+        .unwrap()
+        .into_iter()
+        .map(|c| c.command)
+        .collect();
+    let mut context = Context::new(Codegen::Speed, MemoryLayout::default());
+    context.define_functions(&commands);
+    c_backend::generate_c_program(&commands, &mut context)
+}
+
+/// Compile the C program and run it, returning its stdout, stderr and exit code.
+/// Returns None when there is no C compiler
+fn run_c(test: &str, program: &str) -> Option<(String, String, i32)> {
+    let dir = std::env::temp_dir().join(format!("hacktrans_{}_{}", test, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("program.c");
+    let executable = dir.join("program");
+    std::fs::write(&source, program).unwrap();
+    let compiled = std::process::Command::new("cc")
+        .arg("-o")
+        .arg(&executable)
+        .arg(&source)
+        .status()
+        .ok()?;
+    assert!(compiled.success(), "failed to compile {}", source.display());
+    let output = std::process::Command::new(&executable).output().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    Some((
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+        output.status.code().unwrap(),
+    ))
+}
+
+#[test]
+fn test_c_backend() {
+    // Main.main is the entry point when Sys.init is not defined
+    let program = c_program(&[("Main", MAIN_VM), ("Counter", COUNTER_VM)]).unwrap();
+    assert!(program.contains("\tgoto F_Main_Dmain;\nRET_0:\n"));
+    assert!(program.contains("F_Main_Dmain:\n\tPUSH(0);\n"));
+    assert!(program.contains("L_Main_Dmain_SLOOP:;\n"));
+    assert!(program.contains("static int16_t S_Counter_D0;\n"));
+    let sys = "function Sys.init 0\ncall Main.main 0\npop temp 0\nlabel HALT\ngoto HALT\n";
+    let program = c_program(&[("Main", MAIN_VM), ("Counter", COUNTER_VM), ("Sys", sys)]).unwrap();
+    assert!(program.contains("\tgoto F_Sys_Dinit;\nRET_0:\n"));
+
+    // every call site has an entry in the return table, including calls to the OS
+    let main = "function Main.main 0
+push constant 6
+push constant 7
+call Math.multiply 2
+call Output.printInt 1
+pop temp 0
+call Counter.next 0
+call Counter.next 0
+add
+return
+";
+    let program = c_program(&[("Main", main), ("Counter", COUNTER_VM)]).unwrap();
+    assert!(program.contains("return_table[] = { &&RET_0, &&RET_1, &&RET_2, &&RET_3, &&RET_4 };"));
+    assert!(program.contains("\tCALL_NATIVE(os_Math_multiply, 2);\nRET_1:;\n"));
+    assert!(program.contains("\tPUSH(3);\n"));
+    assert!(program.contains("\tgoto F_Counter_Dnext;\nRET_3:;\n"));
+
+    // underscores are escaped so these names don't collide
+    let collide = "function Main.main 0
+call A_B.c 0
+call A.B_c 0
+add
+call Output.printInt 1
+return
+function A_B.c 0
+push constant 1
+return
+function A.B_c 0
+push constant 2
+return
+";
+    let program = c_program(&[("Main", collide)]).unwrap();
+    assert_eq!(c_backend::function_label("A_B.c"), "F_A__B_Dc");
+    assert_eq!(c_backend::function_label("A.B_c"), "F_A_DB__c");
+    assert!(program.contains("F_A__B_Dc:\n") && program.contains("F_A_DB__c:\n"));
+
+    let error = c_program(&[(
+        "Main",
+        "function Main.main 0\ncall Main.missing 0\nreturn\n",
+    )]);
+    assert_eq!(
+        error.unwrap_err(),
+        "Undefined function called: Main.missing"
+    );
+    let error = c_program(&[("Counter", COUNTER_VM)]);
+    assert_eq!(
+        error.unwrap_err(),
+        "Neither Sys.init nor Main.main is defined"
+    );
+
+    if let Some((stdout, stderr, code)) = run_c(
+        "c_backend",
+        &c_program(&[("Main", main), ("Counter", COUNTER_VM)]).unwrap(),
+    ) {
+        assert_eq!((stdout.as_str(), stderr.as_str(), code), ("42", "", 0));
+    }
+    if let Some(result) = run_c("c_backend_collide", &program) {
+        assert_eq!(result, (String::from("3"), String::new(), 0));
+    }
+}
+
+#[test]
+fn test_c_runtime_errors() {
+    // errors stop the program with the decoded error code after flushing the output
+    let divide = "function Main.main 0
+push constant 1
+call Output.printInt 1
+pop temp 0
+push constant 7
+push constant 0
+call Math.divide 2
+return
+";
+    let program = c_program(&[("Main", divide)]).unwrap();
+    if let Some((stdout, stderr, code)) = run_c("divide_by_zero", &program) {
+        assert_eq!(stdout, "1");
+        assert_eq!(stderr, "ERR3: Math.divide: division by zero\n");
+        assert_eq!(code, 1);
+    }
+    let char_at = "function Main.main 0
+push constant 1
+call String.new 1
+push constant 65
+call String.appendChar 2
+push constant 1
+call String.charAt 2
+return
+";
+    let program = c_program(&[("Main", char_at)]).unwrap();
+    if let Some((stdout, stderr, code)) = run_c("char_at", &program) {
+        assert_eq!(stdout, "");
+        assert_eq!(stderr, "ERR15: String.charAt: string index out of bounds\n");
+        assert_eq!(code, 1);
+    }
+}
@@ -0,0 +1,39 @@
+//! Throughput of the tokenizer on the Pong sources.
+//! Run with `cargo bench --bench tokenizer`
+use jack_compiler::tokenizer;
+use std::path::PathBuf;
+use std::time::Instant;
+
+/// Number of times all sources are tokenized
+const ITERATIONS: usize = 2000;
+
+fn main() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("data")
+        .join("Pong");
+    let mut sources = Vec::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|e| e == "jack") {
+            sources.push(std::fs::read_to_string(path).unwrap());
+        }
+    }
+    let bytes: usize = sources.iter().map(|s| s.len()).sum();
+    let start = Instant::now();
+    let mut tokens = 0;
+    for _ in 0..ITERATIONS {
+        for s in &sources {
+            tokens += tokenizer::tokenize_str(s).unwrap().list.len();
+        }
+    }
+    let elapsed = start.elapsed();
+    let megabytes = (bytes * ITERATIONS) as f64 / 1_000_000.0;
+    println!(
+        "tokenized {} tokens ({:.1} MB) in {:.3} s: {:.1} MB/s",
+        tokens,
+        megabytes,
+        elapsed.as_secs_f64(),
+        megabytes / elapsed.as_secs_f64()
+    );
+}
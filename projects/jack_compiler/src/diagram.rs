@@ -0,0 +1,60 @@
+use super::parser::Class;
+use std::collections::BTreeSet;
+
+/// Escape characters that have special meaning in DOT record labels
+fn escape_record(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if matches!(c, '{' | '}' | '|' | '<' | '>' | '"' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Join lines as a left aligned DOT record field
+fn record_field(lines: &[String]) -> String {
+    lines
+        .iter()
+        .map(|l| format!("{}\\l", escape_record(l)))
+        .collect()
+}
+
+/// Generate a class diagram in DOT format.
+/// Each class is a record node with its class variables and subroutines.
+/// Classes used as class variable types are connected with solid edges,
+/// and classes used only within subroutines are connected with dashed edges.
+/// Only classes within the given list are connected, so OS classes are left out.
+pub fn generate_dot(classes: &[&Class]) -> String {
+    let names: BTreeSet<&str> = classes.iter().map(|c| c.name()).collect();
+    let mut output = String::from("digraph classes {\n");
+    output.push_str("    node [shape=record, fontname=\"monospace\"];\n");
+    for c in classes {
+        output.push_str(&format!(
+            "    \"{0}\" [label=\"{{{0}|{1}|{2}}}\"];\n",
+            c.name(),
+            record_field(&c.class_var_lines()),
+            record_field(&c.subroutine_lines())
+        ));
+    }
+    for c in classes {
+        let var_types = c.class_var_types();
+        for t in var_types.iter().filter(|t| names.contains(t.as_str())) {
+            output.push_str(&format!("    \"{}\" -> \"{}\";\n", c.name(), t));
+        }
+        for t in c
+            .subroutine_types()
+            .iter()
+            .filter(|t| names.contains(t.as_str()) && !var_types.contains(*t) && *t != c.name())
+        {
+            output.push_str(&format!(
+                "    \"{}\" -> \"{}\" [style=dashed];\n",
+                c.name(),
+                t
+            ));
+        }
+    }
+    output.push_str("}\n");
+    output
+}
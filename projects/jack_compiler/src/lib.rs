@@ -1,6 +1,7 @@
 use std::fs::File;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
+pub mod diagram;
 pub mod parser;
 pub mod tokenizer;
 
@@ -9,6 +9,16 @@ use std::path::Path;
 struct Opts {
     #[clap(short)]
     input_file_or_dir: String,
+    /// Also write a class diagram of all classes in DOT format
+    #[clap(long)]
+    diagram: bool,
+    /// Also write the tokens of each file with their kind, text, and position in JSON (<name>T.json)
+    #[clap(long)]
+    emit_tokens_json: bool,
+    /// Check types of assignments, operators, subroutine arguments, and conditions before compiling.
+    /// Nothing is written if an error is found
+    #[clap(long)]
+    typecheck: bool,
 }
 
 fn main() -> std::io::Result<()> {
@@ -25,13 +35,55 @@ fn main() -> std::io::Result<()> {
         let out_name = format!("{}.vm", origin_name);
         output_file_path.set_file_name(out_name);
         let mut info = jack_compiler::parser::ClassParseInfo::new();
-        let class = jack_compiler::parser::parse_file(&mut info, &mut io.input).unwrap();
+        let class = jack_compiler::parser::parse_file(&mut info, &mut io.input)
+            .unwrap_or_else(|e| panic!("{}:{}", io.input_file.display(), e));
+        if opts.emit_tokens_json {
+            // The file is tokenized again since it parsed without errors
+            let source = std::fs::read_to_string(&io.input_file)?;
+            let tokens = jack_compiler::tokenizer::tokenize_str(&source).unwrap();
+            let mut json_path = io.input_file.clone();
+            json_path.set_file_name(format!("{}T.json", origin_name));
+            println!("output: {}", &json_path.display());
+            std::fs::write(json_path, tokens.to_json())?;
+        }
         dir_info
             .info_per_class
             .insert(class.name().to_owned(), info);
         class_list.push((class, output_file_path));
     }
 
+    if opts.diagram {
+        // set output file name as "<input directory or file name>.dot"
+        let name = jack_compiler::get_origin_name(input_path).unwrap();
+        let dot_path = if input_path.is_dir() {
+            input_path.join(format!("{}.dot", name))
+        } else {
+            input_path.with_extension("dot")
+        };
+        println!("output: {}", &dot_path.display());
+        let classes: Vec<&jack_compiler::parser::Class> =
+            class_list.iter().map(|(c, _)| c).collect();
+        let mut dot_file = File::create(dot_path)?;
+        dot_file.write_all(jack_compiler::diagram::generate_dot(&classes).as_bytes())?;
+    }
+
+    if opts.typecheck {
+        let mut error_count = 0;
+        for (c, out_path) in &class_list {
+            let source_path = out_path.with_extension("jack");
+            for d in c.typecheck(&dir_info) {
+                if d.severity == jack_compiler::parser::Severity::Error {
+                    error_count += 1;
+                }
+                eprintln!("{}:{}", source_path.display(), d);
+            }
+        }
+        if error_count > 0 {
+            eprintln!("{} type errors found", error_count);
+            std::process::exit(1);
+        }
+    }
+
     // compile all files
     for (c, out_path) in class_list {
         println!("output: {}", &out_path.display());
@@ -1,9 +1,12 @@
 use super::tokenizer;
 use super::tokenizer::{
-    generate_token_list, Identifier, IntegerConstant, Keyword, KeywordType, SerializeError,
-    StringConstant, Symbol, Token, TokenList, INDENT_STR, NEW_LINE,
+    generate_token_list, Identifier, IntegerConstant, Keyword, KeywordType, SerializeError, Span,
+    StringConstant, Symbol, Token, TokenList, TokenizeError, INDENT_STR, NEW_LINE,
 };
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+
+mod typecheck;
+pub use typecheck::{Diagnostic, Severity};
 
 const CLASS_VAR_DEC: &'static str = "classVarDec";
 const SUBROUTINE_DEC: &'static str = "subroutineDec";
@@ -40,25 +43,23 @@ const STRING_APPEND_CHAR: &'static str = "String.appendChar";
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("{file} {line}:{column} Got unexpected token at {index}: {token:?}")]
+    #[error("{}:{} Got unexpected token: {}", .span.line, .span.column, .token.string())]
     UnexpectedToken {
         token: Token,
+        /// Index of the token in the token list
         index: usize,
-        file: &'static str,
-        line: u32,
-        column: u32,
+        span: Span,
     },
-    #[error("Got unexpected keyword: {0:?}")]
-    UnexpectedKeyword(KeywordType),
+    #[error("{}:{} Got unexpected keyword: {keyword:?}", .span.line, .span.column)]
+    UnexpectedKeyword { keyword: KeywordType, span: Span },
     #[error("Got unknown type: {0}")]
     UnknownType(String),
-    #[error("{file} {line}:{column} Got unexpected symbol at {index}: {symbol}")]
+    #[error("{}:{} Got unexpected symbol: {symbol}", .span.line, .span.column)]
     UnexpectedSymbol {
         symbol: char,
+        /// Index of the token in the token list
         index: usize,
-        file: &'static str,
-        line: u32,
-        column: u32,
+        span: Span,
     },
     #[error(
         "Not all tokens were consumed: token length: {token_length} token index: {current_index}"
@@ -69,6 +70,34 @@ pub enum Error {
     },
     #[error("Unexpected State: {0}")]
     UnexpectedState(String),
+    #[error("{}", join_errors(.0))]
+    Tokenize(Vec<TokenizeError>),
+    #[error("{}:{} Variable {name} is not defined", .span.line, .span.column)]
+    UndefinedVariable { name: String, span: Span },
+    #[error("{}:{} Field {name} cannot be used in function {function}", .span.line, .span.column)]
+    FieldInFunction {
+        name: String,
+        function: String,
+        span: Span,
+    },
+    #[error("{}:{} Subroutine {name} is not defined", .span.line, .span.column)]
+    UndefinedSubroutine { name: String, span: Span },
+    #[error("{}:{} Method {name} cannot be called from function {function}", .span.line, .span.column)]
+    MethodInFunction {
+        name: String,
+        function: String,
+        span: Span,
+    },
+    #[error("{}:{} Void subroutine {function} cannot return a value", .span.line, .span.column)]
+    ReturnValueInVoid { function: String, span: Span },
+    #[error("{}:{} Subroutine {function} must return a value", .span.line, .span.column)]
+    MissingReturnValue { function: String, span: Span },
+}
+
+/// Show each error on its own line
+fn join_errors(errors: &[TokenizeError]) -> String {
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    messages.join("\n")
 }
 
 #[derive(Debug)]
@@ -197,6 +226,32 @@ impl ClassSymbolTable {
             }
         };
     }
+
+    /// Get the entry of a variable that is not in the method table.
+    /// Fields need THIS so they can only be used in constructors and methods
+    fn lookup(
+        &self,
+        name: &Identifier,
+        state: &CompileState,
+    ) -> Result<&ClassSymbolTableEntry, Error> {
+        let entry = self
+            .table
+            .get(&name.value)
+            .ok_or_else(|| Error::UndefinedVariable {
+                name: name.value.clone(),
+                span: name.span,
+            })?;
+        if matches!(entry.category, ClassSymbolCategory::Field)
+            && matches!(state.func_state.subroutine_type, SubroutineType::Function)
+        {
+            return Err(Error::FieldInFunction {
+                name: name.value.clone(),
+                function: state.full_method_name(),
+                span: name.span,
+            });
+        }
+        Ok(entry)
+    }
 }
 
 #[derive(Debug)]
@@ -349,6 +404,8 @@ pub struct ClassParseInfo {
     class_symbol_table: ClassSymbolTable,
     symbol_table_per_method: HashMap<String, MethodSymbolTable>,
     return_type: ReturnTypeTable,
+    /// Constructor, method, or function for each ClassName.SubroutineName
+    subroutine_type: HashMap<String, SubroutineType>,
 }
 
 impl ClassParseInfo {
@@ -357,6 +414,7 @@ impl ClassParseInfo {
             class_symbol_table: ClassSymbolTable::new(),
             symbol_table_per_method: HashMap::new(),
             return_type: ReturnTypeTable::new(),
+            subroutine_type: HashMap::new(),
         }
     }
 }
@@ -488,6 +546,79 @@ impl Class {
         }
         Ok(output)
     }
+
+    /// Get a text line for each class variable (ex: 'field int x')
+    pub fn class_var_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for c in &self.class_vars {
+            for n in &c.var_names {
+                lines.push(format!(
+                    "{} {} {}",
+                    c.prefix.value,
+                    type_name(&c.var_type),
+                    n.value
+                ));
+            }
+        }
+        lines
+    }
+
+    /// Get a signature line for each subroutine (ex: 'method void move(int dx)')
+    pub fn subroutine_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for s in &self.subroutines {
+            let params: Vec<String> = s
+                .param_list
+                .param_type
+                .iter()
+                .zip(s.param_list.name.iter())
+                .map(|(t, n)| format!("{} {}", type_name(t), n.value))
+                .collect();
+            lines.push(format!(
+                "{} {} {}({})",
+                s.prefix.value,
+                type_name(&s.return_type),
+                s.name.value,
+                params.join(", ")
+            ));
+        }
+        lines
+    }
+
+    /// Get names of classes used as types of class variables
+    pub fn class_var_types(&self) -> BTreeSet<String> {
+        self.class_vars
+            .iter()
+            .filter_map(|c| c.var_type.identifier())
+            .map(|id| id.value.clone())
+            .collect()
+    }
+
+    /// Get names of classes used as types within subroutines
+    /// (return type, parameters, and local variables)
+    pub fn subroutine_types(&self) -> BTreeSet<String> {
+        let mut types = BTreeSet::new();
+        for s in &self.subroutines {
+            let tokens = std::iter::once(&s.return_type)
+                .chain(s.param_list.param_type.iter())
+                .chain(s.body.variables.iter().map(|v| &v.var_type));
+            for t in tokens {
+                if let Some(id) = t.identifier() {
+                    types.insert(id.value.clone());
+                }
+            }
+        }
+        types
+    }
+}
+
+/// Get type name from a type token (a Keyword or an Identifier)
+fn type_name(t: &Token) -> &str {
+    match t {
+        Token::Keyword(k) => k.value,
+        Token::Identifier(id) => &id.value,
+        _other => panic!("Unexpected token type: {:?}", _other),
+    }
 }
 
 struct ClassVarDec {
@@ -700,9 +831,7 @@ fn parse_parameter_list(
         return Err(Error::UnexpectedSymbol {
             symbol: s.value,
             index: current_idx,
-            file: file!(),
-            line: line!(),
-            column: column!(),
+            span: s.span,
         });
     }
     target.block.start = s.to_owned();
@@ -730,9 +859,7 @@ fn parse_parameter_list(
                         return Err(Error::UnexpectedSymbol {
                             symbol: _other,
                             index: current_idx,
-                            file: file!(),
-                            line: line!(),
-                            column: column!(),
+                            span: s.span,
                         });
                     }
                 }
@@ -763,9 +890,7 @@ fn parse_parameter_list(
                 return Err(Error::UnexpectedToken {
                     token: _other.to_owned(),
                     index: current_idx,
-                    file: file!(),
-                    line: line!(),
-                    column: column!(),
+                    span: _other.span(),
                 });
             }
         }
@@ -826,9 +951,7 @@ fn parse_subroutine_body(
         return Err(Error::UnexpectedSymbol {
             symbol: s.value,
             index: current_idx,
-            file: file!(),
-            line: line!(),
-            column: column!(),
+            span: s.span,
         });
     }
     target.block.start = s.to_owned();
@@ -848,9 +971,7 @@ fn parse_subroutine_body(
                         return Err(Error::UnexpectedSymbol {
                             symbol: _other,
                             index: current_idx,
-                            file: file!(),
-                            line: line!(),
-                            column: column!(),
+                            span: s.span,
                         });
                     }
                 }
@@ -883,7 +1004,10 @@ fn parse_subroutine_body(
                             parse_statements(ctx, &mut target.statements, tokens, current_idx)?
                     }
                     _other => {
-                        return Err(Error::UnexpectedKeyword(_other));
+                        return Err(Error::UnexpectedKeyword {
+                            keyword: _other,
+                            span: k.span,
+                        });
                     }
                 }
             }
@@ -891,9 +1015,7 @@ fn parse_subroutine_body(
                 return Err(Error::UnexpectedToken {
                     token: _other.to_owned(),
                     index: current_idx,
-                    file: file!(),
-                    line: line!(),
-                    column: column!(),
+                    span: _other.span(),
                 });
             }
         }
@@ -986,9 +1108,7 @@ fn parse_var_dec(
                         return Err(Error::UnexpectedSymbol {
                             symbol: _other,
                             index: current_idx,
-                            file: file!(),
-                            line: line!(),
-                            column: column!(),
+                            span: s.span,
                         });
                     }
                 }
@@ -997,9 +1117,7 @@ fn parse_var_dec(
                 return Err(Error::UnexpectedToken {
                     token: _other.to_owned(),
                     index: current_idx,
-                    file: file!(),
-                    line: line!(),
-                    column: column!(),
+                    span: _other.span(),
                 });
             }
         }
@@ -1259,19 +1377,10 @@ impl VarNameTerm {
             }
             None => {
                 // We look for the variable in class table
-                match class_info.class_symbol_table.table.get(&self.name.value) {
-                    Some(entry) => {
-                        // We found the variable in the class table
-                        let segment = class_symbol_category_to_segment(&entry.category);
-                        output
-                            .push_str(&format!("{} {} {}{}", PUSH, segment, entry.index, NEW_LINE));
-                        Ok(())
-                    }
-                    None => panic!(
-                        "Var {} not found in method or class symbol table",
-                        self.name.value
-                    ),
-                }
+                let entry = class_info.class_symbol_table.lookup(&self.name, state)?;
+                let segment = class_symbol_category_to_segment(&entry.category);
+                output.push_str(&format!("{} {} {}{}", PUSH, segment, entry.index, NEW_LINE));
+                Ok(())
             }
         }
     }
@@ -1296,7 +1405,7 @@ impl KeywordTerm {
         output: &mut String,
         state: &CompileState,
     ) -> Result<(), Error> {
-        match self.keyword.value.as_str() {
+        match self.keyword.value {
             tokenizer::TRUE => {
                 // true is -1 so we not a 0
                 output.push_str(&format!(
@@ -1413,7 +1522,7 @@ impl ArrayVarTerm {
             return self.deref_array(info, output, state, segment, entry.index);
         } else {
             // Should be on class table
-            let entry = class_info.class_symbol_table.table.get(var_name).unwrap();
+            let entry = class_info.class_symbol_table.lookup(&self.name, state)?;
             let segment = class_symbol_category_to_segment(&entry.category);
             return self.deref_array(info, output, state, segment, entry.index);
         }
@@ -1537,7 +1646,10 @@ fn parse_term(
                     };
                     Ok((Term::Keyword(k), current_idx + 1))
                 }
-                _other => Err(Error::UnexpectedKeyword(_other)),
+                _other => Err(Error::UnexpectedKeyword {
+                    keyword: _other,
+                    span: kw.span,
+                }),
             }
         }
         Token::Identifier(id) => {
@@ -1563,9 +1675,7 @@ fn parse_term(
                                 return Err(Error::UnexpectedSymbol {
                                     symbol: close_brace.value,
                                     index: current_idx,
-                                    file: file!(),
-                                    line: line!(),
-                                    column: column!(),
+                                    span: close_brace.span,
                                 });
                             }
                             arr.arr.block.end = close_brace.to_owned();
@@ -1573,7 +1683,10 @@ fn parse_term(
                         }
                         '(' => {
                             // parse subroutineCall (functionCall)
-                            panic!("NotImplemented");
+                            let mut sc = SubroutineCallTerm::new();
+                            current_idx =
+                                parse_subroutine_call(ctx, &mut sc.call, tokens, current_idx - 1)?;
+                            Ok((Term::Subroutine(sc), current_idx))
                         }
                         '.' => {
                             // parse subroutineCall (methodCall)
@@ -1589,9 +1702,7 @@ fn parse_term(
                                 return Err(Error::UnexpectedSymbol {
                                     symbol: open_paren.value,
                                     index: current_idx,
-                                    file: file!(),
-                                    line: line!(),
-                                    column: column!(),
+                                    span: open_paren.span,
                                 });
                             }
                             mc.parameter_block.start = open_paren.to_owned();
@@ -1606,9 +1717,7 @@ fn parse_term(
                                 return Err(Error::UnexpectedSymbol {
                                     symbol: close_paren.value,
                                     index: current_idx,
-                                    file: file!(),
-                                    line: line!(),
-                                    column: column!(),
+                                    span: close_paren.span,
                                 });
                             }
                             mc.parameter_block.end = close_paren.to_owned();
@@ -1646,9 +1755,7 @@ fn parse_term(
                         return Err(Error::UnexpectedSymbol {
                             symbol: end.value,
                             index: current_idx,
-                            file: file!(),
-                            line: line!(),
-                            column: column!(),
+                            span: end.span,
                         });
                     }
                     exp.block.end = end.to_owned();
@@ -1666,9 +1773,7 @@ fn parse_term(
                 _other => Err(Error::UnexpectedSymbol {
                     symbol: _other,
                     index: current_idx,
-                    file: file!(),
-                    line: line!(),
-                    column: column!(),
+                    span: s.span,
                 }),
             }
         }
@@ -1932,9 +2037,7 @@ impl LetStatement {
                 // Should be on class table
                 let entry = class_info
                     .class_symbol_table
-                    .table
-                    .get(&self.var_name.value)
-                    .unwrap();
+                    .lookup(&self.var_name, state)?;
                 let segment = class_symbol_category_to_segment(&entry.category);
                 return self.assign_to_array(info, output, state, segment, entry.index);
             }
@@ -1961,9 +2064,7 @@ impl LetStatement {
                 // Should be on class table
                 let entry = class_info
                     .class_symbol_table
-                    .table
-                    .get(&self.var_name.value)
-                    .unwrap();
+                    .lookup(&self.var_name, state)?;
                 let segment = class_symbol_category_to_segment(&entry.category);
                 output.push_str(&format!("{} {} {}{}", POP, segment, entry.index, NEW_LINE));
                 Ok(())
@@ -2196,24 +2297,32 @@ impl ImplicitMethodCall {
         output: &mut String,
         state: &CompileState,
     ) -> Result<(), Error> {
-        // Implicit method calls should only be used from constructors and methods that can refer to THIS
-        assert!(matches!(
-            state.func_state.subroutine_type,
-            SubroutineType::Constructor | SubroutineType::Method
-        ));
-        // Push THIS first, and then push other parameters
-        output.push_str(&format!("{} {} 0{}", PUSH, POINTER, NEW_LINE));
-        self.parameters.compile(info, output, state)?;
         let class_info = info.info_per_class.get(&state.class_name).unwrap();
         // full name of the target function we're calling
         let func_full_name = format!("{}.{}", state.class_name, self.name.value);
-        let line = format!(
-            "{} {} {}{}",
-            CALL,
-            func_full_name,
-            self.parameters.list.len() + 1, // +1 for the instance we just pushed
-            NEW_LINE
-        );
+        let callee_type = class_info
+            .subroutine_type
+            .get(&func_full_name)
+            .ok_or_else(|| Error::UndefinedSubroutine {
+                name: func_full_name.clone(),
+                span: self.name.span,
+            })?;
+        let mut param_num = self.parameters.list.len();
+        if matches!(callee_type, SubroutineType::Method) {
+            // Methods should only be called from constructors and methods that can refer to THIS
+            if matches!(state.func_state.subroutine_type, SubroutineType::Function) {
+                return Err(Error::MethodInFunction {
+                    name: func_full_name,
+                    function: state.full_method_name(),
+                    span: self.name.span,
+                });
+            }
+            // Push THIS first, and then push other parameters
+            output.push_str(&format!("{} {} 0{}", PUSH, POINTER, NEW_LINE));
+            param_num += 1; // +1 for the instance we just pushed
+        }
+        self.parameters.compile(info, output, state)?;
+        let line = format!("{} {} {}{}", CALL, func_full_name, param_num, NEW_LINE);
         output.push_str(&line);
         // Search for the caller's return type from current class
         let rt = class_info.return_type.table.get(&func_full_name).unwrap();
@@ -2294,9 +2403,7 @@ impl ExplicitMethodCall {
             // If the source is a class instance, we first need to push the instance and then the parameters
             let entry = current_class_info
                 .class_symbol_table
-                .table
-                .get(name)
-                .unwrap();
+                .lookup(&self.source_name, state)?;
             let segment = class_symbol_category_to_segment(&entry.category);
             let line = format!("{} {} {}{}", PUSH, segment, entry.index, NEW_LINE);
             output.push_str(&line);
@@ -2489,19 +2596,27 @@ impl ReturnStatement {
         return_type: &ReturnType,
     ) -> Result<(), Error> {
         // Set return value based on return type
-        match return_type {
-            ReturnType::Void => {
-                // return 0 for void functions
+        match (return_type, &self.expression) {
+            (ReturnType::Void, None) => {
+                // VM functions always return a value so void functions return 0.
+                // The caller drops it
                 output.push_str(&format!("{} {} 0{}", PUSH, CONSTANT, NEW_LINE));
             }
-            _other => {
-                // evaluate expression if it exists and push that value
-                if self.expression.is_some() {
-                    self.expression
-                        .as_ref()
-                        .unwrap()
-                        .compile(info, output, state)?;
-                }
+            (ReturnType::Void, Some(_)) => {
+                return Err(Error::ReturnValueInVoid {
+                    function: state.full_method_name(),
+                    span: self.keyword.span,
+                });
+            }
+            (_, Some(expression)) => {
+                // evaluate expression and push that value
+                expression.compile(info, output, state)?;
+            }
+            (_, None) => {
+                return Err(Error::MissingReturnValue {
+                    function: state.full_method_name(),
+                    span: self.keyword.span,
+                });
             }
         }
         output.push_str(&format!("return{}", NEW_LINE));
@@ -2611,9 +2726,7 @@ fn parse_let_statement(
                     return Err(Error::UnexpectedSymbol {
                         symbol: end_token.value,
                         index: current_idx,
-                        file: file!(),
-                        line: line!(),
-                        column: column!(),
+                        span: end_token.span,
                     });
                 }
                 arr.block.end = end_token.to_owned();
@@ -2630,9 +2743,7 @@ fn parse_let_statement(
                 return Err(Error::UnexpectedSymbol {
                     symbol: _other,
                     index: current_idx,
-                    file: file!(),
-                    line: line!(),
-                    column: column!(),
+                    span: s.span,
                 });
             }
         }
@@ -2652,9 +2763,7 @@ fn parse_else_block(
         return Err(Error::UnexpectedSymbol {
             symbol: block_start.value,
             index: current_idx,
-            file: file!(),
-            line: line!(),
-            column: column!(),
+            span: block_start.span,
         });
     }
     target.statement_block.start = block_start.to_owned();
@@ -2664,9 +2773,7 @@ fn parse_else_block(
         return Err(Error::UnexpectedSymbol {
             symbol: block_end.value,
             index: current_idx,
-            file: file!(),
-            line: line!(),
-            column: column!(),
+            span: block_end.span,
         });
     }
     target.statement_block.end = block_end.to_owned();
@@ -2685,9 +2792,7 @@ fn parse_if_statement(
         return Err(Error::UnexpectedSymbol {
             symbol: cond_start.value,
             index: current_idx,
-            file: file!(),
-            line: line!(),
-            column: column!(),
+            span: cond_start.span,
         });
     }
     target.cond_block.start = cond_start.to_owned();
@@ -2697,9 +2802,7 @@ fn parse_if_statement(
         return Err(Error::UnexpectedSymbol {
             symbol: cond_end.value,
             index: current_idx,
-            file: file!(),
-            line: line!(),
-            column: column!(),
+            span: cond_end.span,
         });
     }
     target.cond_block.end = cond_end.to_owned();
@@ -2709,9 +2812,7 @@ fn parse_if_statement(
         return Err(Error::UnexpectedSymbol {
             symbol: body_start.value,
             index: current_idx,
-            file: file!(),
-            line: line!(),
-            column: column!(),
+            span: body_start.span,
         });
     }
     target.statement_block.start = body_start.to_owned();
@@ -2721,9 +2822,7 @@ fn parse_if_statement(
         return Err(Error::UnexpectedSymbol {
             symbol: body_end.value,
             index: current_idx,
-            file: file!(),
-            line: line!(),
-            column: column!(),
+            span: body_end.span,
         });
     }
     target.statement_block.end = body_end.to_owned();
@@ -2771,9 +2870,7 @@ fn parse_subroutine_call(
                 return Err(Error::UnexpectedSymbol {
                     symbol: end_token.value,
                     index: current_idx,
-                    file: file!(),
-                    line: line!(),
-                    column: column!(),
+                    span: end_token.span,
                 });
             }
             f.parameter_block.end = end_token.to_owned();
@@ -2793,9 +2890,7 @@ fn parse_subroutine_call(
                 return Err(Error::UnexpectedSymbol {
                     symbol: start.value,
                     index: current_idx,
-                    file: file!(),
-                    line: line!(),
-                    column: column!(),
+                    span: start.span,
                 });
             }
             m.parameter_block.start = start.to_owned();
@@ -2805,9 +2900,7 @@ fn parse_subroutine_call(
                 return Err(Error::UnexpectedSymbol {
                     symbol: end.value,
                     index: current_idx,
-                    file: file!(),
-                    line: line!(),
-                    column: column!(),
+                    span: end.span,
                 });
             }
             m.parameter_block.end = end.to_owned();
@@ -2818,9 +2911,7 @@ fn parse_subroutine_call(
             return Err(Error::UnexpectedSymbol {
                 symbol: _other,
                 index: current_idx,
-                file: file!(),
-                line: line!(),
-                column: column!(),
+                span: next.span,
             });
         }
     }
@@ -2839,9 +2930,7 @@ fn parse_do_statement(
         return Err(Error::UnexpectedSymbol {
             symbol: end_token.value,
             index: current_idx,
-            file: file!(),
-            line: line!(),
-            column: column!(),
+            span: end_token.span,
         });
     }
     target.end = end_token.to_owned();
@@ -2874,9 +2963,7 @@ fn parse_return_statement(
                         return Err(Error::UnexpectedSymbol {
                             symbol: end.value,
                             index: current_idx,
-                            file: file!(),
-                            line: line!(),
-                            column: column!(),
+                            span: end.span,
                         });
                     }
                     target.end = end.to_owned();
@@ -2894,9 +2981,7 @@ fn parse_return_statement(
                 return Err(Error::UnexpectedSymbol {
                     symbol: end.value,
                     index: current_idx,
-                    file: file!(),
-                    line: line!(),
-                    column: column!(),
+                    span: end.span,
                 });
             }
             target.end = end.to_owned();
@@ -2918,9 +3003,7 @@ fn parse_while_statement(
         return Err(Error::UnexpectedSymbol {
             symbol: cond_start.value,
             index: current_idx,
-            file: file!(),
-            line: line!(),
-            column: column!(),
+            span: cond_start.span,
         });
     }
     target.condition.start = cond_start.to_owned();
@@ -2930,9 +3013,7 @@ fn parse_while_statement(
         return Err(Error::UnexpectedSymbol {
             symbol: cond_end.value,
             index: current_idx,
-            file: file!(),
-            line: line!(),
-            column: column!(),
+            span: cond_end.span,
         });
     }
     target.condition.end = cond_end.to_owned();
@@ -2942,9 +3023,7 @@ fn parse_while_statement(
         return Err(Error::UnexpectedSymbol {
             symbol: body_start.value,
             index: current_idx,
-            file: file!(),
-            line: line!(),
-            column: column!(),
+            span: body_start.span,
         });
     }
     target.body.start = body_start.to_owned();
@@ -2954,9 +3033,7 @@ fn parse_while_statement(
         return Err(Error::UnexpectedSymbol {
             symbol: body_end.value,
             index: current_idx,
-            file: file!(),
-            line: line!(),
-            column: column!(),
+            span: body_end.span,
         });
     }
     target.body.end = body_end.to_owned();
@@ -3005,7 +3082,10 @@ fn parse_statements(
                     target.list.push(Statement::Return(r));
                 }
                 _other => {
-                    return Err(Error::UnexpectedKeyword(_other));
+                    return Err(Error::UnexpectedKeyword {
+                        keyword: _other,
+                        span: k.span,
+                    });
                 }
             },
             Token::Symbol(s) => {
@@ -3019,9 +3099,7 @@ fn parse_statements(
                         return Err(Error::UnexpectedSymbol {
                             symbol: _other,
                             index: current_idx,
-                            file: file!(),
-                            line: line!(),
-                            column: column!(),
+                            span: s.span,
                         });
                     }
                 }
@@ -3030,9 +3108,7 @@ fn parse_statements(
                 return Err(Error::UnexpectedToken {
                     token: _other.to_owned(),
                     index: current_idx,
-                    file: file!(),
-                    line: line!(),
-                    column: column!(),
+                    span: _other.span(),
                 });
             }
         }
@@ -3087,16 +3163,19 @@ fn parse_subroutine_dec(
             KeywordType::Int | KeywordType::Char | KeywordType::Boolean | KeywordType::Void => {
                 token
             }
-            _other => return Err(Error::UnexpectedKeyword(_other)),
+            _other => {
+                return Err(Error::UnexpectedKeyword {
+                    keyword: _other,
+                    span: word.span,
+                })
+            }
         },
         Token::Identifier(_) => token,
         _other => {
             return Err(Error::UnexpectedToken {
                 token: _other.to_owned(),
                 index: current_idx,
-                file: file!(),
-                line: line!(),
-                column: column!(),
+                span: _other.span(),
             })
         }
     };
@@ -3108,6 +3187,10 @@ fn parse_subroutine_dec(
     info.return_type
         .table
         .insert(full_name.clone(), token_to_return_type(rt));
+    info.subroutine_type.insert(
+        full_name.clone(),
+        keyword_to_subroutine_type(target.prefix.value),
+    );
     current_idx = parse_parameter_list(info, &mut target.param_list, tokens, current_idx + 1)?;
     // add all parameters to symbol table
     for i in 0..target.param_list.name.len() {
@@ -3137,7 +3220,10 @@ fn parse_type<'a>(
     match token {
         Token::Keyword(word) => match word.keyword() {
             KeywordType::Int | KeywordType::Char | KeywordType::Boolean => Ok(token),
-            _other => Err(Error::UnexpectedKeyword(_other)),
+            _other => Err(Error::UnexpectedKeyword {
+                keyword: _other,
+                span: word.span,
+            }),
         },
         Token::Identifier(_id) => {
             // TODO:
@@ -3151,9 +3237,7 @@ fn parse_type<'a>(
         _other => Err(Error::UnexpectedToken {
             token: _other.to_owned(),
             index: token_index,
-            file: file!(),
-            line: line!(),
-            column: column!(),
+            span: _other.span(),
         }),
     }
 }
@@ -3191,9 +3275,7 @@ fn parse_class_var_dec(
                         return Err(Error::UnexpectedSymbol {
                             symbol: _other,
                             index: current_idx,
-                            file: file!(),
-                            line: line!(),
-                            column: column!(),
+                            span: s.span,
                         });
                     }
                 }
@@ -3210,9 +3292,7 @@ fn parse_class_var_dec(
                 return Err(Error::UnexpectedToken {
                     token: _other.to_owned(),
                     index: current_idx,
-                    file: file!(),
-                    line: line!(),
-                    column: column!(),
+                    span: _other.span(),
                 });
             }
         }
@@ -3238,9 +3318,7 @@ fn parse_class(
         return Err(Error::UnexpectedSymbol {
             symbol: open_brace.value,
             index: current_idx,
-            file: file!(),
-            line: line!(),
-            column: column!(),
+            span: open_brace.span,
         });
     }
     class.begin_symbol = open_brace.to_owned();
@@ -3254,9 +3332,7 @@ fn parse_class(
                     return Err(Error::UnexpectedSymbol {
                         symbol: close_brace.value,
                         index: current_idx,
-                        file: file!(),
-                        line: line!(),
-                        column: column!(),
+                        span: close_brace.span,
                     });
                 }
                 class.end_symbol = close_brace.to_owned();
@@ -3283,7 +3359,10 @@ fn parse_class(
                         class.subroutines.push(sd);
                     }
                     _other => {
-                        return Err(Error::UnexpectedKeyword(keyword.keyword()));
+                        return Err(Error::UnexpectedKeyword {
+                            keyword: keyword.keyword(),
+                            span: keyword.span,
+                        });
                     }
                 }
             }
@@ -3291,9 +3370,7 @@ fn parse_class(
                 return Err(Error::UnexpectedToken {
                     token: _other.to_owned(),
                     index: current_idx,
-                    file: file!(),
-                    line: line!(),
-                    column: column!(),
+                    span: _other.span(),
                 });
             }
         }
@@ -3302,15 +3379,19 @@ fn parse_class(
 }
 
 /// Parse specified file and generate an internal tree representation
-pub fn parse_file(
+/// Parse a class from given reader (ex: a BufReader of a file, or the bytes of a string)
+pub fn parse_file<R: std::io::BufRead>(
     info: &mut ClassParseInfo,
-    file_reader: &mut std::io::BufReader<std::fs::File>,
+    reader: &mut R,
 ) -> Result<Class, Error> {
-    let tokens = generate_token_list(file_reader);
+    let tokens = generate_token_list(reader).map_err(Error::Tokenize)?;
     let mut current_index = 0;
     let keyword = tokens.list[current_index].keyword().unwrap();
     if !matches!(keyword.keyword(), KeywordType::Class) {
-        return Err(Error::UnexpectedKeyword(keyword.keyword()));
+        return Err(Error::UnexpectedKeyword {
+            keyword: keyword.keyword(),
+            span: keyword.span,
+        });
     }
     let mut class = Class::new();
     class.prefix = keyword.clone();
@@ -0,0 +1,462 @@
+use super::{
+    token_to_return_type, CallType, Class, DirectoryParseInfo, Expression, MethodSymbolCategory,
+    ReturnType, Statement, StatementList, SubroutineCall, SymbolType, Term,
+};
+use crate::tokenizer::{self, Span};
+use std::fmt;
+
+const ARRAY: &str = "Array";
+const STRING: &str = "String";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// Allowed by the VM but most likely a mistake
+    Warning,
+    Error,
+}
+
+/// Problem found by the type checker
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(
+            f,
+            "{}:{} {}: {}",
+            self.span.line, self.span.column, severity, self.message
+        )
+    }
+}
+
+/// Type of a value while type checking
+#[derive(Debug, Clone, PartialEq)]
+enum Type {
+    Int,
+    Char,
+    Boolean,
+    Class(String),
+    /// null can be any object
+    Null,
+    Void,
+    /// Type we cannot tell (ex: array elements), which is compatible with anything
+    Unknown,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "{}", tokenizer::INT),
+            Type::Char => write!(f, "{}", tokenizer::CHAR),
+            Type::Boolean => write!(f, "{}", tokenizer::BOOL),
+            Type::Class(name) => write!(f, "{}", name),
+            Type::Null => write!(f, "{}", tokenizer::NULL),
+            Type::Void => write!(f, "{}", tokenizer::VOID),
+            Type::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+impl From<&SymbolType> for Type {
+    fn from(t: &SymbolType) -> Type {
+        match t {
+            SymbolType::Int => Type::Int,
+            SymbolType::Char => Type::Char,
+            SymbolType::Boolean => Type::Boolean,
+            SymbolType::Class(name) => Type::Class(name.clone()),
+        }
+    }
+}
+
+impl From<&ReturnType> for Type {
+    fn from(t: &ReturnType) -> Type {
+        match t {
+            ReturnType::Void => Type::Void,
+            ReturnType::Int => Type::Int,
+            ReturnType::Char => Type::Char,
+            ReturnType::Boolean => Type::Boolean,
+            ReturnType::Class(name) => Type::Class(name.clone()),
+        }
+    }
+}
+
+/// Whether the type can be used as a number.
+/// char is a number in Jack
+fn is_numeric(t: &Type) -> bool {
+    matches!(t, Type::Int | Type::Char | Type::Unknown)
+}
+
+/// Whether a value of a type can be assigned to a variable of another type
+fn is_assignable(target: &Type, value: &Type) -> bool {
+    match (target, value) {
+        (Type::Unknown, _) | (_, Type::Unknown) => true,
+        (Type::Int | Type::Char, Type::Int | Type::Char) => true,
+        (Type::Class(_), Type::Null) => true,
+        // Arrays hold the address of any object, and Memory.alloc returns an Array
+        (Type::Class(c), Type::Class(_) | Type::Int) if c == ARRAY => true,
+        (Type::Class(_), Type::Class(c)) if c == ARRAY => true,
+        (t, v) => t == v,
+    }
+}
+
+/// Position of the first token of a term
+fn term_span(term: &Term) -> Span {
+    match term {
+        Term::Integer(i) => i.integer.span,
+        Term::String(s) => s.string.span,
+        Term::Keyword(k) => k.keyword.span,
+        Term::VarName(v) => v.name.span,
+        Term::ArrayVar(av) => av.name.span,
+        Term::Subroutine(sr) => match &sr.call.call {
+            CallType::Implicit(f) => f.name.span,
+            CallType::Explicit(m) => m.source_name.span,
+        },
+        Term::ExpresssionInParenthesis(e) => e.block.start.span,
+        Term::UnaryOp(u) => u.op.span,
+    }
+}
+
+fn expression_span(expression: &Expression) -> Span {
+    term_span(&expression.terms[0])
+}
+
+/// State while checking subroutines of a class
+struct Checker<'a> {
+    info: &'a DirectoryParseInfo,
+    class_name: &'a str,
+    /// ClassName.SubroutineName of the current subroutine
+    subroutine: String,
+    return_type: Type,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn report(&mut self, severity: Severity, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            message,
+            span,
+        });
+    }
+
+    /// Type of a variable in the current subroutine or class
+    fn var_type(&self, name: &str) -> Option<Type> {
+        let class_info = self.info.info_per_class.get(self.class_name)?;
+        if let Some(entry) = class_info
+            .symbol_table_per_method
+            .get(&self.subroutine)
+            .and_then(|t| t.table.get(name))
+        {
+            return Some(Type::from(&entry.symbol_type));
+        }
+        class_info
+            .class_symbol_table
+            .table
+            .get(name)
+            .map(|entry| Type::from(&entry.symbol_type))
+    }
+
+    fn statements(&mut self, statements: &StatementList) {
+        for s in &statements.list {
+            self.statement(s);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let(l) => {
+                let value = self.expression(&l.right_hand_side);
+                if let Some(arr) = &l.array {
+                    // Array elements can be any type
+                    self.index(&arr.expression);
+                    return;
+                }
+                let target = self.var_type(&l.var_name.value).unwrap_or(Type::Unknown);
+                if !is_assignable(&target, &value) {
+                    self.report(
+                        Severity::Error,
+                        expression_span(&l.right_hand_side),
+                        format!(
+                            "Cannot assign {} to {} of type {}",
+                            value, l.var_name.value, target
+                        ),
+                    );
+                }
+            }
+            Statement::If(i) => {
+                self.condition(&i.condition);
+                self.statements(&i.statements);
+                if let Some(e) = &i.else_block {
+                    self.statements(&e.statements);
+                }
+            }
+            Statement::While(w) => {
+                self.condition(&w.expression);
+                self.statements(&w.statements);
+            }
+            Statement::Do(d) => {
+                self.call(&d.subroutine_call);
+            }
+            Statement::Return(r) => {
+                // Missing or extra return values are reported by the compiler
+                if let Some(e) = &r.expression {
+                    let value = self.expression(e);
+                    if self.return_type != Type::Void && !is_assignable(&self.return_type, &value) {
+                        let message = format!(
+                            "Cannot return {} from {} of type {}",
+                            value, self.subroutine, self.return_type
+                        );
+                        self.report(Severity::Error, expression_span(e), message);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Conditions should be boolean.
+    /// Numbers work since anything other than 0 is true, so they are only warned
+    fn condition(&mut self, expression: &Expression) {
+        let t = self.expression(expression);
+        let severity = match t {
+            Type::Boolean | Type::Unknown => return,
+            Type::Int | Type::Char => Severity::Warning,
+            _ => Severity::Error,
+        };
+        self.report(
+            severity,
+            expression_span(expression),
+            format!("Condition is {} instead of boolean", t),
+        );
+    }
+
+    fn index(&mut self, expression: &Expression) {
+        let t = self.expression(expression);
+        if !is_numeric(&t) {
+            self.report(
+                Severity::Error,
+                expression_span(expression),
+                format!("Array index must be int but got {}", t),
+            );
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Type {
+        let mut left = self.term(&expression.terms[0]);
+        for (op, term) in expression.ops.iter().zip(&expression.terms[1..]) {
+            let right = self.term(term);
+            left = self.binary_op(op.symbol.value, op.symbol.span, &left, &right);
+        }
+        left
+    }
+
+    /// Check operands of a binary operator and get the type of the result
+    fn binary_op(&mut self, op: char, span: Span, left: &Type, right: &Type) -> Type {
+        match op {
+            '+' | '-' | '*' | '/' | '<' | '>' => {
+                for t in [left, right] {
+                    if !is_numeric(t) {
+                        self.report(
+                            Severity::Error,
+                            span,
+                            format!("Operator {} expects int but got {}", op, t),
+                        );
+                    }
+                }
+                if matches!(op, '<' | '>') {
+                    Type::Boolean
+                } else {
+                    Type::Int
+                }
+            }
+            '&' | '|' => match (left, right) {
+                (Type::Boolean | Type::Unknown, Type::Boolean | Type::Unknown) => Type::Boolean,
+                (l, r) if is_numeric(l) && is_numeric(r) => Type::Int,
+                (l, r) => {
+                    self.report(
+                        Severity::Error,
+                        span,
+                        format!(
+                            "Operator {} expects boolean or int operands but got {} and {}",
+                            op, l, r
+                        ),
+                    );
+                    Type::Unknown
+                }
+            },
+            '=' => {
+                if !is_assignable(left, right) && !is_assignable(right, left) {
+                    self.report(
+                        Severity::Warning,
+                        span,
+                        format!("Comparing {} with {}", left, right),
+                    );
+                }
+                Type::Boolean
+            }
+            _ => Type::Unknown,
+        }
+    }
+
+    fn term(&mut self, term: &Term) -> Type {
+        match term {
+            Term::Integer(_) => Type::Int,
+            Term::String(_) => Type::Class(STRING.to_string()),
+            Term::Keyword(k) => match k.keyword.value {
+                tokenizer::TRUE | tokenizer::FALSE => Type::Boolean,
+                tokenizer::NULL => Type::Null,
+                tokenizer::THIS => Type::Class(self.class_name.to_string()),
+                _ => Type::Unknown,
+            },
+            // Undefined variables are reported by the compiler
+            Term::VarName(v) => self.var_type(&v.name.value).unwrap_or(Type::Unknown),
+            Term::ArrayVar(av) => {
+                self.index(&av.arr.expression);
+                Type::Unknown
+            }
+            Term::Subroutine(sr) => self.call(&sr.call),
+            Term::ExpresssionInParenthesis(e) => self.expression(&e.expression),
+            Term::UnaryOp(u) => {
+                let t = self.term(&u.term);
+                match u.op.value {
+                    '-' if is_numeric(&t) => Type::Int,
+                    '~' if t == Type::Boolean || t == Type::Unknown => Type::Boolean,
+                    '~' if is_numeric(&t) => Type::Int,
+                    op => {
+                        self.report(
+                            Severity::Error,
+                            u.op.span,
+                            format!("Operator {} cannot be used on {}", op, t),
+                        );
+                        Type::Unknown
+                    }
+                }
+            }
+        }
+    }
+
+    /// Check arguments of a subroutine call and get its return type
+    fn call(&mut self, call: &SubroutineCall) -> Type {
+        let (full_name, span, parameters) = match &call.call {
+            CallType::Implicit(f) => (
+                format!("{}.{}", self.class_name, f.name.value),
+                f.name.span,
+                &f.parameters,
+            ),
+            CallType::Explicit(m) => {
+                let class_name = match self.var_type(&m.source_name.value) {
+                    Some(Type::Class(name)) => name,
+                    Some(t) => {
+                        let message = format!(
+                            "Cannot call {} on {} of type {}",
+                            m.method_name.value, m.source_name.value, t
+                        );
+                        self.report(Severity::Error, m.source_name.span, message);
+                        return Type::Unknown;
+                    }
+                    // Not a variable so it should be a class name
+                    None => m.source_name.value.clone(),
+                };
+                (
+                    format!("{}.{}", class_name, m.method_name.value),
+                    m.method_name.span,
+                    &m.parameters,
+                )
+            }
+        };
+        let arguments: Vec<(Type, Span)> = parameters
+            .list
+            .iter()
+            .map(|e| (self.expression(e), expression_span(e)))
+            .collect();
+        let class_name = full_name.split('.').next().unwrap();
+        if let Some(class_info) = self.info.info_per_class.get(class_name) {
+            // Parameters are only known for classes in the directory
+            match class_info.symbol_table_per_method.get(&full_name) {
+                Some(table) => {
+                    let mut params: Vec<(usize, Type)> = table
+                        .table
+                        .iter()
+                        .filter(|(name, entry)| {
+                            matches!(entry.category, MethodSymbolCategory::Argument)
+                                && name.as_str() != tokenizer::THIS
+                        })
+                        .map(|(_, entry)| (entry.index, Type::from(&entry.symbol_type)))
+                        .collect();
+                    params.sort_by_key(|(index, _)| *index);
+                    self.arguments(&full_name, span, &params, &arguments);
+                }
+                None => {
+                    self.report(
+                        Severity::Error,
+                        span,
+                        format!("Subroutine {} is not defined", full_name),
+                    );
+                    return Type::Unknown;
+                }
+            }
+        }
+        self.info
+            .get_return_type(&full_name)
+            .map(Type::from)
+            .unwrap_or(Type::Unknown)
+    }
+
+    fn arguments(
+        &mut self,
+        full_name: &str,
+        span: Span,
+        params: &[(usize, Type)],
+        arguments: &[(Type, Span)],
+    ) {
+        if params.len() != arguments.len() {
+            let message = format!(
+                "{} expects {} arguments but got {}",
+                full_name,
+                params.len(),
+                arguments.len()
+            );
+            self.report(Severity::Error, span, message);
+            return;
+        }
+        for (i, ((_, param), (arg, arg_span))) in params.iter().zip(arguments).enumerate() {
+            if !is_assignable(param, arg) {
+                let message = format!(
+                    "Argument {} of {} expects {} but got {}",
+                    i + 1,
+                    full_name,
+                    param,
+                    arg
+                );
+                self.report(Severity::Error, *arg_span, message);
+            }
+        }
+    }
+}
+
+impl Class {
+    /// Check types of assignments, operators, subroutine arguments, and conditions.
+    /// The class and all classes it uses should be in the directory information
+    pub fn typecheck(&self, info: &DirectoryParseInfo) -> Vec<Diagnostic> {
+        let mut checker = Checker {
+            info,
+            class_name: &self.name.value,
+            subroutine: String::new(),
+            return_type: Type::Void,
+            diagnostics: Vec::new(),
+        };
+        for s in &self.subroutines {
+            checker.subroutine = format!("{}.{}", self.name.value, s.name.value);
+            checker.return_type = Type::from(&token_to_return_type(&s.return_type));
+            checker.statements(&s.body.statements);
+        }
+        checker.diagnostics
+    }
+}
@@ -1,17 +1,100 @@
+use std::collections::VecDeque;
 use std::io::BufRead;
 
 /// Context of the file parsing process
 pub struct FileContext {
     /// Whether current line started as a multiline comment
     in_comment: bool,
+    /// Whether comments are kept as trivia
+    keep_trivia: bool,
+    /// Comments found so far when they are kept
+    trivia: Vec<Trivia>,
+    /// Block comment that has not ended yet
+    block_comment: Option<Trivia>,
 }
 
 impl FileContext {
     pub fn new() -> FileContext {
-        FileContext { in_comment: false }
+        FileContext {
+            in_comment: false,
+            keep_trivia: false,
+            trivia: Vec::new(),
+            block_comment: None,
+        }
+    }
+
+    /// Create a context that keeps the comments as trivia instead of discarding them
+    pub fn with_trivia() -> FileContext {
+        FileContext {
+            keep_trivia: true,
+            ..FileContext::new()
+        }
+    }
+
+    /// Take the comments found so far
+    pub fn take_trivia(&mut self) -> Vec<Trivia> {
+        std::mem::take(&mut self.trivia)
+    }
+
+    /// Record the character of a block comment.
+    /// `was_in_comment` and `in_comment` are the comment state before and after the character
+    fn record_block_comment(
+        &mut self,
+        was_in_comment: bool,
+        in_comment: bool,
+        c: char,
+        span: Span,
+    ) {
+        match (was_in_comment, in_comment) {
+            (false, true) => {
+                // The comment started at the previous '/'
+                self.block_comment = Some(Trivia {
+                    kind: TriviaKind::BlockComment,
+                    text: String::from("/*"),
+                    span: Span {
+                        column: span.column - 1,
+                        ..span
+                    },
+                    token_index: 0,
+                });
+            }
+            (true, true) => self.block_comment.as_mut().unwrap().text.push(c),
+            (true, false) => {
+                let mut comment = self.block_comment.take().unwrap();
+                comment.text.push(c);
+                if comment.text.starts_with("/**") && comment.text != "/**/" {
+                    comment.kind = TriviaKind::DocComment;
+                }
+                self.trivia.push(comment);
+            }
+            (false, false) => {}
+        }
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TriviaKind {
+    /// Comment from "//" to the end of the line
+    LineComment,
+    /// Comment between "/*" and "*/"
+    BlockComment,
+    /// Comment between "/**" and "*/"
+    DocComment,
+}
+
+/// Comment kept with the tokens
+#[derive(Debug, Clone)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    /// Text of the comment including the comment markers.
+    /// Lines of block comments are separated by "\n"
+    pub text: String,
+    /// Position of the first character of the comment
+    pub span: Span,
+    /// Index of the token the comment comes before (the number of tokens for comments after the last token)
+    pub token_index: usize,
+}
+
 pub const NEW_LINE: &str = "\r\n";
 pub const INDENT_STR: &'static str = "  ";
 #[derive(thiserror::Error, Debug)]
@@ -20,6 +103,21 @@ pub enum SerializeError {
     UnexpectedState(String),
 }
 
+/// Largest value of an integer constant
+pub const MAX_INTEGER: u16 = 32767;
+
+#[derive(thiserror::Error, Debug)]
+pub enum TokenizeError {
+    #[error("{}:{} Unterminated string constant", .span.line, .span.column)]
+    UnterminatedString { span: Span },
+    #[error("{}:{} Invalid character: {character}", .span.line, .span.column)]
+    InvalidCharacter { character: char, span: Span },
+    #[error("{}:{} Invalid integer constant: {text} (must be 0-{})", .span.line, .span.column, MAX_INTEGER)]
+    InvalidInteger { text: String, span: Span },
+    #[error("{line}: Failed to read line: {source}")]
+    Read { line: usize, source: std::io::Error },
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum KeywordType {
     Class,
@@ -45,21 +143,145 @@ pub enum KeywordType {
     This,
 }
 
-/// Generate token list from given file reader
-pub fn generate_token_list(file_reader: &mut std::io::BufReader<std::fs::File>) -> TokenList {
-    let mut tokens = TokenList { list: Vec::new() };
-    let mut context = FileContext::new();
-    for line in file_reader.lines() {
-        let line_text = line.unwrap();
-        let mut tk = parse_line(&mut context, &line_text);
-        tokens.list.append(&mut tk);
+/// Position of a token in the source file
+#[derive(Debug, Copy, Clone, Default, PartialEq, PartialOrd)]
+pub struct Span {
+    /// Line number starting from 1
+    pub line: usize,
+    /// Column of the first character starting from 1
+    pub column: usize,
+}
+
+/// Reads tokens from the reader on demand, one line at a time
+pub struct Tokenizer<R: BufRead> {
+    lines: std::io::Lines<R>,
+    /// Number of the last line read
+    line_number: usize,
+    context: FileContext,
+    /// Tokens and errors of the last line that are not returned yet
+    pending: VecDeque<Result<Token, TokenizeError>>,
+    /// True after a read error since the rest of the input cannot be read
+    failed: bool,
+}
+
+impl<R: BufRead> Tokenizer<R> {
+    pub fn new(reader: R) -> Tokenizer<R> {
+        Tokenizer {
+            lines: reader.lines(),
+            line_number: 0,
+            context: FileContext::new(),
+            pending: VecDeque::new(),
+            failed: false,
+        }
+    }
+
+    /// Create a tokenizer that keeps the comments as trivia (see take_trivia)
+    pub fn with_trivia(reader: R) -> Tokenizer<R> {
+        Tokenizer {
+            context: FileContext::with_trivia(),
+            ..Tokenizer::new(reader)
+        }
+    }
+
+    /// Take the comments of the lines read so far.
+    /// token_index of the comments is not set since the tokenizer does not keep the tokens
+    pub fn take_trivia(&mut self) -> Vec<Trivia> {
+        self.context.take_trivia()
+    }
+}
+
+impl<R: BufRead> Iterator for Tokenizer<R> {
+    type Item = Result<Token, TokenizeError>;
+
+    /// Returns the next token, or the errors of a line with errors instead of its tokens
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            if self.failed {
+                return None;
+            }
+            self.line_number += 1;
+            match self.lines.next()? {
+                Ok(line_text) => {
+                    match parse_line(&mut self.context, self.line_number, &line_text) {
+                        Ok(tokens) => self.pending.extend(tokens.into_iter().map(Ok)),
+                        Err(errors) => self.pending.extend(errors.into_iter().map(Err)),
+                    }
+                }
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(TokenizeError::Read {
+                        line: self.line_number,
+                        source: e,
+                    }));
+                }
+            }
+        }
+        self.pending.pop_front()
+    }
+}
+
+/// Tokenize all lines, and keep the comments if the context keeps them.
+/// Lines with errors are skipped so the errors of all lines are returned
+fn collect_tokens<L: AsRef<str>>(
+    mut context: FileContext,
+    lines: impl Iterator<Item = std::io::Result<L>>,
+) -> Result<TokenList, Vec<TokenizeError>> {
+    let mut tokens = TokenList {
+        list: Vec::new(),
+        trivia: Vec::new(),
+    };
+    let mut errors = Vec::new();
+    for (idx, line) in lines.enumerate() {
+        let line_text = match line {
+            Ok(l) => l,
+            Err(e) => {
+                errors.push(TokenizeError::Read {
+                    line: idx + 1,
+                    source: e,
+                });
+                break;
+            }
+        };
+        match parse_line(&mut context, idx + 1, line_text.as_ref()) {
+            Ok(mut tk) => tokens.list.append(&mut tk),
+            Err(mut e) => errors.append(&mut e),
+        }
     }
-    tokens
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    tokens.trivia = context.take_trivia();
+    for t in &mut tokens.trivia {
+        // attach the comment to the first token after it
+        t.token_index = tokens.list.partition_point(|token| token.span() < t.span);
+    }
+    Ok(tokens)
+}
+
+/// Generate token list from given reader (ex: a BufReader of a file).
+/// Lines with errors are skipped so the errors of all lines are returned
+pub fn generate_token_list<R: BufRead>(reader: &mut R) -> Result<TokenList, Vec<TokenizeError>> {
+    collect_tokens(FileContext::new(), reader.lines())
+}
+
+/// Generate token list from given reader, keeping the comments as trivia of the list
+pub fn generate_token_list_with_trivia<R: BufRead>(
+    reader: &mut R,
+) -> Result<TokenList, Vec<TokenizeError>> {
+    collect_tokens(FileContext::with_trivia(), reader.lines())
+}
+
+/// Generate token list from jack source in memory.
+/// Lines are tokenized in place without copying them
+pub fn tokenize_str(source: &str) -> Result<TokenList, Vec<TokenizeError>> {
+    collect_tokens(FileContext::new(), source.lines().map(Ok))
 }
 
 #[derive(Debug)]
 pub struct TokenList {
     pub list: Vec<Token>,
+    /// Comments in order of the source when they are kept (see generate_token_list_with_trivia)
+    pub trivia: Vec<Trivia>,
 }
 
 impl TokenList {
@@ -76,6 +298,43 @@ impl TokenList {
         output.push_str(&end_tag);
         Ok(output)
     }
+
+    /// Serialize the tokens to a JSON array with the kind, text, and span of each token
+    pub fn to_json(&self) -> String {
+        let tokens: Vec<String> = self
+            .list
+            .iter()
+            .map(|t| {
+                let span = t.span();
+                format!(
+                    "  {{ \"kind\": {}, \"text\": {}, \"span\": {{ \"line\": {}, \"column\": {} }} }}",
+                    json_string(t.kind()),
+                    json_string(&t.string()),
+                    span.line,
+                    span.column
+                )
+            })
+            .collect();
+        if tokens.is_empty() {
+            return String::from("[]\n");
+        }
+        format!("[\n{}\n]\n", tokens.join(",\n"))
+    }
+}
+
+/// Quote a string for JSON
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
 }
 
 #[derive(Debug, Clone)]
@@ -132,11 +391,35 @@ impl Token {
             _ => None,
         }
     }
+
+    /// Get the kind of the token, named like the tags of the XML
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Token::Keyword(_) => "keyword",
+            Token::Symbol(_) => "symbol",
+            Token::Identifier(_) => "identifier",
+            Token::IntegerConstant(_) => "integerConstant",
+            Token::StringConstant(_) => "stringConstant",
+        }
+    }
+
+    /// Get the position of the token in the source file
+    pub fn span(&self) -> Span {
+        match self {
+            Token::Keyword(k) => k.span,
+            Token::Symbol(s) => s.span,
+            Token::Identifier(i) => i.span,
+            Token::IntegerConstant(ic) => ic.span,
+            Token::StringConstant(sc) => sc.span,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Keyword {
-    pub value: String,
+    /// One of KEYWORD_LIST
+    pub value: &'static str,
+    pub span: Span,
 }
 
 pub const STATIC: &str = "static";
@@ -146,9 +429,9 @@ pub const CLASS: &str = "class";
 pub const METHOD: &str = "method";
 const FIELD: &str = "field";
 pub const VOID: &str = "void";
-const INT: &str = "int";
-const CHAR: &str = "char";
-const BOOL: &str = "boolean";
+pub const INT: &str = "int";
+pub const CHAR: &str = "char";
+pub const BOOL: &str = "boolean";
 const VAR: &str = "var";
 const LET: &str = "let";
 const IF: &str = "if";
@@ -164,12 +447,13 @@ pub const NULL: &str = "null";
 impl Keyword {
     pub fn new() -> Keyword {
         Keyword {
-            value: String::new(),
+            value: "",
+            span: Span::default(),
         }
     }
 
     pub fn keyword(&self) -> KeywordType {
-        match self.value.as_str() {
+        match self.value {
             CLASS => KeywordType::Class,
             CONSTRUCTOR => KeywordType::Constructor,
             FUNCTION => KeywordType::Function,
@@ -216,12 +500,14 @@ impl Keyword {
 #[derive(Debug, Clone)]
 pub struct Symbol {
     pub value: char,
+    pub span: Span,
 }
 
 impl Symbol {
     pub fn new() -> Symbol {
         Symbol {
             value: '\0', // Init with a null character
+            span: Span::default(),
         }
     }
 }
@@ -259,12 +545,14 @@ impl Symbol {
 #[derive(Debug, Clone)]
 pub struct Identifier {
     pub value: String,
+    pub span: Span,
 }
 
 impl Identifier {
     pub fn new() -> Identifier {
         Identifier {
             value: String::new(),
+            span: Span::default(),
         }
     }
 }
@@ -290,6 +578,7 @@ impl Identifier {
 #[derive(Debug, Clone)]
 pub struct IntegerConstant {
     pub value: u16,
+    pub span: Span,
 }
 
 impl IntegerConstant {
@@ -313,6 +602,7 @@ impl IntegerConstant {
 #[derive(Debug, Clone)]
 pub struct StringConstant {
     pub value: String,
+    pub span: Span,
 }
 
 impl StringConstant {
@@ -350,8 +640,13 @@ struct LineContext {
     comment: CommentState,
     /// True if current char is inside a string constant
     in_string: bool,
-    /// List of chars that are not yet finished as a token
-    char_stash: Vec<char>,
+    /// Byte offset in the line where the token that is not yet finished starts.
+    /// For string constants this is the char after the opening quote
+    stash_start: Option<usize>,
+    /// Position of the first char of the unfinished token, or the opening quote of a string constant
+    stash_span: Span,
+    /// Position of the last '/' if it may start a comment
+    pending_slash: Option<Span>,
 }
 
 const SYMBOL_LIST: [char; 19] = [
@@ -444,33 +739,63 @@ fn update_comment_state(state: &mut CommentState, c: char) -> LineParseResult {
     LineParseResult::Continue
 }
 
-/// Create token by analyzing the content
-fn extract_token(stash: &Vec<char>) -> Result<Token, &str> {
-    let len = stash.len();
-    if len == 0 {
-        return Err("Empty stash given");
-    }
-    let word: String = stash.iter().cloned().collect();
-
-    if len == 1 && SYMBOL_LIST.contains(&stash[0]) {
+/// Create token by analyzing a non-empty word of the line
+fn extract_token(word: &str, span: Span) -> Result<Token, TokenizeError> {
+    let first = word.chars().next().unwrap();
+    if word.len() == 1 && SYMBOL_LIST.contains(&first) {
         // Got a symbol
-        Ok(Token::Symbol(Symbol { value: stash[0] }))
-    } else if stash[0].is_ascii_digit() {
+        Ok(Token::Symbol(Symbol { value: first, span }))
+    } else if first.is_ascii_digit() {
         // If the first symbol is an integer it is an integer const
-        Ok(Token::IntegerConstant(IntegerConstant {
-            value: str::parse::<u16>(&word.as_str()).unwrap(),
-        }))
-    } else if KEYWORD_LIST.contains(&word.as_str()) {
+        match str::parse::<u16>(word) {
+            Ok(value) if value <= MAX_INTEGER => {
+                Ok(Token::IntegerConstant(IntegerConstant { value, span }))
+            }
+            _ => Err(TokenizeError::InvalidInteger {
+                text: word.to_string(),
+                span,
+            }),
+        }
+    } else if let Some(keyword) = KEYWORD_LIST.iter().find(|k| **k == word) {
         // If the word matches keyword list we return keyword
-        Ok(Token::Keyword(Keyword { value: word }))
+        Ok(Token::Keyword(Keyword {
+            value: keyword,
+            span,
+        }))
     } else {
         // all other cases are identifiers
-        Ok(Token::Identifier(Identifier { value: word }))
+        Ok(Token::Identifier(Identifier {
+            value: word.to_string(),
+            span,
+        }))
+    }
+}
+
+/// Push the unfinished token that ends at byte offset `end` of the line if there is one
+fn flush_stash(
+    ctx: &mut LineContext,
+    line: &str,
+    end: usize,
+    tokens: &mut Vec<Token>,
+    errors: &mut Vec<TokenizeError>,
+) {
+    if let Some(start) = ctx.stash_start.take() {
+        match extract_token(&line[start..end], ctx.stash_span) {
+            Ok(t) => tokens.push(t),
+            Err(e) => errors.push(e),
+        }
     }
 }
 
-pub fn parse_line(context: &mut FileContext, line: &str) -> Vec<Token> {
+/// Parse a line to tokens. `line_number` starts from 1 and is used for the span of the tokens.
+/// Returns all errors in the line if there are any
+pub fn parse_line(
+    context: &mut FileContext,
+    line_number: usize,
+    line: &str,
+) -> Result<Vec<Token>, Vec<TokenizeError>> {
     let mut token_list = Vec::new();
+    let mut errors = Vec::new();
     let mut ctx = LineContext {
         comment: CommentState {
             in_region: context.in_comment,
@@ -479,77 +804,137 @@ pub fn parse_line(context: &mut FileContext, line: &str) -> Vec<Token> {
             next_maybe_region_end: false,
         },
         in_string: false,
-        char_stash: Vec::new(),
+        stash_start: None,
+        stash_span: Span::default(),
+        pending_slash: None,
     };
-    // iterate over all character
-    for c in line.chars() {
-        // println!("{}", c);
+    // iterate over all character.
+    // Tokens are sliced from the line so chars are not copied until a token is finished
+    let mut column = 0;
+    for (idx, c) in line.char_indices() {
+        column += 1;
+        let span = Span {
+            line: line_number,
+            column,
+        };
         if ctx.in_string {
-            // We are currently in a string so we stash all chars unless we get the end quote
+            // We are currently in a string so we go on unless we get the end quote
             if c == '"' {
                 // We are now at end of string
-                // Get all stashed characters and push to token list
-                let str = ctx.char_stash.iter().collect();
-                token_list.push(Token::StringConstant(StringConstant { value: str }));
-                ctx.char_stash.clear();
+                let start = ctx.stash_start.take().unwrap();
+                token_list.push(Token::StringConstant(StringConstant {
+                    value: line[start..idx].to_string(),
+                    span: ctx.stash_span,
+                }));
                 ctx.in_string = false;
-            } else {
-                ctx.char_stash.push(c);
             }
         } else {
             // not in string
+            let was_in_comment = ctx.comment.in_region;
             let ret = update_comment_state(&mut ctx.comment, c);
             match ret {
                 LineParseResult::LineComment => {
                     // We encountered a line comment symbol so we break here and go to next line.
-                    // left over token should be the previous '/' symbol so we just drop it and go on
+                    // The previous '/' is part of the comment so we just drop it and go on
+                    ctx.pending_slash = None;
+                    if context.keep_trivia {
+                        // The comment started at the previous '/'
+                        context.trivia.push(Trivia {
+                            kind: TriviaKind::LineComment,
+                            text: line[idx - 1..].to_string(),
+                            span: Span {
+                                column: column - 1,
+                                ..span
+                            },
+                            token_index: 0,
+                        });
+                    }
                     break;
                 }
                 LineParseResult::Continue => {
                     // We just continue
                 }
             }
+            if context.keep_trivia {
+                context.record_block_comment(was_in_comment, ctx.comment.in_region, c, span);
+            }
             if ctx.comment.in_region {
                 // We are in region comment so we go to next char
-                // If we have any previous char it should be a '/' symbol so we drop it
-                ctx.char_stash.clear();
+                // The previous '/' is part of the comment so we drop it
+                ctx.pending_slash = None;
                 continue;
             }
+            if was_in_comment {
+                // This is the '/' that ended the region comment
+                continue;
+            }
+            if let Some(slash_span) = ctx.pending_slash.take() {
+                // The previous '/' did not start a comment so it is a symbol
+                token_list.push(Token::Symbol(Symbol {
+                    value: '/',
+                    span: slash_span,
+                }));
+            }
             if c.is_whitespace() {
                 // look at stash and if we have anything push it as token
-                if !ctx.char_stash.is_empty() {
-                    token_list.push(extract_token(&ctx.char_stash).unwrap());
-                    ctx.char_stash.clear();
-                }
+                flush_stash(&mut ctx, line, idx, &mut token_list, &mut errors);
             } else if c == '"' {
                 // We are at start of string
+                flush_stash(&mut ctx, line, idx, &mut token_list, &mut errors);
                 ctx.in_string = true;
+                ctx.stash_start = Some(idx + 1);
+                ctx.stash_span = span;
             } else if SYMBOL_LIST.contains(&c) {
                 // Got a symbol
                 match c {
                     '/' => {
                         // May be a div symbol or comment symbol.
-                        // We stash the character and go next
-                        ctx.char_stash.push(c);
-                        continue;
+                        // We push the token before it and decide on the next char
+                        flush_stash(&mut ctx, line, idx, &mut token_list, &mut errors);
+                        ctx.pending_slash = Some(span);
                     }
                     _ => {
                         // All other symbols can be simply added as token
                         // If we already have anything in the stash we push it as a token first
-                        if !ctx.char_stash.is_empty() {
-                            token_list.push(extract_token(&ctx.char_stash).unwrap());
-                            ctx.char_stash.clear();
-                        }
-                        token_list.push(Token::Symbol(Symbol { value: c }));
+                        flush_stash(&mut ctx, line, idx, &mut token_list, &mut errors);
+                        token_list.push(Token::Symbol(Symbol { value: c, span }));
                     }
                 }
-            } else {
-                // Push all other char to stash
-                ctx.char_stash.push(c);
+            } else if !(c.is_ascii_alphanumeric() || c == '_') {
+                // Not part of any token so we report it and handle it like whitespace
+                flush_stash(&mut ctx, line, idx, &mut token_list, &mut errors);
+                errors.push(TokenizeError::InvalidCharacter { character: c, span });
+            } else if ctx.stash_start.is_none() {
+                // Start a new token. All other chars until the end of the token are in the stash
+                ctx.stash_start = Some(idx);
+                ctx.stash_span = span;
             }
         }
     }
+    if ctx.in_string {
+        // String constants cannot continue to the next line
+        errors.push(TokenizeError::UnterminatedString {
+            span: ctx.stash_span,
+        });
+    } else if !ctx.comment.in_region {
+        // Tokens end at the end of the line
+        if let Some(slash_span) = ctx.pending_slash {
+            token_list.push(Token::Symbol(Symbol {
+                value: '/',
+                span: slash_span,
+            }));
+        }
+        flush_stash(&mut ctx, line, line.len(), &mut token_list, &mut errors);
+    }
+    if context.keep_trivia && ctx.comment.in_region {
+        // The block comment continues to the next line
+        context.block_comment.as_mut().unwrap().text.push('\n');
+    }
     // update context for the next line
     context.in_comment = ctx.comment.in_region;
-    token_list
+    if errors.is_empty() {
+        Ok(token_list)
+    } else {
+        Err(errors)
+    }
 }
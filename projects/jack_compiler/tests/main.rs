@@ -1,14 +1,21 @@
 use jack_compiler::{
-    generate_ioset, get_origin_name,
+    diagram, generate_ioset, get_origin_name,
     parser::{self},
     tokenizer,
 };
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 const TEST_DIR: &'static str = "tests";
 const DATA_DIR: &'static str = "data";
 const GOLD_VM_DIR: &'static str = "Gold";
 
+/// Read a golden file for comparison with generated text.
+/// Carriage returns are dropped from both so the comparison doesn't depend on NEW_LINE or the line endings of the checkout
+fn assert_same_as_golden(golden_file_path: &Path, text: &str) {
+    let golden = std::fs::read_to_string(golden_file_path).unwrap();
+    assert_eq!(golden.replace('\r', ""), text.replace('\r', ""));
+}
+
 fn test_tokenizer(root: &PathBuf, dir: &str) -> Result<(), std::io::Error> {
     let target = root.join(TEST_DIR).join(DATA_DIR).join(dir);
     // println!("{:?}", target);
@@ -19,13 +26,12 @@ fn test_tokenizer(root: &PathBuf, dir: &str) -> Result<(), std::io::Error> {
         let mut golden_file_path = io.input_file.clone();
         let golden_name = format!("{}T.xml", origin);
         golden_file_path.set_file_name(&golden_name);
-        let tokens = tokenizer::generate_token_list(&mut io.input);
+        let tokens = tokenizer::generate_token_list(&mut io.input).unwrap();
 
         // Read Golden XML results and compare with results
-        let golden_xml = std::fs::read_to_string(golden_file_path).unwrap();
         let xml = tokens.serialize().unwrap();
         println!("{} vs {}", &golden_name, io.input_file.display());
-        assert_eq!(golden_xml, xml);
+        assert_same_as_golden(&golden_file_path, &xml);
     }
     Ok(())
 }
@@ -45,12 +51,10 @@ fn test_parser(root: &PathBuf, dir: &str) {
             .expect(format!("Parse failed at {}", io.input_file.display()).as_str());
 
         // Read Golden XML results and compare with results
-        let golden_xml = std::fs::read_to_string(golden_file_path).unwrap();
         let mut xml = String::from("");
         class.serialize(&mut xml, 0).unwrap();
-        // println!("{}", golden_xml);
         // println!("{}", xml);
-        assert_eq!(golden_xml, xml);
+        assert_same_as_golden(&golden_file_path, &xml);
         println!("OK: {} vs {}", &golden_name, io.input_file.display());
     }
 }
@@ -98,8 +102,7 @@ fn test_compiler(root: &PathBuf, dir: &str, print_xml: bool, print_vm: bool, com
                 .join(dir)
                 .join(name);
             println!("{}", &gold_path.display());
-            let golden_vm = std::fs::read_to_string(&gold_path).unwrap();
-            assert_eq!(golden_vm, vm);
+            assert_same_as_golden(&gold_path, &vm);
             println!("OK: {} vs {}", &gold_path.display(), input_file.display());
         }
     }
@@ -176,3 +179,376 @@ fn test_compiler_complex_arrays() {
     let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     test_compiler(&root, "ComplexArrays", false, false, true);
 }
+
+#[test]
+fn test_diagram_pong() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let target = root.join(TEST_DIR).join(DATA_DIR).join("Pong");
+    let io_list = generate_ioset(&target).unwrap();
+    let mut class_list = Vec::new();
+    for mut io in io_list {
+        let mut ctx = parser::ClassParseInfo::new();
+        let class = parser::parse_file(&mut ctx, &mut io.input)
+            .expect(format!("Parse failed at {}", io.input_file.display()).as_str());
+        class_list.push(class);
+    }
+    let classes: Vec<&parser::Class> = class_list.iter().collect();
+    let dot = diagram::generate_dot(&classes);
+    // class variables are solid edges and other usage is dashed
+    assert!(dot.contains("\"PongGame\" -> \"Bat\";"));
+    assert!(dot.contains("\"PongGame\" -> \"Ball\";"));
+    assert!(dot.contains("\"Main\" -> \"PongGame\" [style=dashed];"));
+    assert!(dot.contains("method void setDirection(int Adirection)\\l"));
+    // OS classes are not part of the diagram
+    assert!(!dot.contains("-> \"Screen\""));
+}
+
+#[test]
+fn test_token_span() {
+    let mut ctx = tokenizer::FileContext::new();
+    let tokens = tokenizer::parse_line(&mut ctx, 3, "  let s = \"a b\"; // x").unwrap();
+    let spans: Vec<(String, usize, usize)> = tokens
+        .iter()
+        .map(|t| (t.string(), t.span().line, t.span().column))
+        .collect();
+    assert_eq!(
+        spans,
+        [
+            (String::from("let"), 3, 3),
+            (String::from("s"), 3, 7),
+            (String::from("="), 3, 9),
+            (String::from("a b"), 3, 11),
+            (String::from(";"), 3, 16),
+        ]
+    );
+}
+
+#[test]
+fn test_tokenize_str() {
+    let tokens =
+        tokenizer::tokenize_str("class Main {\n  // comment\n  field int x;\n}\n").unwrap();
+    let words: Vec<String> = tokens.list.iter().map(|t| t.string()).collect();
+    assert_eq!(words, ["class", "Main", "{", "field", "int", "x", ";", "}"]);
+    assert_eq!(tokens.list[3].span().line, 3);
+    // a word right before a string constant is a token of its own
+    let tokens = tokenizer::tokenize_str("x\"a b\"").unwrap();
+    let words: Vec<String> = tokens.list.iter().map(|t| t.string()).collect();
+    assert_eq!(words, ["x", "a b"]);
+}
+
+#[test]
+fn test_tokenize_block_comment_in_line() {
+    let words = |source: &str| -> Vec<String> {
+        let tokens = tokenizer::tokenize_str(source).unwrap();
+        tokens.list.iter().map(|t| t.string()).collect()
+    };
+    // tokens before and after a block comment in the same line are kept
+    assert_eq!(words("let x/* c */= 1;"), ["let", "x", "=", "1", ";"]);
+    assert_eq!(words("let x /* c */ = 1;"), ["let", "x", "=", "1", ";"]);
+    assert_eq!(words("/* c */let x = 1;"), ["let", "x", "=", "1", ";"]);
+    assert_eq!(
+        words("let x = 1; /* c\nstill c */ let y = 2;"),
+        ["let", "x", "=", "1", ";", "let", "y", "=", "2", ";"]
+    );
+    // the division symbol is not part of a comment
+    assert_eq!(words("x/y /* c */ / z"), ["x", "/", "y", "/", "z"]);
+    // a token at the end of a line is kept
+    assert_eq!(words("} else\n{"), ["}", "else", "{"]);
+    assert_eq!(
+        words("let x = y /\nz;"),
+        ["let", "x", "=", "y", "/", "z", ";"]
+    );
+    let tokens = tokenizer::tokenize_str("let x /* c */ = 1;").unwrap();
+    assert_eq!(tokens.list[2].span().column, 15);
+}
+
+/// Reader of an endless jack source
+struct EndlessSource;
+
+impl std::io::Read for EndlessSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let line = b"let x = x + 1;\n";
+        let len = std::cmp::min(buf.len(), line.len());
+        buf[..len].copy_from_slice(&line[..len]);
+        Ok(len)
+    }
+}
+
+#[test]
+fn test_tokenizer_iterator() {
+    // tokens are read on demand so an endless source can be tokenized
+    let reader = std::io::BufReader::new(EndlessSource);
+    let tokens: Vec<tokenizer::Token> = tokenizer::Tokenizer::new(reader)
+        .take(8)
+        .map(|t| t.unwrap())
+        .collect();
+    let words: Vec<String> = tokens.iter().map(|t| t.string()).collect();
+    assert_eq!(words, ["let", "x", "=", "x", "+", "1", ";", "let"]);
+    assert_eq!(tokens[7].span().line, 2);
+    // errors are returned in place of the tokens of the line
+    let items: Vec<_> =
+        tokenizer::Tokenizer::new("let a;\nlet # b;\nlet c;\n".as_bytes()).collect();
+    let errors: Vec<usize> = items
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| t.is_err())
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!((items.len(), errors), (7, vec![3]));
+}
+
+#[test]
+fn test_trivia() {
+    let source = "/** Doc */\nclass Main { // hello\n  /* a\n  b */\n  field int x;\n}\n// end\n";
+    let tokens = tokenizer::generate_token_list_with_trivia(&mut source.as_bytes()).unwrap();
+    let trivia: Vec<(tokenizer::TriviaKind, &str, usize, usize, usize)> = tokens
+        .trivia
+        .iter()
+        .map(|t| {
+            (
+                t.kind,
+                t.text.as_str(),
+                t.span.line,
+                t.span.column,
+                t.token_index,
+            )
+        })
+        .collect();
+    assert_eq!(
+        trivia,
+        [
+            (tokenizer::TriviaKind::DocComment, "/** Doc */", 1, 1, 0),
+            (tokenizer::TriviaKind::LineComment, "// hello", 2, 14, 3),
+            (tokenizer::TriviaKind::BlockComment, "/* a\n  b */", 3, 3, 3),
+            (tokenizer::TriviaKind::LineComment, "// end", 7, 1, 8),
+        ]
+    );
+    // the tokens are the same with and without trivia
+    let plain = tokenizer::tokenize_str(source).unwrap();
+    assert_eq!(plain.serialize().unwrap(), tokens.serialize().unwrap());
+    assert!(plain.trivia.is_empty());
+}
+
+#[test]
+fn test_tokens_json() {
+    let tokens = tokenizer::tokenize_str("do Output.printString(\"a\\b\");\n").unwrap();
+    let json = tokens.to_json();
+    assert!(json.starts_with(
+        "[\n  { \"kind\": \"keyword\", \"text\": \"do\", \"span\": { \"line\": 1, \"column\": 1 } },\n"
+    ));
+    assert!(json.contains(
+        "{ \"kind\": \"stringConstant\", \"text\": \"a\\\\b\", \"span\": { \"line\": 1, \"column\": 23 } }"
+    ));
+    assert!(json.ends_with("} }\n]\n"));
+    assert_eq!(tokenizer::tokenize_str("").unwrap().to_json(), "[]\n");
+}
+
+#[test]
+fn test_tokenize_errors() {
+    let source = "let s = \"abc;\nlet x = 32768 + 1x;\nlet y = #;\nlet z = 32767;\n";
+    let errors = tokenizer::tokenize_str(source).unwrap_err();
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    // errors of all lines are reported
+    assert_eq!(
+        messages,
+        [
+            "1:9 Unterminated string constant",
+            "2:9 Invalid integer constant: 32768 (must be 0-32767)",
+            "2:17 Invalid integer constant: 1x (must be 0-32767)",
+            "3:9 Invalid character: #",
+        ]
+    );
+}
+
+#[test]
+fn test_parser_error_position() {
+    let source =
+        "class Main {\n  function void main() {\n    let x = 1;\n    return;\n  }\n  ]\n}\n";
+    let mut ctx = parser::ClassParseInfo::new();
+    let error = match parser::parse_file(&mut ctx, &mut source.as_bytes()) {
+        Ok(_) => panic!("Parse should fail"),
+        Err(e) => e,
+    };
+    // errors point at the token in the jack source
+    assert_eq!(error.to_string(), "6:3 Got unexpected symbol: ]");
+}
+
+/// Compile a single class from source
+fn compile_source(source: &str) -> Result<String, parser::Error> {
+    let mut ctx = parser::ClassParseInfo::new();
+    let class = parser::parse_file(&mut ctx, &mut source.as_bytes())?;
+    let mut dir_info = parser::DirectoryParseInfo::new();
+    dir_info.info_per_class.insert(class.name().to_owned(), ctx);
+    class.compile(&dir_info)
+}
+
+#[test]
+fn test_compile_field_access() {
+    let class = |body: &str| {
+        format!(
+            "class Main {{\n  field int x;\n  static int s;\n{}\n}}\n",
+            body
+        )
+    };
+    // fields are in the this segment and statics in the static segment
+    let vm = compile_source(&class(
+        "  method int get() {\n    let x = s;\n    return x;\n  }",
+    ))
+    .unwrap();
+    assert!(vm.contains("push static 0"));
+    assert!(vm.contains("pop this 0"));
+    assert!(vm.contains("push this 0"));
+    // functions have no THIS so fields cannot be used
+    let error = compile_source(&class("  function int get() {\n    return x;\n  }")).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "5:12 Field x cannot be used in function Main.get"
+    );
+    let error = compile_source(&class("  function int get() {\n    return y;\n  }")).unwrap_err();
+    assert_eq!(error.to_string(), "5:12 Variable y is not defined");
+}
+
+#[test]
+fn test_compile_call_in_same_class() {
+    let source = "class Main {
+  function int twice(int x) {
+    return x + x;
+  }
+  method int size() {
+    return 2;
+  }
+  method int area() {
+    return twice(size()) * 3;
+  }
+  function int main() {
+    return twice(1);
+  }
+}
+";
+    let vm = compile_source(source).unwrap();
+    // methods get THIS as the first argument
+    let lines: Vec<&str> = vm.lines().map(str::trim_end).collect();
+    assert!(lines
+        .windows(2)
+        .any(|w| w == ["push pointer 0", "call Main.size 1"]));
+    // functions are called with their arguments only
+    assert!(vm.contains("call Main.twice 1"));
+    assert!(!vm.contains("call Main.twice 2"));
+    // functions have no THIS to call methods with
+    let error =
+        compile_source("class Main {\n  method void m() {\n    return;\n  }\n  function void f() {\n    do m();\n    return;\n  }\n}\n")
+            .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "6:8 Method Main.m cannot be called from function Main.f"
+    );
+}
+
+#[test]
+fn test_compile_return_value() {
+    let vm = compile_source("class Main {\n  function int one() {\n    return 1 + 2;\n  }\n}\n")
+        .unwrap();
+    let lines: Vec<&str> = vm.lines().map(str::trim_end).collect();
+    assert_eq!(
+        lines,
+        [
+            "function Main.one 0",
+            "push constant 1",
+            "push constant 2",
+            "add",
+            "return"
+        ]
+    );
+    let error =
+        compile_source("class Main {\n  function void f() {\n    return 1;\n  }\n}\n").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "3:5 Void subroutine Main.f cannot return a value"
+    );
+    let error =
+        compile_source("class Main {\n  function int f() {\n    return;\n  }\n}\n").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "3:5 Subroutine Main.f must return a value"
+    );
+}
+
+/// Type check all classes in a test directory
+fn typecheck_dir(root: &PathBuf, dir: &str) -> Vec<parser::Diagnostic> {
+    let target = root.join(TEST_DIR).join(DATA_DIR).join(dir);
+    let mut dir_info = parser::DirectoryParseInfo::new();
+    let mut class_list = Vec::new();
+    for mut io in generate_ioset(&target).unwrap() {
+        let mut ctx = parser::ClassParseInfo::new();
+        let class = parser::parse_file(&mut ctx, &mut io.input).unwrap();
+        dir_info.info_per_class.insert(class.name().to_owned(), ctx);
+        class_list.push(class);
+    }
+    class_list
+        .iter()
+        .flat_map(|c| c.typecheck(&dir_info))
+        .collect()
+}
+
+#[test]
+fn test_typecheck_programs() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    for dir in [
+        "Seven",
+        "ConvertToBin",
+        "Square2",
+        "Average",
+        "Pong",
+        "ComplexArrays",
+    ] {
+        let diagnostics = typecheck_dir(&root, dir);
+        assert!(diagnostics.is_empty(), "{}: {:?}", dir, diagnostics);
+    }
+}
+
+#[test]
+fn test_typecheck() {
+    let source = "class Main {
+  field Point p;
+  function int add(int a, int b) {
+    return a + b;
+  }
+  method void run(boolean flag) {
+    var int x;
+    var char c;
+    let x = flag;
+    let c = 65;
+    let x = Main.add(1);
+    let x = Main.add(1, true);
+    let x = 1 + flag;
+    if (x) {
+      let p = null;
+    }
+    while (p) {
+      let p = Point.new();
+    }
+    return;
+  }
+}
+";
+    let mut ctx = parser::ClassParseInfo::new();
+    let class = parser::parse_file(&mut ctx, &mut source.as_bytes()).unwrap();
+    let mut dir_info = parser::DirectoryParseInfo::new();
+    dir_info.info_per_class.insert(class.name().to_owned(), ctx);
+    let messages: Vec<String> = class
+        .typecheck(&dir_info)
+        .iter()
+        .map(|d| d.to_string())
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "9:13 error: Cannot assign boolean to x of type int",
+            "11:18 error: Main.add expects 2 arguments but got 1",
+            "12:25 error: Argument 2 of Main.add expects int but got boolean",
+            "13:15 error: Operator + expects int but got boolean",
+            "14:9 warning: Condition is int instead of boolean",
+            "17:12 error: Condition is Point instead of boolean",
+        ]
+    );
+}